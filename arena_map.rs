@@ -0,0 +1,374 @@
+use core::cmp::{Eq, Ord};
+use red_black_tree::{RBColor, Red, Black};
+
+/**
+ * A persistent red-black map whose nodes live side by side in one vector
+ * instead of in separate managed boxes. Children are indices into that
+ * vector, and a version of the map is just the index of its root, so
+ * versions share nodes exactly as RBMap versions do.
+ *
+ * Updates copy the path they change onto the end of the arena and never
+ * touch a node already there, so old versions stay valid until the next
+ * `compact`. Compacting copies the nodes the given live versions can
+ * reach into a fresh arena in breadth-first order and drops the rest: the
+ * roots come first and each level follows the one above it, so the upper
+ * levels every lookup starts from share a few cache lines.
+ *
+ * Like RBMap, deleting a key leaves it behind with no value. Only RBMap
+ * has an arena form; tree::Tree is out of scope here.
+ */
+pub struct ArenaRBMap<K: Copy Eq Ord, V: Copy> {
+  mut nodes: ~[ArenaNode<K, V>],
+  // bumped by every compaction; versions from an earlier one are stale.
+  mut generation: uint
+}
+
+struct ArenaNode<K: Copy Eq Ord, V: Copy> {
+  color: RBColor,
+  left: u32,
+  key: K,
+  value: Option<V>,
+  right: u32
+}
+
+// a version of the map: where its root is, and how many live entries it has.
+pub struct ArenaVersion {
+  root: u32,
+  len: uint,
+  generation: uint
+}
+
+// the index standing for an empty subtree.
+const NIL : u32 = 0xffffffff;
+
+pure fn ArenaRBMap<K: Copy Eq Ord, V: Copy>() -> ArenaRBMap<K, V> {
+  ArenaRBMap { nodes: ~[], generation: 0 }
+}
+
+impl ArenaVersion {
+  pure fn len() -> uint {
+    self.len
+  }
+
+  pure fn is_empty() -> bool {
+    self.len == 0
+  }
+}
+
+impl<K: Copy Eq Ord, V: Copy> ArenaRBMap<K, V> {
+  pure fn empty() -> ArenaVersion {
+    ArenaVersion { root: NIL, len: 0, generation: self.generation }
+  }
+
+  // nodes in the arena, reachable or not.
+  pure fn num_nodes() -> uint {
+    self.nodes.len()
+  }
+
+  // false once a compaction has happened that `v` wasn't passed to.
+  pure fn is_live(v: ArenaVersion) -> bool {
+    v.generation == self.generation
+  }
+
+  pure fn get(v: ArenaVersion, k: K) -> Option<V> {
+    assert(self.is_live(v));
+    let mut i = v.root;
+    while i != NIL {
+      let n = &self.nodes[i as uint];
+      if k < n.key {
+        i = n.left;
+      } else if n.key < k {
+        i = n.right;
+      } else {
+        return copy n.value;
+      }
+    }
+    None
+  }
+
+  pure fn contains_key(v: ArenaVersion, k: K) -> bool {
+    self.get(v, k).is_some()
+  }
+
+  // calls `f` on the live entries of `v` in ascending key order until it returns false.
+  pure fn each(v: ArenaVersion, f: fn(&K, &V) -> bool) {
+    assert(self.is_live(v));
+    let mut stack = ~[];
+    let mut i = v.root;
+    loop {
+      while i != NIL {
+        stack.push(i);
+        i = self.nodes[i as uint].left;
+      }
+      if stack.is_empty() {
+        return;
+      }
+      let n = &self.nodes[stack.pop() as uint];
+      match n.value {
+        Some(ref value) => if !f(&n.key, value) { return; },
+        None => ()
+      }
+      i = n.right;
+    }
+  }
+
+  fn put(v: ArenaVersion, k: K, value: V) -> ArenaVersion {
+    let len = if self.contains_key(v, k) { v.len } else { v.len + 1 };
+    self.modify(v, k, Some(value), len)
+  }
+
+  // deleting a key that isn't there returns `v` itself.
+  fn delete(v: ArenaVersion, k: K) -> ArenaVersion {
+    if !self.contains_key(v, k) {
+      return v;
+    }
+    self.modify(v, k, None, v.len - 1)
+  }
+
+  /**
+   * Rebuilds the arena with only the nodes reachable from `live`, and
+   * returns the same versions pointing into the new one, in order. Any
+   * version not passed in is stale afterwards.
+   */
+  fn compact(live: &[ArenaVersion]) -> ~[ArenaVersion] {
+    // where each reachable node goes, handed out breadth first from the roots.
+    let mut moved_to = vec::from_elem(self.nodes.len(), NIL);
+    let mut order = ~[];
+    for vec::each(live) |v| {
+      assert(self.is_live(*v));
+      if v.root != NIL && moved_to[v.root as uint] == NIL {
+        moved_to[v.root as uint] = order.len() as u32;
+        order.push(v.root);
+      }
+    }
+    let mut next = 0;
+    while next < order.len() {
+      let n = &self.nodes[order[next] as uint];
+      for vec::each(~[n.left, n.right]) |child| {
+        if *child != NIL && moved_to[*child as uint] == NIL {
+          moved_to[*child as uint] = order.len() as u32;
+          order.push(*child);
+        }
+      }
+      next += 1;
+    }
+
+    let relink = |i: u32| if i == NIL { NIL } else { moved_to[i as uint] };
+    let mut fresh = ~[];
+    for vec::each(order) |i| {
+      let n = &self.nodes[*i as uint];
+      fresh.push(ArenaNode {
+        color: n.color,
+        left: relink(n.left),
+        key: copy n.key,
+        value: copy n.value,
+        right: relink(n.right)
+      });
+    }
+
+    self.nodes <-> fresh;
+    self.generation += 1;
+    vec::map(live, |v| {
+      ArenaVersion { root: relink(v.root), len: v.len, generation: self.generation }
+    })
+  }
+
+  priv fn modify(v: ArenaVersion, k: K, value: Option<V>, len: uint) -> ArenaVersion {
+    assert(self.is_live(v));
+    let root = self.insert(v.root, k, value);
+    // the root was copied by this update, so nothing else can see it.
+    self.nodes[root as uint].color = Black;
+    ArenaVersion { root: root, len: len, generation: self.generation }
+  }
+
+  priv fn alloc(color: RBColor, left: u32, k: K, value: Option<V>, right: u32) -> u32 {
+    // the new node's index has to fit in a u32 without becoming NIL.
+    assert(self.nodes.len() < NIL as uint);
+    self.nodes.push(ArenaNode { color: color, left: left, key: k, value: value, right: right });
+    (self.nodes.len() - 1) as u32
+  }
+
+  priv pure fn is_red(i: u32) -> bool {
+    i != NIL && match self.nodes[i as uint].color { Red => true, Black => false }
+  }
+
+  priv fn insert(i: u32, k: K, value: Option<V>) -> u32 {
+    if i == NIL {
+      return self.alloc(Red, NIL, k, value, NIL);
+    }
+    let (color, left, right, below, above) = {
+      let n = &self.nodes[i as uint];
+      (n.color, n.left, n.right, k < n.key, n.key < k)
+    };
+    // only a node that gets rebuilt around a new child needs its entry copied.
+    if below {
+      let left = self.insert(left, k, value);
+      let (key, old) = self.entry(i);
+      self.balance(color, left, key, old, right)
+    } else if above {
+      let right = self.insert(right, k, value);
+      let (key, old) = self.entry(i);
+      self.balance(color, left, key, old, right)
+    } else {
+      self.alloc(color, left, k, value, right)
+    }
+  }
+
+  priv pure fn entry(i: u32) -> (K, Option<V>) {
+    let n = &self.nodes[i as uint];
+    (copy n.key, copy n.value)
+  }
+
+  // Okasaki's balance: a black node over two reds in a row becomes a red
+  // node over two blacks.
+  priv fn balance(color: RBColor, left: u32, k: K, value: Option<V>, right: u32) -> u32 {
+    match color {
+      Red => return self.alloc(Red, left, k, value, right),
+      Black => ()
+    }
+
+    if self.is_red(left) {
+      let y = copy self.nodes[left as uint];
+      if self.is_red(y.left) {
+        let x = copy self.nodes[y.left as uint];
+        let l = self.alloc(Black, x.left, x.key, x.value, x.right);
+        let r = self.alloc(Black, y.right, k, value, right);
+        return self.alloc(Red, l, y.key, y.value, r);
+      }
+      if self.is_red(y.right) {
+        let x = copy self.nodes[y.right as uint];
+        let l = self.alloc(Black, y.left, y.key, y.value, x.left);
+        let r = self.alloc(Black, x.right, k, value, right);
+        return self.alloc(Red, l, x.key, x.value, r);
+      }
+    }
+
+    if self.is_red(right) {
+      let y = copy self.nodes[right as uint];
+      if self.is_red(y.left) {
+        let x = copy self.nodes[y.left as uint];
+        let l = self.alloc(Black, left, k, value, x.left);
+        let r = self.alloc(Black, x.right, y.key, y.value, y.right);
+        return self.alloc(Red, l, x.key, x.value, r);
+      }
+      if self.is_red(y.right) {
+        let x = copy self.nodes[y.right as uint];
+        let l = self.alloc(Black, left, k, value, y.left);
+        let r = self.alloc(Black, x.left, x.key, x.value, x.right);
+        return self.alloc(Red, l, y.key, y.value, r);
+      }
+    }
+
+    self.alloc(Black, left, k, value, right)
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use red_black_tree::{RBMap, RBMap_empty};
+  use persistent_map::*;
+
+  fn entries(arena: &ArenaRBMap<uint, uint>, v: ArenaVersion) -> ~[(uint, uint)] {
+    let mut out = ~[];
+    for arena.each(v) |k, val| { out.push((*k, *val)); }
+    out
+  }
+
+  fn rb_entries(m: @RBMap<uint, uint>) -> ~[(uint, uint)] {
+    let mut out = ~[];
+    for m.iter |k, val| { out.push((*k, *val)); }
+    out
+  }
+
+  #[test]
+  fn test_matches_rbmap() {
+    let arena = ArenaRBMap();
+    let mut v = arena.empty();
+    let mut model = RBMap_empty();
+    let mut seed = 3u;
+    for uint::range(0, 3000) |i| {
+      seed = (seed * 1103515245 + 12345) % 2147483648;
+      let k = seed % 1000;
+      if seed % 4 == 0 {
+        v = arena.delete(v, k);
+        model = model.delete(k);
+      } else {
+        v = arena.put(v, k, i);
+        model = model.put(k, i);
+      }
+      assert(v.len() == model.len());
+    }
+    assert(entries(&arena, v) == rb_entries(model));
+    assert(arena.get(v, 1001) == None);
+  }
+
+  #[test]
+  fn test_versions_persist() {
+    let arena = ArenaRBMap();
+    let v1 = arena.put(arena.put(arena.empty(), 1, 10), 2, 20);
+    let v2 = arena.put(v1, 1, 11);
+    let v3 = arena.delete(v2, 2);
+
+    assert(arena.get(v1, 1) == Some(10));
+    assert(arena.get(v2, 1) == Some(11));
+    assert(arena.get(v3, 2) == None);
+    assert(arena.get(v2, 2) == Some(20));
+    assert(v3.len() == 1);
+    assert(arena.delete(v3, 7).root == v3.root);
+  }
+
+  #[test]
+  fn test_compact_reclaims_dead_versions() {
+    let arena = ArenaRBMap();
+    let mut v = arena.empty();
+    for uint::range(0, 1000) |i| {
+      v = arena.put(v, i, i);
+    }
+    let older = v;
+    v = arena.put(v, 500, 0);
+    // every put copied a path, so the arena holds far more than 1000 nodes.
+    assert(arena.num_nodes() > 5000);
+
+    let kept = arena.compact(~[older, v]);
+    // two versions that differ on one path: 1000 nodes plus one copied path.
+    assert(arena.num_nodes() >= 1000 && arena.num_nodes() < 1050);
+    assert(!arena.is_live(v));
+    assert(arena.is_live(kept[0]));
+    assert(arena.get(kept[0], 500) == Some(500));
+    assert(arena.get(kept[1], 500) == Some(0));
+    assert(kept[1].len() == 1000);
+
+    // after dropping the old version, one more compaction leaves one tree.
+    let kept = arena.compact(~[kept[1]]);
+    assert(arena.num_nodes() == 1000);
+    assert(entries(&arena, kept[0]).len() == 1000);
+    assert(arena.compact(~[]).is_empty());
+    assert(arena.num_nodes() == 0);
+  }
+
+  #[test]
+  fn test_compact_is_breadth_first() {
+    let arena = ArenaRBMap();
+    let mut v = arena.empty();
+    for uint::range(0, 100) |i| {
+      v = arena.put(v, i, i);
+    }
+    let v = arena.compact(~[v])[0];
+    assert(v.root == 0);
+
+    // every node's children come after it, in the order they were queued.
+    let mut next = 1;
+    for uint::range(0, arena.num_nodes()) |i| {
+      let n = &arena.nodes[i];
+      for vec::each(~[n.left, n.right]) |child| {
+        if *child != NIL {
+          assert(*child == next);
+          next += 1;
+        }
+      }
+    }
+    assert(next as uint == arena.num_nodes());
+    assert(entries(&arena, v).len() == 100);
+  }
+}
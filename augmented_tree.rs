@@ -0,0 +1,278 @@
+use core::cmp::{Eq, Ord};
+use red_black_tree::{RBColor, Red, Black, RBNodes, modify_in};
+
+/**
+ * Describes a summary kept for every subtree of an AugMap. Summaries must
+ * form a monoid: `merge` is associative and `empty` is its identity, so a
+ * subtree's summary doesn't depend on how the tree happens to be balanced.
+ */
+pub trait Augment<K, V, S> {
+  // the summary of a subtree with no entries.
+  pure fn empty() -> S;
+
+  // the summary of a single entry.
+  pure fn single(k: &K, v: &V) -> S;
+
+  // the summary of two adjacent runs of entries, `a` before `b`.
+  pure fn merge(a: &S, b: &S) -> S;
+}
+
+pub enum AugNode<K: Copy Eq Ord, V: Copy, S: Copy> {
+  //    color    left              k  value      right             summary
+  ANode(RBColor, @AugNode<K,V,S>, K, Option<V>, @AugNode<K,V,S>, S),
+  ALeaf
+}
+
+/**
+ * A purely functional red-black map that keeps a user supplied summary
+ * (sum, min, max endpoint, ...) in every node. Updates go through RBMap's
+ * own modify_in and balancing with an AugMap as the node builder, so
+ * every node the rotations build gets its summary recomputed from its
+ * children, and a summary query over any key range only has to look at
+ * O(log n) nodes.
+ */
+pub struct AugMap<K: Copy Eq Ord, V: Copy, S: Copy, A: Copy Augment<K, V, S>> {
+  aug: A,
+  root: @AugNode<K, V, S>
+}
+
+pure fn AugMap<K: Copy Eq Ord, V: Copy, S: Copy, A: Copy Augment<K, V, S>>(aug: A) -> AugMap<K, V, S, A> {
+  AugMap { aug: aug, root: @ALeaf }
+}
+
+pure fn summary_of<K: Copy Eq Ord, V: Copy, S: Copy, A: Augment<K, V, S>>(aug: &A, t: @AugNode<K, V, S>) -> S {
+  match t {
+    @ALeaf => aug.empty(),
+    @ANode(_, _, _, _, _, s) => s
+  }
+}
+
+// recolors a red root black; the summary doesn't depend on color.
+pure fn ablacken<K: Copy Eq Ord, V: Copy, S: Copy>(t: @AugNode<K, V, S>) -> @AugNode<K, V, S> {
+  match t {
+    @ANode(Red, l, k, v, r, s) => @ANode(Black, l, k, v, r, s),
+    _ => t
+  }
+}
+
+// builds a node, recomputing its summary from its children.
+pure fn anode<K: Copy Eq Ord, V: Copy, S: Copy, A: Augment<K, V, S>>(aug: &A, c: RBColor, l: @AugNode<K,V,S>, k: K, v: Option<V>, r: @AugNode<K,V,S>) -> @AugNode<K,V,S> {
+  let left = summary_of(aug, l);
+  let right = summary_of(aug, r);
+  let s = match v {
+    Some(ref value) => aug.merge(&aug.merge(&left, &aug.single(&k, value)), &right),
+    None => aug.merge(&left, &right)
+  };
+  @ANode(c, l, k, v, r, s)
+}
+
+impl<K: Copy Eq Ord, V: Copy, S: Copy, A: Copy Augment<K, V, S>> AugMap<K, V, S, A> {
+  pure fn get(k: K) -> Option<V> {
+    let mut cur = self.root;
+    loop {
+      match cur {
+        @ALeaf => return None,
+        @ANode(_, left, key, maybe_value, right, _) => {
+          if (k.lt(&key)) {
+            cur = left;
+          } else if (k.gt(&key)) {
+            cur = right;
+          } else {
+            return copy maybe_value;
+          }
+        }
+      }
+    }
+  }
+
+  pure fn put(k: K, v: V) -> AugMap<K, V, S, A> {
+    AugMap { aug: self.aug, root: ablacken(modify_in(&self, self.root, k, Some(v))) }
+  }
+
+  // deleting a key that isn't there returns the map itself.
+  pure fn delete(k: K) -> AugMap<K, V, S, A> {
+    if self.get(k).is_none() {
+      return self;
+    }
+    AugMap { aug: self.aug, root: ablacken(modify_in(&self, self.root, k, None)) }
+  }
+
+  // the summary of every entry in the map.
+  pure fn summary() -> S {
+    summary_of(&self.aug, self.root)
+  }
+
+  // the summary of the entries with lo <= key < hi, in O(log n).
+  pure fn range_summary(lo: K, hi: K) -> S {
+    self.bounded_summary(self.root, Some(lo), Some(hi))
+  }
+
+  priv pure fn bounded_summary(t: @AugNode<K, V, S>, lo: Option<K>, hi: Option<K>) -> S {
+    match t {
+      @ALeaf => self.aug.empty(),
+      @ANode(_, left, key, maybe_value, right, s) => {
+        match (lo, hi) {
+          (None, None) => return s,
+          (Some(l), _) if key.lt(&l) => return self.bounded_summary(right, lo, hi),
+          (_, Some(h)) if key.ge(&h) => return self.bounded_summary(left, lo, hi),
+          _ => ()
+        }
+
+        // `key` is inside the range, so each side is bounded on one end only.
+        let l = self.bounded_summary(left, lo, None);
+        let r = self.bounded_summary(right, None, hi);
+        match maybe_value {
+          Some(ref value) => self.aug.merge(&self.aug.merge(&l, &self.aug.single(&key, value)), &r),
+          None => self.aug.merge(&l, &r)
+        }
+      }
+    }
+  }
+
+  // visits every entry in ascending key order until `f` returns false.
+  pure fn each(f: fn(&K, &V) -> bool) {
+    self.traverse(self.root, f);
+  }
+
+  priv pure fn traverse(t: @AugNode<K, V, S>, f: fn(&K, &V) -> bool) -> bool {
+    match t {
+      @ALeaf => true,
+      @ANode(_, left, key, maybe_value, right, _) => {
+        if (!self.traverse(left, f)) {
+          return false;
+        }
+        match maybe_value {
+          Some(value) => if (!f(&key, &value)) { return false; },
+          None => ()
+        }
+        self.traverse(right, f)
+      }
+    }
+  }
+}
+
+impl<K: Copy Eq Ord, V: Copy, S: Copy, A: Copy Augment<K, V, S>> AugMap<K, V, S, A> : RBNodes<K, V, @AugNode<K, V, S>> {
+  pure fn node(c: RBColor, l: @AugNode<K,V,S>, k: K, v: Option<V>, r: @AugNode<K,V,S>) -> @AugNode<K,V,S> {
+    anode(&self.aug, c, l, k, v, r)
+  }
+
+  pure fn expose(t: &@AugNode<K,V,S>) -> Option<(RBColor, @AugNode<K,V,S>, K, Option<V>, @AugNode<K,V,S>)> {
+    match *t {
+      @ALeaf => None,
+      @ANode(c, l, k, v, r, _) => Some((c, l, k, v, r))
+    }
+  }
+}
+
+// sums int values.
+pub enum SumOf {
+  Sum
+}
+
+impl<K> SumOf : Augment<K, int, int> {
+  pure fn empty() -> int { 0 }
+  pure fn single(_k: &K, v: &int) -> int { *v }
+  pure fn merge(a: &int, b: &int) -> int { *a + *b }
+}
+
+// the smallest int value, if there is one.
+pub enum MinOf {
+  Min
+}
+
+impl<K> MinOf : Augment<K, int, Option<int>> {
+  pure fn empty() -> Option<int> { None }
+  pure fn single(_k: &K, v: &int) -> Option<int> { Some(*v) }
+  pure fn merge(a: &Option<int>, b: &Option<int>) -> Option<int> {
+    match (*a, *b) {
+      (Some(x), Some(y)) => Some(if x < y { x } else { y }),
+      (None, y) => y,
+      (x, None) => x
+    }
+  }
+}
+
+// counts entries.
+pub enum CountOf {
+  Count
+}
+
+impl<K, V> CountOf : Augment<K, V, uint> {
+  pure fn empty() -> uint { 0 }
+  pure fn single(_k: &K, _v: &V) -> uint { 1 }
+  pure fn merge(a: &uint, b: &uint) -> uint { *a + *b }
+}
+
+
+#[test]
+fn test_range_sum() {
+  let mut m = AugMap(Sum);
+  for uint::range(0, 100) |i| {
+    m = m.put(i, i as int);
+  }
+
+  assert(m.summary() == 4950);
+  assert(m.range_summary(10, 20) == 145);
+  assert(m.range_summary(0, 1) == 0);
+  assert(m.range_summary(99, 1000) == 99);
+  assert(m.range_summary(50, 50) == 0);
+
+  let m2 = m.delete(15).put(12, 100);
+  assert(m2.range_summary(10, 20) == 145 - 15 - 12 + 100);
+  assert(m.range_summary(10, 20) == 145);
+}
+
+#[test]
+fn test_range_min_and_count() {
+  let values = ~[7, 3, 9, 1, 8, 2, 6];
+  let mut mins = AugMap(Min);
+  let mut counts = AugMap(Count);
+  for vec::eachi(values) |i, v| {
+    mins = mins.put(i, *v);
+    counts = counts.put(i, *v);
+  }
+
+  assert(mins.summary() == Some(1));
+  assert(mins.range_summary(0, 3) == Some(3));
+  assert(mins.range_summary(4, 7) == Some(2));
+  assert(mins.range_summary(4, 4) == None);
+
+  assert(counts.range_summary(2, 6) == 4);
+  assert(counts.delete(3).summary() == 6);
+}
+
+#[cfg(test)]
+fn black_depths<K: Copy Eq Ord, V: Copy, S: Copy>(t: @AugNode<K, V, S>, parent_red: bool) -> (uint, uint) {
+  match t {
+    @ALeaf => (0, 0),
+    @ANode(c, l, _, _, r, _) => {
+      let red = match c { Red => true, Black => false };
+      assert(!(red && parent_red));
+      let (lb, ld) = black_depths(l, red);
+      let (rb, rd) = black_depths(r, red);
+      assert(lb == rb);
+      (lb + if red { 0 } else { 1 }, 1 + uint::max(ld, rd))
+    }
+  }
+}
+
+#[test]
+fn test_puts_keep_balance() {
+  let mut m = AugMap(Count);
+  for uint::range(0, 1000) |i| {
+    m = m.put(i, i);
+  }
+  match m.root {
+    @ANode(Black, _, _, _, _, _) => (),
+    _ => fail ~"the root should be black"
+  }
+  let (_, depth) = black_depths(m.root, false);
+  assert(depth <= 20);
+
+  for uint::range(0, 500) |i| {
+    m = m.delete(i * 2);
+  }
+  let (_, depth) = black_depths(m.root, false);
+  assert(depth <= 20);
+  assert(m.summary() == 500);
+}
@@ -0,0 +1,112 @@
+use core::cmp::{Eq, Ord};
+use red_black_tree::{RBMap, RBMap_empty};
+use persistent_map::PersistentMap;
+
+/**
+ * A purely functional counting bag (multiset): a map from each key to the
+ * number of times it was added, built on RBMap. Every operation returns a
+ * new version and leaves the old one valid.
+ */
+pub struct Bag<K: Copy Eq Ord> {
+  map: @RBMap<K, uint>,
+  total: uint
+}
+
+pure fn Bag<K: Copy Eq Ord>() -> Bag<K> {
+  Bag { map: RBMap_empty(), total: 0 }
+}
+
+impl<K: Copy Eq Ord> Bag<K> {
+  pure fn insert(k: K) -> Bag<K> {
+    self.insert_n(k, 1)
+  }
+
+  pure fn insert_n(k: K, n: uint) -> Bag<K> {
+    if (n == 0) {
+      return self;
+    }
+    Bag { map: self.map.put(k, self.count(k) + n), total: self.total + n }
+  }
+
+  pure fn remove_one(k: K) -> Bag<K> {
+    match self.count(k) {
+      0 => self,
+      1 => Bag { map: self.map.delete(k), total: self.total - 1 },
+      n => Bag { map: self.map.put(k, n - 1), total: self.total - 1 }
+    }
+  }
+
+  pure fn remove_all(k: K) -> Bag<K> {
+    match self.count(k) {
+      0 => self,
+      n => Bag { map: self.map.delete(k), total: self.total - n }
+    }
+  }
+
+  pure fn count(k: K) -> uint {
+    match self.map.get(k) {
+      Some(n) => n,
+      None => 0
+    }
+  }
+
+  pure fn contains(k: K) -> bool {
+    self.count(k) > 0
+  }
+
+  // total number of items, counting repeats.
+  pure fn len() -> uint {
+    self.total
+  }
+
+  pure fn is_empty() -> bool {
+    self.total == 0
+  }
+
+  // visits each distinct key with its count, in ascending key order.
+  pure fn each(f: fn(&K, uint) -> bool) {
+    self.map.traverse(|k, n| f(k, *n));
+  }
+}
+
+
+#[test]
+fn test_counts() {
+  let v1 = Bag().insert("stevej").insert("thatstacy").insert("stevej");
+  assert(v1.count("stevej") == 2);
+  assert(v1.count("thatstacy") == 1);
+  assert(v1.count("jeremy") == 0);
+  assert(v1.len() == 3);
+
+  let v2 = v1.insert_n("jeremy", 5);
+  assert(v2.count("jeremy") == 5);
+  assert(v2.len() == 8);
+  assert(v1.count("jeremy") == 0);
+}
+
+#[test]
+fn test_remove() {
+  let v1 = Bag().insert_n(7, 2).insert(9);
+
+  let v2 = v1.remove_one(7);
+  assert(v2.count(7) == 1);
+  let v3 = v2.remove_one(7);
+  assert(!v3.contains(7));
+  assert(v3.len() == 1);
+
+  let v4 = v1.remove_all(7).remove_all(9);
+  assert(v4.is_empty());
+  assert(v1.count(7) == 2);
+}
+
+#[test]
+fn test_ordered_traversal() {
+  let bag = Bag().insert(3).insert(1).insert(3).insert(2);
+
+  let mut counts : ~[(int, uint)] = ~[];
+  for bag.each |k, n| {
+    counts.push((*k, n));
+  }
+
+  assert(counts == ~[(1, 1), (2, 1), (3, 2)]);
+}
@@ -0,0 +1,353 @@
+/**
+ * Timings for the crate's structures against what std offers for the
+ * same job, run with `make bench`. Every benchmark is an ignored test
+ * whose name starts with `bench`, like bench_maps.
+ *
+ * The std side: std::treemap and core's LinearMap stand in for an ordered
+ * and a hashed map, std::priority_queue for a binary heap, and
+ * core::hash's SipHash for the default hasher. This core has no
+ * count_ones, so ctpop32 is timed against the bit-at-a-time loop and
+ * Kernighan's clear-the-lowest-bit loop instead. The crate has no hash
+ * trie, so the persistent maps timed are tree::Tree, RBMap, BTreeMap and
+ * ArenaRBMap.
+ *
+ * Inputs come from seeded xorshift generators, so every run times the
+ * same work and results can be compared across changes.
+ */
+use core::cmp::{Eq, Ord};
+use core::hash::Hash;
+use core::send_map::linear::LinearMap;
+use std::treemap;
+use std::priority_queue;
+use tree;
+use red_black_tree::RBMap_empty;
+use memory::rbmap_sharing_stats;
+use btree::BTreeMap;
+use arena_map::ArenaRBMap;
+use pairing_heap::{MaxHeap, Heap};
+use fnv1a::fnv1a_64;
+use ctpop::ctpop32;
+use persistent_map::*;
+
+const MAP_SIZE : uint = 200000;
+const HEAP_SIZE : uint = 200000;
+const HASH_INPUTS : uint = 100000;
+const POPCOUNT_INPUTS : uint = 1000000;
+
+// Marsaglia's xorshift64. It never repeats within 2^64 - 1 steps, so
+// its outputs make distinct keys.
+struct XorShift {
+  mut state: u64
+}
+
+pure fn XorShift(seed: u64) -> XorShift {
+  XorShift { state: if seed == 0 { 88172645463325252 } else { seed } }
+}
+
+impl XorShift {
+  fn next() -> u64 {
+    self.state ^= self.state << 13;
+    self.state ^= self.state >> 7;
+    self.state ^= self.state << 17;
+    self.state
+  }
+}
+
+// `n` distinct keys in no particular order.
+fn random_keys(seed: u64, n: uint) -> ~[u64] {
+  let rng = XorShift(seed);
+  vec::from_fn(n, |_| rng.next())
+}
+
+fn random_byte_strings(seed: u64, n: uint, len: uint) -> ~[~[u8]] {
+  let rng = XorShift(seed);
+  vec::from_fn(n, |_| vec::from_fn(len, |_| rng.next() as u8))
+}
+
+// runs `f` once and prints how long each of its `ops` operations took.
+fn time(label: &str, ops: uint, f: fn()) {
+  let start = std::time::precise_time_ns();
+  f();
+  let ns = std::time::precise_time_ns() - start;
+  io::println(fmt!("%s: %? ns/op", label, ns / (ops as u64)));
+}
+
+// puts, gets, iterates and deletes `keys` in a persistent map.
+fn time_persistent_map<M: PersistentMap<u64, u64>>(name: &str, empty: M, keys: &[u64]) {
+  let n = keys.len();
+  let mut map = empty;
+  do time(fmt!("%s put", name), n) {
+    for vec::each(keys) |k| {
+      map = map.put(*k, *k);
+    }
+  }
+  assert(map.len() == n);
+
+  do time(fmt!("%s get", name), n) {
+    for vec::each(keys) |k| {
+      assert(map.get(*k) == Some(*k));
+    }
+  }
+
+  let mut sum = 0;
+  do time(fmt!("%s iterate", name), n) {
+    for map.iter |_, v| {
+      sum += *v;
+    }
+  }
+
+  do time(fmt!("%s delete", name), n) {
+    for vec::each(keys) |k| {
+      map = map.delete(*k);
+    }
+  }
+  assert(map.is_empty());
+  io::println(fmt!("%s checksum %?", name, sum));
+}
+
+#[test]
+#[ignore]
+fn bench_maps() {
+  let keys = random_keys(1, MAP_SIZE);
+
+  // RBMap's numbers only mean anything while it stays balanced, at most
+  // 2 log2(n + 1) deep.
+  let mut rbmap = RBMap_empty();
+  for vec::each(keys) |k| {
+    rbmap = rbmap.put(*k, *k);
+  }
+  let mut log2 = 0;
+  while (1u << log2) <= keys.len() {
+    log2 += 1;
+  }
+  assert(rbmap_sharing_stats(~[rbmap]).max_depth <= 2 * log2);
+
+  time_persistent_map("tree::Tree", tree::Empty(), keys);
+  time_persistent_map("RBMap", RBMap_empty(), keys);
+  time_persistent_map("BTreeMap", BTreeMap(), keys);
+
+  // ArenaRBMap isn't a PersistentMap; its gets are read against RBMap's
+  // above, after compacting into breadth-first order.
+  let arena = ArenaRBMap();
+  let mut v = arena.empty();
+  do time("ArenaRBMap put", keys.len()) {
+    for vec::each(keys) |k| {
+      v = arena.put(v, *k, *k);
+    }
+  }
+  let v = arena.compact(~[v])[0];
+  do time("ArenaRBMap get", keys.len()) {
+    for vec::each(keys) |k| {
+      assert(arena.get(v, *k) == Some(*k));
+    }
+  }
+
+  // std::treemap has no delete.
+  let tm = treemap::TreeMap();
+  do time("std::treemap put", keys.len()) {
+    for vec::each(keys) |k| {
+      treemap::insert(tm, *k, *k);
+    }
+  }
+  do time("std::treemap get", keys.len()) {
+    for vec::each(keys) |k| {
+      assert(treemap::find(tm, *k) == Some(*k));
+    }
+  }
+  let mut sum = 0;
+  do time("std::treemap iterate", keys.len()) {
+    treemap::traverse(tm, |_, v| sum += *v);
+  }
+
+  let mut lm = LinearMap();
+  do time("LinearMap put", keys.len()) {
+    for vec::each(keys) |k| {
+      lm.insert(*k, *k);
+    }
+  }
+  do time("LinearMap get", keys.len()) {
+    for vec::each(keys) |k| {
+      assert(lm.find(k).is_some());
+    }
+  }
+  do time("LinearMap iterate", keys.len()) {
+    for lm.each |_, v| {
+      sum += *v;
+    }
+  }
+  do time("LinearMap delete", keys.len()) {
+    for vec::each(keys) |k| {
+      assert(lm.remove(k));
+    }
+  }
+  io::println(fmt!("std checksum %?", sum));
+}
+
+#[test]
+#[ignore]
+fn bench_heaps() {
+  let elems = random_keys(2, HEAP_SIZE);
+
+  let mut heap = MaxHeap();
+  do time("PairingHeap push", elems.len()) {
+    for vec::each(elems) |e| {
+      heap = heap.insert(*e);
+    }
+  }
+  let mut last = u64::max_value;
+  do time("PairingHeap pop", elems.len()) {
+    while !heap.is_empty() {
+      let (e, rest) = heap.pop();
+      assert(e.get() <= last);
+      last = e.get();
+      heap = rest;
+    }
+  }
+
+  let mut queue = priority_queue::from_vec(~[]);
+  do time("std::priority_queue push", elems.len()) {
+    for vec::each(elems) |e| {
+      queue.push(*e);
+    }
+  }
+  let mut last = u64::max_value;
+  do time("std::priority_queue pop", elems.len()) {
+    while !queue.is_empty() {
+      let e = queue.pop();
+      assert(e <= last);
+      last = e;
+    }
+  }
+}
+
+// each transient against the plain operations it batches, ending with
+// a persistent version either way.
+#[test]
+#[ignore]
+fn bench_transients() {
+  let keys = random_keys(5, MAP_SIZE);
+
+  let mut map = RBMap_empty();
+  do time("RBMap put", keys.len()) {
+    for vec::each(keys) |k| {
+      map = map.put(*k, *k);
+    }
+  }
+  let mut built = RBMap_empty();
+  do time("RBMap transient put", keys.len()) {
+    let t = RBMap_empty().transient();
+    for vec::each(keys) |k| {
+      t.put(*k, *k);
+    }
+    built = t.persistent();
+  }
+  assert(built.len() == map.len());
+
+  let mut heap = MaxHeap();
+  do time("PairingHeap insert", keys.len()) {
+    for vec::each(keys) |k| {
+      heap = heap.insert(*k);
+    }
+  }
+  do time("PairingHeap pop", keys.len()) {
+    while !heap.is_empty() {
+      heap = heap.pop().second();
+    }
+  }
+
+  let t = MaxHeap().transient();
+  do time("PairingHeap transient insert", keys.len()) {
+    for vec::each(keys) |k| {
+      t.insert(*k);
+    }
+    heap = t.persistent();
+  }
+  assert(heap.peek().is_some());
+  do time("PairingHeap transient pop", keys.len()) {
+    while !t.is_empty() {
+      t.pop();
+    }
+  }
+}
+
+#[test]
+#[ignore]
+fn bench_hashes() {
+  for vec::each(~[8, 64, 1024]) |len| {
+    let inputs = random_byte_strings(3, HASH_INPUTS, *len);
+    let mut fnv = 0;
+    do time(fmt!("fnv1a_64 %u bytes", *len), inputs.len()) {
+      for vec::each(inputs) |bytes| {
+        fnv ^= fnv1a_64(copy *bytes);
+      }
+    }
+    let mut sip = 0;
+    do time(fmt!("SipHash %u bytes", *len), inputs.len()) {
+      for vec::each(inputs) |bytes| {
+        sip ^= bytes.hash();
+      }
+    }
+    io::println(fmt!("checksums %? %?", fnv, sip));
+  }
+}
+
+// the bit-at-a-time population count.
+pure fn popcount_loop(x: u32) -> u32 {
+  let mut x = x;
+  let mut n = 0;
+  while x != 0 {
+    n += x & 1;
+    x >>= 1;
+  }
+  n
+}
+
+// one step per set bit.
+pure fn popcount_kernighan(x: u32) -> u32 {
+  let mut x = x;
+  let mut n = 0;
+  while x != 0 {
+    x &= x - 1;
+    n += 1;
+  }
+  n
+}
+
+fn time_popcount(name: &str, words: &[u32], f: fn(u32) -> u32) -> u32 {
+  let mut total = 0;
+  do time(name, words.len()) {
+    for vec::each(words) |w| {
+      total += f(*w);
+    }
+  }
+  total
+}
+
+#[test]
+#[ignore]
+fn bench_popcount() {
+  let words = vec::map(random_keys(4, POPCOUNT_INPUTS), |x| *x as u32);
+  let a = time_popcount("ctpop32", words, ctpop32);
+  let b = time_popcount("bit loop", words, popcount_loop);
+  let c = time_popcount("kernighan", words, popcount_kernighan);
+  // all three agree, or the timings mean nothing.
+  assert(a == b && b == c);
+}
+
+#[test]
+fn test_generators_are_reproducible() {
+  assert(random_keys(9, 1000) == random_keys(9, 1000));
+  assert(random_keys(9, 10) != random_keys(10, 10));
+  assert(random_byte_strings(5, 3, 16) == random_byte_strings(5, 3, 16));
+
+  let keys = std::sort::merge_sort(|a, b| *a <= *b, random_keys(9, 1000));
+  for uint::range(1, keys.len()) |i| {
+    assert(keys[i - 1] != keys[i]);
+  }
+
+  for vec::each(random_keys(11, 1000)) |x| {
+    let w = *x as u32;
+    assert(ctpop32(w) == popcount_loop(w));
+    assert(ctpop32(w) == popcount_kernighan(w));
+  }
+}
@@ -0,0 +1,489 @@
+use ctpop::ctpop32;
+use lzc::{lzc32, tzc32};
+use red_black_tree::{RBMap, RBMap_empty};
+use persistent_map::*;
+
+// words per chunk of a PersistentBitSet; a chunk covers 256 bits.
+const CHUNK_WORDS : uint = 8;
+const CHUNK_BITS : uint = 256;
+
+pure fn bit(i: uint) -> u32 {
+  1 << ((i % 32) as u32)
+}
+
+pure fn count_words(words: &[u32]) -> uint {
+  let mut n = 0;
+  for vec::each(words) |w| {
+    n += ctpop32(*w) as uint;
+  }
+  n
+}
+
+// visits the set bits of `words` in ascending order, numbered from `base`.
+pure fn each_set_bit(words: &[u32], base: uint, f: fn(uint) -> bool) -> bool {
+  for vec::eachi(words) |w, word| {
+    let mut rest = *word;
+    while rest != 0 {
+      if !f(base + w * 32 + (tzc32(rest) as uint)) {
+        return false;
+      }
+      // clear the lowest set bit.
+      rest &= rest - 1;
+    }
+  }
+  true
+}
+
+// visits the set bits of `words` in descending order, numbered from `base`.
+pure fn each_set_bit_rev(words: &[u32], base: uint, f: fn(uint) -> bool) -> bool {
+  let mut w = words.len();
+  while w > 0 {
+    w -= 1;
+    let mut rest = words[w];
+    while rest != 0 {
+      let top = 31 - (lzc32(rest) as uint);
+      if !f(base + w * 32 + top) {
+        return false;
+      }
+      rest &= !(1 << (top as u32));
+    }
+  }
+  true
+}
+
+/**
+ * A fixed-capacity set of the integers [0, capacity), one bit each, in
+ * u32 words. Set operations work a word at a time, `len` is a popcount,
+ * and iteration jumps between set bits with trailing (or, going down,
+ * leading) zero counts.
+ */
+pub struct BitSet {
+  mut words: ~[u32],
+  capacity: uint
+}
+
+pure fn BitSet(capacity: uint) -> BitSet {
+  BitSet { words: vec::from_elem((capacity + 31) / 32, 0u32), capacity: capacity }
+}
+
+pure fn BitSet_from_vec(capacity: uint, elems: &[uint]) -> BitSet {
+  let mut words = vec::from_elem((capacity + 31) / 32, 0u32);
+  for vec::each(elems) |i| {
+    assert(*i < capacity);
+    words[*i / 32] |= bit(*i);
+  }
+  BitSet { words: words, capacity: capacity }
+}
+
+impl BitSet {
+  pure fn capacity() -> uint {
+    self.capacity
+  }
+
+  // returns true if `i` wasn't already in the set.
+  fn insert(i: uint) -> bool {
+    assert(i < self.capacity);
+    let added = !self.contains(i);
+    self.words[i / 32] |= bit(i);
+    added
+  }
+
+  // returns true if `i` was in the set.
+  fn remove(i: uint) -> bool {
+    let present = self.contains(i);
+    if present {
+      self.words[i / 32] &= !bit(i);
+    }
+    present
+  }
+
+  fn clear() {
+    self.words = vec::from_elem(self.words.len(), 0u32);
+  }
+
+  pure fn contains(i: uint) -> bool {
+    i < self.capacity && self.words[i / 32] & bit(i) != 0
+  }
+
+  pure fn len() -> uint {
+    count_words(self.words)
+  }
+
+  pure fn is_empty() -> bool {
+    vec::all(self.words, |w| *w == 0)
+  }
+
+  pure fn min() -> Option<uint> {
+    let mut found = None;
+    each_set_bit(self.words, 0, |i| { found = Some(i); false });
+    found
+  }
+
+  pure fn max() -> Option<uint> {
+    let mut found = None;
+    each_set_bit_rev(self.words, 0, |i| { found = Some(i); false });
+    found
+  }
+
+  // visits the members in ascending order.
+  pure fn each(f: fn(uint) -> bool) {
+    each_set_bit(self.words, 0, f);
+  }
+
+  // visits the members in descending order.
+  pure fn each_rev(f: fn(uint) -> bool) {
+    each_set_bit_rev(self.words, 0, f);
+  }
+
+  // the set operations below need both sets to have the same capacity.
+  priv pure fn combine(other: &BitSet, f: fn(u32, u32) -> u32) -> BitSet {
+    assert(self.capacity == other.capacity);
+    let mut words = ~[];
+    for uint::range(0, self.words.len()) |w| {
+      words.push(f(self.words[w], other.words[w]));
+    }
+    BitSet { words: words, capacity: self.capacity }
+  }
+
+  pure fn union(other: &BitSet) -> BitSet {
+    self.combine(other, |a, b| a | b)
+  }
+
+  pure fn intersection(other: &BitSet) -> BitSet {
+    self.combine(other, |a, b| a & b)
+  }
+
+  pure fn difference(other: &BitSet) -> BitSet {
+    self.combine(other, |a, b| a & !b)
+  }
+
+  pure fn symmetric_difference(other: &BitSet) -> BitSet {
+    self.combine(other, |a, b| a ^ b)
+  }
+
+  pure fn is_subset(other: &BitSet) -> bool {
+    self.difference(other).is_empty()
+  }
+
+  pure fn is_disjoint(other: &BitSet) -> bool {
+    self.intersection(other).is_empty()
+  }
+
+  pure fn to_vec() -> ~[uint] {
+    let mut v = ~[];
+    for self.each |i| { v.push(i); }
+    v
+  }
+
+  // a persistent copy of the set as it is now.
+  pure fn snapshot() -> PersistentBitSet {
+    let mut chunks = RBMap_empty();
+    for uint::range(0, (self.words.len() + CHUNK_WORDS - 1) / CHUNK_WORDS) |c| {
+      let last = uint::min((c + 1) * CHUNK_WORDS, self.words.len());
+      let chunk = vec::slice(self.words, c * CHUNK_WORDS, last);
+      if vec::any(chunk, |w| *w != 0) {
+        chunks = chunks.put(c, @pad_chunk(chunk));
+      }
+    }
+    PersistentBitSet { chunks: chunks, capacity: self.capacity, size: self.len() }
+  }
+}
+
+pure fn pad_chunk(words: ~[u32]) -> ~[u32] {
+  let mut chunk = words;
+  while chunk.len() < CHUNK_WORDS {
+    chunk.push(0);
+  }
+  chunk
+}
+
+/**
+ * A purely functional BitSet. The bits are split into 256-bit chunks kept
+ * in an RBMap by chunk number, and chunks with no bits set are left out.
+ * Changing a bit copies one chunk and the map path above it, so old
+ * versions stay valid and share every other chunk; set operations reuse
+ * a chunk unchanged when both inputs hold the very same one.
+ */
+pub struct PersistentBitSet {
+  chunks: @RBMap<uint, @~[u32]>,
+  capacity: uint,
+  size: uint
+}
+
+pure fn PersistentBitSet(capacity: uint) -> PersistentBitSet {
+  PersistentBitSet { chunks: RBMap_empty(), capacity: capacity, size: 0 }
+}
+
+impl PersistentBitSet {
+  pure fn capacity() -> uint {
+    self.capacity
+  }
+
+  pure fn len() -> uint {
+    self.size
+  }
+
+  pure fn is_empty() -> bool {
+    self.size == 0
+  }
+
+  pure fn contains(i: uint) -> bool {
+    match self.chunks.get(i / CHUNK_BITS) {
+      Some(chunk) => chunk[(i % CHUNK_BITS) / 32] & bit(i) != 0,
+      None => false
+    }
+  }
+
+  pure fn insert(i: uint) -> PersistentBitSet {
+    assert(i < self.capacity);
+    if self.contains(i) {
+      return self;
+    }
+    let c = i / CHUNK_BITS;
+    let mut chunk = match self.chunks.get(c) {
+      Some(chunk) => copy *chunk,
+      None => vec::from_elem(CHUNK_WORDS, 0u32)
+    };
+    chunk[(i % CHUNK_BITS) / 32] |= bit(i);
+    PersistentBitSet { chunks: self.chunks.put(c, @chunk), capacity: self.capacity, size: self.size + 1 }
+  }
+
+  pure fn remove(i: uint) -> PersistentBitSet {
+    if !self.contains(i) {
+      return self;
+    }
+    let c = i / CHUNK_BITS;
+    let mut chunk = copy *self.chunks.get(c).get();
+    chunk[(i % CHUNK_BITS) / 32] &= !bit(i);
+    PersistentBitSet { chunks: self.with_chunk(c, chunk), capacity: self.capacity, size: self.size - 1 }
+  }
+
+  // puts `chunk` at `c`, or drops `c` if the chunk is empty.
+  priv pure fn with_chunk(c: uint, chunk: ~[u32]) -> @RBMap<uint, @~[u32]> {
+    if vec::any(chunk, |w| *w != 0) { self.chunks.put(c, @chunk) } else { self.chunks.delete(c) }
+  }
+
+  pure fn min() -> Option<uint> {
+    let mut found = None;
+    self.each(|i| { found = Some(i); false });
+    found
+  }
+
+  pure fn max() -> Option<uint> {
+    let mut found = None;
+    self.each_rev(|i| { found = Some(i); false });
+    found
+  }
+
+  pure fn each(f: fn(uint) -> bool) {
+    for self.chunks.iter |c, chunk| {
+      if !each_set_bit(**chunk, *c * CHUNK_BITS, f) {
+        return;
+      }
+    }
+  }
+
+  pure fn each_rev(f: fn(uint) -> bool) {
+    self.chunks.traverse_rev(|c, chunk| each_set_bit_rev(**chunk, *c * CHUNK_BITS, f));
+  }
+
+  pure fn union(other: &PersistentBitSet) -> PersistentBitSet {
+    assert(self.capacity == other.capacity);
+    let mut chunks = self.chunks;
+    let mut size = self.size;
+    for other.chunks.iter |c, theirs| {
+      match self.chunks.get(*c) {
+        None => {
+          chunks = chunks.put(*c, *theirs);
+          size += count_words(**theirs);
+        }
+        Some(mine) => {
+          if !box::ptr_eq(mine, *theirs) {
+            let merged = vec::map2(*mine, **theirs, |a, b| *a | *b);
+            size += count_words(merged) - count_words(*mine);
+            chunks = chunks.put(*c, @merged);
+          }
+        }
+      }
+    }
+    PersistentBitSet { chunks: chunks, capacity: self.capacity, size: size }
+  }
+
+  pure fn intersection(other: &PersistentBitSet) -> PersistentBitSet {
+    assert(self.capacity == other.capacity);
+    let mut result = PersistentBitSet(self.capacity);
+    for self.chunks.iter |c, mine| {
+      match other.chunks.get(*c) {
+        None => (),
+        Some(theirs) => {
+          let chunk = if box::ptr_eq(*mine, theirs) {
+            *mine
+          } else {
+            @vec::map2(**mine, *theirs, |a, b| *a & *b)
+          };
+          let n = count_words(*chunk);
+          if n > 0 {
+            result = PersistentBitSet { chunks: result.chunks.put(*c, chunk), capacity: self.capacity, size: result.size + n };
+          }
+        }
+      }
+    }
+    result
+  }
+
+  pure fn difference(other: &PersistentBitSet) -> PersistentBitSet {
+    assert(self.capacity == other.capacity);
+    let mut result = self;
+    for other.chunks.iter |c, theirs| {
+      match self.chunks.get(*c) {
+        None => (),
+        Some(mine) => {
+          let chunk = vec::map2(*mine, **theirs, |a, b| *a & !*b);
+          let size = result.size - count_words(*mine) + count_words(chunk);
+          result = PersistentBitSet { chunks: result.with_chunk(*c, chunk), capacity: self.capacity, size: size };
+        }
+      }
+    }
+    result
+  }
+
+  pure fn symmetric_difference(other: &PersistentBitSet) -> PersistentBitSet {
+    self.difference(other).union(&other.difference(&self))
+  }
+
+  // a mutable copy of this version.
+  pure fn thaw() -> BitSet {
+    let mut words = vec::from_elem((self.capacity + 31) / 32, 0u32);
+    for self.chunks.iter |c, chunk| {
+      for vec::eachi(**chunk) |w, word| {
+        let index = *c * CHUNK_WORDS + w;
+        if index < words.len() {
+          words[index] = *word;
+        }
+      }
+    }
+    BitSet { words: words, capacity: self.capacity }
+  }
+
+  pure fn to_vec() -> ~[uint] {
+    let mut v = ~[];
+    for self.each |i| { v.push(i); }
+    v
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  fn sample(n: uint, seed: uint, capacity: uint) -> ~[uint] {
+    let mut v = ~[];
+    let mut x = seed;
+    for uint::range(0, n) |_| {
+      x = (x * 1103515245 + 12345) % 2147483648;
+      v.push((x >> 4) % capacity);
+    }
+    v
+  }
+
+  fn naive(capacity: uint, elems: &[uint]) -> ~[bool] {
+    let mut present = vec::from_elem(capacity, false);
+    for vec::each(elems) |i| { present[*i] = true; }
+    present
+  }
+
+  fn members(present: &[bool]) -> ~[uint] {
+    let mut v = ~[];
+    for vec::eachi(present) |i, p| {
+      if *p { v.push(i); }
+    }
+    v
+  }
+
+  fn combined(na: &[bool], nb: &[bool], f: fn(bool, bool) -> bool) -> ~[uint] {
+    let mut v = ~[];
+    for uint::range(0, na.len()) |i| {
+      if f(na[i], nb[i]) { v.push(i); }
+    }
+    v
+  }
+
+  #[test]
+  fn test_insert_remove_contains() {
+    let set = BitSet(100);
+    assert(set.is_empty());
+    assert(set.insert(3));
+    assert(!set.insert(3));
+    assert(set.insert(99));
+    assert(set.insert(32));
+    assert(set.len() == 3);
+    assert(set.contains(32) && !set.contains(31) && !set.contains(1000));
+    assert(set.to_vec() == ~[3, 32, 99]);
+    assert(set.min() == Some(3));
+    assert(set.max() == Some(99));
+
+    let mut down = ~[];
+    for set.each_rev |i| { down.push(i); }
+    assert(down == ~[99, 32, 3]);
+
+    assert(set.remove(32));
+    assert(!set.remove(32));
+    assert(set.to_vec() == ~[3, 99]);
+    set.clear();
+    assert(set.is_empty() && set.max().is_none());
+  }
+
+  #[test]
+  fn test_set_operations_match_naive() {
+    let capacity = 1000;
+    let xs = sample(300, 1, capacity);
+    let ys = sample(300, 2, capacity);
+    let a = BitSet_from_vec(capacity, xs);
+    let b = BitSet_from_vec(capacity, ys);
+    let na = naive(capacity, xs);
+    let nb = naive(capacity, ys);
+
+    assert(a.to_vec() == members(na));
+    assert(a.union(&b).to_vec() == combined(na, nb, |x, y| x || y));
+    assert(a.intersection(&b).to_vec() == combined(na, nb, |x, y| x && y));
+    assert(a.difference(&b).to_vec() == combined(na, nb, |x, y| x && !y));
+    assert(a.symmetric_difference(&b).to_vec() == combined(na, nb, |x, y| x != y));
+    assert(a.intersection(&b).is_subset(&a));
+    assert(a.difference(&b).is_disjoint(&b));
+
+    let pa = a.snapshot();
+    let pb = b.snapshot();
+    assert(pa.len() == a.len());
+    assert(pa.union(&pb).to_vec() == combined(na, nb, |x, y| x || y));
+    assert(pa.intersection(&pb).to_vec() == combined(na, nb, |x, y| x && y));
+    assert(pa.difference(&pb).to_vec() == combined(na, nb, |x, y| x && !y));
+    assert(pa.symmetric_difference(&pb).to_vec() == combined(na, nb, |x, y| x != y));
+    assert(pa.union(&pb).len() == a.union(&b).len());
+    assert(pa.difference(&pb).len() == a.difference(&b).len());
+  }
+
+  #[test]
+  fn test_persistent_versions_share_chunks() {
+    let v1 = PersistentBitSet(10000).insert(5).insert(300).insert(9999);
+    let v2 = v1.insert(6).remove(9999);
+
+    assert(v1.to_vec() == ~[5, 300, 9999]);
+    assert(v2.to_vec() == ~[5, 6, 300]);
+    assert(v2.len() == 3);
+    assert(v2.max() == Some(300));
+    assert(v1.max() == Some(9999));
+
+    // the chunk holding 300 wasn't touched, so both versions share it.
+    assert(box::ptr_eq(v1.chunks.get(1).get(), v2.chunks.get(1).get()));
+    // and removing the last bit of a chunk drops it.
+    assert(v2.chunks.get(9999 / 256).is_none());
+
+    let thawed = v2.thaw();
+    thawed.insert(7);
+    assert(thawed.to_vec() == ~[5, 6, 7, 300]);
+    assert(v2.to_vec() == ~[5, 6, 300]);
+
+    let mut down = ~[];
+    for v1.each_rev |i| { down.push(i); }
+    assert(down == ~[9999, 300, 5]);
+  }
+}
@@ -0,0 +1,308 @@
+use ctpop::ctpop32;
+use bytes::{push_u32_le, push_u64_le, read_u32_le, read_u64_le};
+
+const BITVECTOR_MAGIC : u32 = 0x42495456;  // "BITV"
+
+// words per superblock; a superblock covers 256 bits.
+const SUPERBLOCK_WORDS : uint = 8;
+
+/**
+ * An immutable succinct bit vector with constant time rank and
+ * logarithmic time select.
+ *
+ * Next to the bits it keeps two small tables: the number of 1s before
+ * every 256-bit superblock, and the number of 1s before every word
+ * within its superblock (at most 224, so a byte each). rank adds the two
+ * to a ctpop32 of the masked word; select binary searches the superblock
+ * table and then scans at most 8 words. Each 256 bits carry a uint and
+ * 8 bytes of tables, about 50% on top of the bits themselves on a 64-bit
+ * target.
+ */
+pub struct BitVector {
+  // one spare zero word on the end, so rank(len) never reads past the bits.
+  words: ~[u32],
+  superblocks: ~[uint],
+  blocks: ~[u8],
+  len: uint
+}
+
+// bit i of the vector is bit (i % 32) of word i / 32.
+pure fn BitVector_from_words(words: &[u32], len: uint) -> BitVector {
+  let num_words = len / 32 + 1;
+  let mut padded = ~[];
+  for uint::range(0, num_words) |w| {
+    let word = if w < words.len() { words[w] } else { 0 };
+    // clear anything past `len`, so the counts only see real bits.
+    let used = if (w + 1) * 32 <= len { 32 } else if w * 32 < len { len - w * 32 } else { 0 };
+    padded.push(word & low_mask(used));
+  }
+
+  let mut superblocks = ~[];
+  let mut blocks = ~[];
+  let mut total = 0;
+  let mut in_superblock = 0;
+  for uint::range(0, num_words) |w| {
+    if w % SUPERBLOCK_WORDS == 0 {
+      superblocks.push(total);
+      in_superblock = 0;
+    }
+    blocks.push(in_superblock as u8);
+    let ones = ctpop32(padded[w]) as uint;
+    total += ones;
+    in_superblock += ones;
+  }
+
+  BitVector { words: padded, superblocks: superblocks, blocks: blocks, len: len }
+}
+
+pure fn BitVector(bits: &[bool]) -> BitVector {
+  let mut words = vec::from_elem(bits.len() / 32 + 1, 0u32);
+  for vec::eachi(bits) |i, b| {
+    if *b {
+      words[i / 32] |= 1 << ((i % 32) as u32);
+    }
+  }
+  BitVector_from_words(words, bits.len())
+}
+
+fn BitVector_from_bytes(buf: &[u8]) -> Option<BitVector> {
+  let mut pos = 0;
+  if read_u32_le(buf, &mut pos) != Some(BITVECTOR_MAGIC) {
+    return None;
+  }
+  let len = match read_u64_le(buf, &mut pos) { Some(n) => n, None => return None };
+  // the words must be exactly what's left; rounding len up could wrap.
+  let num_words = len / 32 + if len % 32 == 0 { 0 } else { 1 };
+  let rest = buf.len() - pos;
+  if rest % 4 != 0 || (rest / 4) as u64 != num_words {
+    return None;
+  }
+  let mut words = ~[];
+  for uint::range(0, rest / 4) |_| {
+    words.push(read_u32_le(buf, &mut pos).get());
+  }
+  Some(BitVector_from_words(words, len as uint))
+}
+
+// the lowest n bits set, for n <= 32.
+pure fn low_mask(n: uint) -> u32 {
+  if n >= 32 { 0xffffffff } else { (1 << (n as u32)) - 1 }
+}
+
+// the position of the k-th (from 0) set bit of `word`; it must have more than k.
+pure fn select_in_word(word: u32, k: uint) -> uint {
+  let mut seen = 0;
+  let mut pos = 0;
+  // skip whole bytes first.
+  loop {
+    let ones = ctpop32((word >> (pos as u32)) & 0xff) as uint;
+    if seen + ones > k {
+      break;
+    }
+    seen += ones;
+    pos += 8;
+  }
+  loop {
+    if word & (1 << (pos as u32)) != 0 {
+      if seen == k {
+        return pos;
+      }
+      seen += 1;
+    }
+    pos += 1;
+  }
+}
+
+impl BitVector {
+  pure fn len() -> uint {
+    self.len
+  }
+
+  pure fn get(i: uint) -> bool {
+    assert(i < self.len);
+    self.words[i / 32] & (1 << ((i % 32) as u32)) != 0
+  }
+
+  // the number of 1s.
+  pure fn count_ones() -> uint {
+    self.rank1(self.len)
+  }
+
+  pure fn count_zeros() -> uint {
+    self.len - self.count_ones()
+  }
+
+  // the number of 1s in positions [0, i), for i <= len.
+  pure fn rank1(i: uint) -> uint {
+    assert(i <= self.len);
+    let w = i / 32;
+    self.superblocks[w / SUPERBLOCK_WORDS] + (self.blocks[w] as uint) +
+      (ctpop32(self.words[w] & low_mask(i % 32)) as uint)
+  }
+
+  // the number of 0s in positions [0, i), for i <= len.
+  pure fn rank0(i: uint) -> uint {
+    i - self.rank1(i)
+  }
+
+  // the position of the k-th (from 0) 1, or None if there are k or fewer.
+  pure fn select1(k: uint) -> Option<uint> {
+    if k >= self.count_ones() {
+      return None;
+    }
+    self.select(k, true)
+  }
+
+  // the position of the k-th (from 0) 0, or None if there are k or fewer.
+  pure fn select0(k: uint) -> Option<uint> {
+    if k >= self.count_zeros() {
+      return None;
+    }
+    self.select(k, false)
+  }
+
+  // the number of `ones` (or zeros) before superblock s.
+  priv pure fn superblock_rank(s: uint, ones: bool) -> uint {
+    if ones { self.superblocks[s] } else { s * SUPERBLOCK_WORDS * 32 - self.superblocks[s] }
+  }
+
+  priv pure fn select(k: uint, ones: bool) -> Option<uint> {
+    // the last superblock with at most k matching bits before it.
+    let mut lo = 0;
+    let mut hi = self.superblocks.len();
+    while hi - lo > 1 {
+      let mid = (lo + hi) / 2;
+      if self.superblock_rank(mid, ones) <= k {
+        lo = mid;
+      } else {
+        hi = mid;
+      }
+    }
+
+    let mut remaining = k - self.superblock_rank(lo, ones);
+    let last = uint::min((lo + 1) * SUPERBLOCK_WORDS, self.words.len());
+    for uint::range(lo * SUPERBLOCK_WORDS, last) |w| {
+      let word = if ones { self.words[w] } else { !self.words[w] };
+      let count = ctpop32(word) as uint;
+      if remaining < count {
+        return Some(w * 32 + select_in_word(word, remaining));
+      }
+      remaining -= count;
+    }
+    fail ~"select ran past the end of its superblock";
+  }
+
+  // visits each bit in order.
+  pure fn each(f: fn(bool) -> bool) {
+    for uint::range(0, self.len) |i| {
+      if !f(self.get(i)) {
+        return;
+      }
+    }
+  }
+
+  // only the bits are written; the rank tables are rebuilt on load.
+  fn to_bytes() -> ~[u8] {
+    let mut out = ~[];
+    push_u32_le(&mut out, BITVECTOR_MAGIC);
+    push_u64_le(&mut out, self.len as u64);
+    for uint::range(0, (self.len + 31) / 32) |w| {
+      push_u32_le(&mut out, self.words[w]);
+    }
+    out
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use bytes::{push_u32_le, push_u64_le};
+
+  fn random_bits(n: uint, seed: uint, one_in: uint) -> ~[bool] {
+    let mut bits = ~[];
+    let mut x = seed;
+    for uint::range(0, n) |_| {
+      x = (x * 1103515245 + 12345) % 2147483648;
+      bits.push((x >> 8) % one_in == 0);
+    }
+    bits
+  }
+
+  fn check_against_naive(bits: &[bool]) {
+    let bv = BitVector(bits);
+    assert(bv.len() == bits.len());
+
+    let mut ones = 0;
+    let mut zeros = 0;
+    for vec::eachi(bits) |i, b| {
+      assert(bv.get(i) == *b);
+      assert(bv.rank1(i) == ones);
+      assert(bv.rank0(i) == zeros);
+      if *b {
+        assert(bv.select1(ones) == Some(i));
+        ones += 1;
+      } else {
+        assert(bv.select0(zeros) == Some(i));
+        zeros += 1;
+      }
+    }
+    assert(bv.rank1(bits.len()) == ones);
+    assert(bv.count_ones() == ones);
+    assert(bv.select1(ones).is_none());
+    assert(bv.select0(zeros).is_none());
+  }
+
+  #[test]
+  fn test_against_naive_scan() {
+    check_against_naive(~[]);
+    check_against_naive(~[true]);
+    check_against_naive(~[false]);
+    check_against_naive(vec::from_elem(256, true));
+    check_against_naive(vec::from_elem(257, false));
+    check_against_naive(random_bits(5000, 1, 2));
+    check_against_naive(random_bits(5000, 2, 17));
+    check_against_naive(random_bits(3001, 3, 1));
+  }
+
+  #[test]
+  fn test_from_words_ignores_bits_past_len() {
+    let bv = BitVector_from_words(~[0xffffffff, 0xffffffff], 40);
+    assert(bv.count_ones() == 40);
+    assert(bv.select1(39) == Some(39));
+    assert(bv.select1(40).is_none());
+  }
+
+  #[test]
+  fn test_serialization() {
+    let bits = random_bits(1000, 4, 3);
+    let bv = BitVector(bits);
+    let bytes = bv.to_bytes();
+    assert(bytes.len() == 12 + 4 * 32);
+
+    let restored = BitVector_from_bytes(bytes).get();
+    assert(restored.len() == 1000);
+    assert(restored.words == bv.words);
+    assert(restored.select1(100) == bv.select1(100));
+
+    assert(BitVector_from_bytes(vec::slice(bytes, 0, 20)).is_none());
+  }
+
+  // a header claiming `len` bits, followed by `words` zero words.
+  fn header(len: u64, words: uint) -> ~[u8] {
+    let mut out = ~[];
+    push_u32_le(&mut out, BITVECTOR_MAGIC);
+    push_u64_le(&mut out, len);
+    out + vec::from_elem(words * 4, 0u8)
+  }
+
+  #[test]
+  fn test_from_bytes_checks_len() {
+    assert(BitVector_from_bytes(header(64, 2)).is_some());
+    assert(BitVector_from_bytes(header(0, 0)).get().len() == 0);
+    assert(BitVector_from_bytes(header(65, 2)).is_none());
+    assert(BitVector_from_bytes(header(64, 3)).is_none());
+    // (len + 31) / 32 would wrap around to 0 here.
+    assert(BitVector_from_bytes(header(u64::max_value, 0)).is_none());
+    assert(BitVector_from_bytes(header(1 << 40, 2)).is_none());
+  }
+}
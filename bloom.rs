@@ -0,0 +1,458 @@
+use fnv1a::fnv1a_64;
+use ctpop::ctpop32;
+use bytes::{push_u32_le, push_u64_le, read_u32_le, read_u64_le};
+
+const BLOOM_MAGIC : u32 = 0x424c4f4d;   // "BLOM"
+const COUNTING_MAGIC : u32 = 0x43424c4d;  // "CBLM"
+
+/**
+ * Works out a filter size (bits, hashes) for `capacity` items at the given
+ * false positive rate: m = -n ln p / (ln 2)^2 and k = (m / n) ln 2. Fails
+ * unless 0 < fp_rate < 1, since no size gives any other rate.
+ */
+pure fn optimal_shape(capacity: uint, fp_rate: f64) -> (uint, uint) {
+  // written so that NaN fails too.
+  if !(fp_rate > 0f64 && fp_rate < 1f64) {
+    fail fmt!("optimal_shape(%u, %?): the false positive rate must be between 0 and 1", capacity, fp_rate);
+  }
+  let n = if capacity == 0 { 1f64 } else { capacity as f64 };
+  let ln2 = f64::ln(2f64);
+  let m = f64::ceil(-n * f64::ln(fp_rate) / (ln2 * ln2));
+  let k = f64::round(m / n * ln2);
+  (if m < 1f64 { 1 } else { m as uint }, if k < 1f64 { 1 } else { k as uint })
+}
+
+/**
+ * The k bit positions for `data`. One FNV-1a 64 hash is split into two
+ * 32-bit halves h1 and h2 and the positions are h1 + i * h2 (mod m),
+ * which is as good as k independent hashes [Kirsch, Mitzenmacher 2006].
+ */
+pure fn bloom_indices(data: &[u8], num_bits: uint, num_hashes: uint) -> ~[uint] {
+  let hash = fnv1a_64(vec::from_slice(data));
+  let h1 = hash & 0xffffffff;
+  let h2 = (hash >> 32) | 1;
+
+  let mut indices = ~[];
+  for uint::range(0, num_hashes) |i| {
+    indices.push(((h1 + (i as u64) * h2) % (num_bits as u64)) as uint);
+  }
+  indices
+}
+
+/**
+ * A Bloom filter: a set membership test that can answer "maybe" for
+ * items that were never inserted, but never "no" for ones that were.
+ */
+pub struct BloomFilter {
+  mut bits: ~[u32],
+  num_bits: uint,
+  num_hashes: uint
+}
+
+// fails if `num_bits` or `num_hashes` is zero; such a filter can't hold anything.
+pure fn BloomFilter(num_bits: uint, num_hashes: uint) -> BloomFilter {
+  if num_bits == 0 || num_hashes == 0 {
+    fail fmt!("BloomFilter(%u, %u): needs at least one bit and one hash", num_bits, num_hashes);
+  }
+  BloomFilter {
+    bits: vec::from_elem((num_bits + 31) / 32, 0u32),
+    num_bits: num_bits,
+    num_hashes: num_hashes
+  }
+}
+
+// a filter sized to hold `capacity` items at the given false positive rate.
+pure fn BloomFilter_with_rate(capacity: uint, fp_rate: f64) -> BloomFilter {
+  let (num_bits, num_hashes) = optimal_shape(capacity, fp_rate);
+  BloomFilter(num_bits, num_hashes)
+}
+
+fn BloomFilter_from_bytes(buf: &[u8]) -> Option<BloomFilter> {
+  let mut pos = 0;
+  if read_u32_le(buf, &mut pos) != Some(BLOOM_MAGIC) {
+    return None;
+  }
+  let num_bits = match read_u64_le(buf, &mut pos) { Some(n) => n, None => return None };
+  let num_hashes = match read_u32_le(buf, &mut pos) { Some(n) => n as uint, None => return None };
+  if num_bits == 0 || num_hashes == 0 {
+    return None;
+  }
+
+  // the words must be exactly what's left, checked before allocating and
+  // without rounding num_bits up, which could wrap.
+  let words = num_bits / 32 + if num_bits % 32 == 0 { 0 } else { 1 };
+  let rest = buf.len() - pos;
+  if rest % 4 != 0 || (rest / 4) as u64 != words {
+    return None;
+  }
+
+  let filter = BloomFilter(num_bits as uint, num_hashes);
+  for uint::range(0, filter.bits.len()) |i| {
+    filter.bits[i] = read_u32_le(buf, &mut pos).get();
+  }
+  Some(filter)
+}
+
+impl BloomFilter {
+  fn insert(data: &[u8]) {
+    for vec::each(bloom_indices(data, self.num_bits, self.num_hashes)) |i| {
+      self.bits[*i / 32] |= 1 << ((*i % 32) as u32);
+    }
+  }
+
+  pure fn contains(data: &[u8]) -> bool {
+    for vec::each(bloom_indices(data, self.num_bits, self.num_hashes)) |i| {
+      if self.bits[*i / 32] & (1 << ((*i % 32) as u32)) == 0 {
+        return false;
+      }
+    }
+    true
+  }
+
+  pure fn same_shape(other: &BloomFilter) -> bool {
+    self.num_bits == other.num_bits && self.num_hashes == other.num_hashes
+  }
+
+  // number of bits set, counted a word at a time.
+  pure fn bits_set() -> uint {
+    let mut n = 0;
+    for vec::each(self.bits) |word| {
+      n += ctpop32(*word) as uint;
+    }
+    n
+  }
+
+  // the fraction of bits set. Above about one half the false positive rate climbs quickly.
+  pure fn fill_ratio() -> f64 {
+    (self.bits_set() as f64) / (self.num_bits as f64)
+  }
+
+  // estimates how many distinct items were inserted: -(m / k) ln(1 - X / m).
+  pure fn estimated_count() -> f64 {
+    let m = self.num_bits as f64;
+    let x = self.bits_set() as f64;
+    if x >= m {
+      return m;
+    }
+    -(m / (self.num_hashes as f64)) * f64::ln(1f64 - x / m)
+  }
+
+  // the expected false positive rate at the current fill: ratio^k.
+  pure fn false_positive_rate() -> f64 {
+    f64::pow(self.fill_ratio(), self.num_hashes as f64)
+  }
+
+  // a filter holding everything in either filter; None if the shapes differ.
+  pure fn union(other: &BloomFilter) -> Option<BloomFilter> {
+    self.combine(other, |a, b| a | b)
+  }
+
+  // a filter that (maybe) holds what both filters hold; None if the shapes differ.
+  pure fn intersection(other: &BloomFilter) -> Option<BloomFilter> {
+    self.combine(other, |a, b| a & b)
+  }
+
+  priv pure fn combine(other: &BloomFilter, f: fn(u32, u32) -> u32) -> Option<BloomFilter> {
+    if !self.same_shape(other) {
+      return None;
+    }
+    let mut bits = ~[];
+    for uint::range(0, self.bits.len()) |i| {
+      bits.push(f(self.bits[i], other.bits[i]));
+    }
+    Some(BloomFilter { bits: bits, num_bits: self.num_bits, num_hashes: self.num_hashes })
+  }
+
+  fn to_bytes() -> ~[u8] {
+    let mut out = ~[];
+    push_u32_le(&mut out, BLOOM_MAGIC);
+    push_u64_le(&mut out, self.num_bits as u64);
+    push_u32_le(&mut out, self.num_hashes as u32);
+    for vec::each(self.bits) |word| {
+      push_u32_le(&mut out, *word);
+    }
+    out
+  }
+}
+
+/**
+ * A Bloom filter with a small counter in place of each bit, so items can
+ * be removed again. Counters stop at 255; a counter that got that high is
+ * never decremented, since it may have lost count.
+ */
+pub struct CountingBloomFilter {
+  mut counters: ~[u8],
+  num_hashes: uint
+}
+
+// fails if `num_counters` or `num_hashes` is zero.
+pure fn CountingBloomFilter(num_counters: uint, num_hashes: uint) -> CountingBloomFilter {
+  if num_counters == 0 || num_hashes == 0 {
+    fail fmt!("CountingBloomFilter(%u, %u): needs at least one counter and one hash",
+              num_counters, num_hashes);
+  }
+  CountingBloomFilter {
+    counters: vec::from_elem(num_counters, 0u8),
+    num_hashes: num_hashes
+  }
+}
+
+pure fn CountingBloomFilter_with_rate(capacity: uint, fp_rate: f64) -> CountingBloomFilter {
+  let (num_counters, num_hashes) = optimal_shape(capacity, fp_rate);
+  CountingBloomFilter(num_counters, num_hashes)
+}
+
+fn CountingBloomFilter_from_bytes(buf: &[u8]) -> Option<CountingBloomFilter> {
+  let mut pos = 0;
+  if read_u32_le(buf, &mut pos) != Some(COUNTING_MAGIC) {
+    return None;
+  }
+  let num_counters = match read_u64_le(buf, &mut pos) { Some(n) => n, None => return None };
+  let num_hashes = match read_u32_le(buf, &mut pos) { Some(n) => n as uint, None => return None };
+  if num_counters == 0 || num_hashes == 0 || (buf.len() - pos) as u64 != num_counters {
+    return None;
+  }
+  Some(CountingBloomFilter {
+    counters: vec::slice(buf, pos, buf.len()),
+    num_hashes: num_hashes
+  })
+}
+
+impl CountingBloomFilter {
+  fn insert(data: &[u8]) {
+    for vec::each(bloom_indices(data, self.counters.len(), self.num_hashes)) |i| {
+      if self.counters[*i] < 255 {
+        self.counters[*i] += 1;
+      }
+    }
+  }
+
+  // removes one copy of `data`. Returns false, changing nothing, if it isn't there.
+  fn remove(data: &[u8]) -> bool {
+    if !self.contains(data) {
+      return false;
+    }
+    for vec::each(bloom_indices(data, self.counters.len(), self.num_hashes)) |i| {
+      if self.counters[*i] < 255 {
+        self.counters[*i] -= 1;
+      }
+    }
+    true
+  }
+
+  pure fn contains(data: &[u8]) -> bool {
+    for vec::each(bloom_indices(data, self.counters.len(), self.num_hashes)) |i| {
+      if self.counters[*i] == 0 {
+        return false;
+      }
+    }
+    true
+  }
+
+  pure fn same_shape(other: &CountingBloomFilter) -> bool {
+    self.counters.len() == other.counters.len() && self.num_hashes == other.num_hashes
+  }
+
+  // adds the counts of both filters, as if every insert went into one.
+  pure fn union(other: &CountingBloomFilter) -> Option<CountingBloomFilter> {
+    self.combine(other, |a, b| if (a as uint) + (b as uint) > 255 { 255 } else { a + b })
+  }
+
+  pure fn intersection(other: &CountingBloomFilter) -> Option<CountingBloomFilter> {
+    self.combine(other, |a, b| if a < b { a } else { b })
+  }
+
+  priv pure fn combine(other: &CountingBloomFilter, f: fn(u8, u8) -> u8) -> Option<CountingBloomFilter> {
+    if !self.same_shape(other) {
+      return None;
+    }
+    let mut counters = ~[];
+    for uint::range(0, self.counters.len()) |i| {
+      counters.push(f(self.counters[i], other.counters[i]));
+    }
+    Some(CountingBloomFilter { counters: counters, num_hashes: self.num_hashes })
+  }
+
+  // the plain filter with a bit set wherever a counter is non-zero.
+  fn to_bloom_filter() -> BloomFilter {
+    let filter = BloomFilter(self.counters.len(), self.num_hashes);
+    for uint::range(0, self.counters.len()) |i| {
+      if self.counters[i] != 0 {
+        filter.bits[i / 32] |= 1 << ((i % 32) as u32);
+      }
+    }
+    filter
+  }
+
+  fn to_bytes() -> ~[u8] {
+    let mut out = ~[];
+    push_u32_le(&mut out, COUNTING_MAGIC);
+    push_u64_le(&mut out, self.counters.len() as u64);
+    push_u32_le(&mut out, self.num_hashes as u32);
+    out + self.counters
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use bytes::{push_u32_le, push_u64_le};
+
+  fn key(i: uint) -> ~[u8] {
+    str::to_bytes(fmt!("item-%u", i))
+  }
+
+  #[test]
+  fn test_no_false_negatives() {
+    let filter = BloomFilter_with_rate(1000, 0.01);
+    for uint::range(0, 1000) |i| {
+      filter.insert(key(i));
+    }
+    for uint::range(0, 1000) |i| {
+      assert(filter.contains(key(i)));
+    }
+  }
+
+  #[test]
+  fn test_false_positive_rate_near_target() {
+    let filter = BloomFilter_with_rate(1000, 0.01);
+    for uint::range(0, 1000) |i| {
+      filter.insert(key(i));
+    }
+
+    let mut false_positives = 0;
+    for uint::range(1000, 11000) |i| {
+      if filter.contains(key(i)) {
+        false_positives += 1;
+      }
+    }
+    // 1% of 10000 is 100; leave room for an unlucky hash.
+    assert(false_positives < 200);
+
+    let estimate = filter.estimated_count();
+    assert(estimate > 900f64 && estimate < 1100f64);
+    assert(filter.fill_ratio() > 0.4 && filter.fill_ratio() < 0.6);
+  }
+
+  #[test]
+  fn test_union_and_intersection() {
+    let a = BloomFilter(1024, 4);
+    let b = BloomFilter(1024, 4);
+    a.insert(str::to_bytes("stevej"));
+    b.insert(str::to_bytes("thatstacy"));
+
+    let both = a.union(&b).get();
+    assert(both.contains(str::to_bytes("stevej")));
+    assert(both.contains(str::to_bytes("thatstacy")));
+
+    let neither = a.intersection(&b).get();
+    assert(!neither.contains(str::to_bytes("stevej")));
+
+    assert(a.union(&BloomFilter(2048, 4)).is_none());
+  }
+
+  #[test]
+  fn test_serialization() {
+    let filter = BloomFilter_with_rate(100, 0.05);
+    for uint::range(0, 100) |i| {
+      filter.insert(key(i));
+    }
+
+    let bytes = filter.to_bytes();
+    let restored = BloomFilter_from_bytes(bytes).get();
+    assert(restored.same_shape(&filter));
+    assert(restored.bits == filter.bits);
+
+    assert(BloomFilter_from_bytes(vec::slice(bytes, 0, bytes.len() - 1)).is_none());
+    assert(BloomFilter_from_bytes(~[1, 2, 3]).is_none());
+  }
+
+  // a header with the given counts and `words` words of bits after it.
+  fn bloom_header(num_bits: u64, num_hashes: u32, words: uint) -> ~[u8] {
+    let mut out = ~[];
+    push_u32_le(&mut out, BLOOM_MAGIC);
+    push_u64_le(&mut out, num_bits);
+    push_u32_le(&mut out, num_hashes);
+    out + vec::from_elem(words * 4, 0u8)
+  }
+
+  #[test]
+  fn test_from_bytes_rejects_bad_headers() {
+    assert(BloomFilter_from_bytes(bloom_header(64, 3, 2)).is_some());
+    assert(BloomFilter_from_bytes(bloom_header(0, 3, 0)).is_none());
+    assert(BloomFilter_from_bytes(bloom_header(64, 0, 2)).is_none());
+    // a huge num_bits must not be trusted, or rounded up until it wraps.
+    assert(BloomFilter_from_bytes(bloom_header(u64::max_value, 3, 2)).is_none());
+    assert(BloomFilter_from_bytes(bloom_header(1 << 40, 3, 2)).is_none());
+    assert(BloomFilter_from_bytes(bloom_header(64, 3, 3)).is_none());
+
+    let mut counting = ~[];
+    push_u32_le(&mut counting, COUNTING_MAGIC);
+    push_u64_le(&mut counting, 0);
+    push_u32_le(&mut counting, 3);
+    assert(CountingBloomFilter_from_bytes(counting).is_none());
+  }
+
+  #[test]
+  #[should_fail]
+  fn test_zero_bits_fails() {
+    BloomFilter(0, 3);
+  }
+
+  #[test]
+  #[should_fail]
+  fn test_zero_rate_fails() {
+    BloomFilter_with_rate(100, 0f64);
+  }
+
+  #[test]
+  #[should_fail]
+  fn test_rate_of_one_fails() {
+    CountingBloomFilter_with_rate(100, 1f64);
+  }
+
+  #[test]
+  #[should_fail]
+  fn test_nan_rate_fails() {
+    BloomFilter_with_rate(100, f64::NaN);
+  }
+
+  #[test]
+  fn test_counting_remove() {
+    let filter = CountingBloomFilter_with_rate(100, 0.01);
+    filter.insert(str::to_bytes("stevej"));
+    filter.insert(str::to_bytes("stevej"));
+    filter.insert(str::to_bytes("thatstacy"));
+
+    assert(filter.remove(str::to_bytes("stevej")));
+    assert(filter.contains(str::to_bytes("stevej")));
+    assert(filter.remove(str::to_bytes("stevej")));
+    assert(!filter.contains(str::to_bytes("stevej")));
+    assert(!filter.remove(str::to_bytes("stevej")));
+    assert(filter.contains(str::to_bytes("thatstacy")));
+
+    let plain = filter.to_bloom_filter();
+    assert(plain.contains(str::to_bytes("thatstacy")));
+  }
+
+  #[test]
+  fn test_counting_algebra_and_serialization() {
+    let a = CountingBloomFilter(512, 3);
+    let b = CountingBloomFilter(512, 3);
+    a.insert(str::to_bytes("x"));
+    b.insert(str::to_bytes("x"));
+    b.insert(str::to_bytes("y"));
+
+    let sum = a.union(&b).get();
+    assert(sum.remove(str::to_bytes("x")));
+    assert(sum.contains(str::to_bytes("x")));
+
+    let common = a.intersection(&b).get();
+    assert(common.contains(str::to_bytes("x")));
+    assert(!common.contains(str::to_bytes("y")));
+
+    let restored = CountingBloomFilter_from_bytes(b.to_bytes()).get();
+    assert(restored.counters == b.counters);
+    assert(restored.contains(str::to_bytes("y")));
+  }
+}
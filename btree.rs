@@ -0,0 +1,433 @@
+use core::cmp::{Eq, Ord};
+use persistent_map::{PersistentMap, OrderedPersistentMap};
+use vec_util::{sub, insert_at, remove_at, replace_at};
+
+/**
+ * The most keys a B-tree node holds. Every node but the root holds at
+ * least half as many. Wider nodes mean fewer pointers to chase on a
+ * lookup and more copying on an update.
+ */
+const BTREE_ORDER : uint = 32;
+const BTREE_MIN : uint = 16;
+
+/**
+ * A node of a B-tree. Leaves have no children; an interior node with n
+ * keys has n + 1 children, and every key in children[i] sorts between
+ * keys[i - 1] and keys[i].
+ */
+pub struct BNode<K: Copy Eq Ord, V: Copy> {
+  keys: ~[K],
+  values: ~[V],
+  children: ~[@BNode<K, V>],
+  // number of entries in this subtree.
+  size: uint
+}
+
+/**
+ * A purely functional, copy-on-write B-tree map. An update copies the
+ * O(log n) nodes on the path to the changed key, each at most
+ * BTREE_ORDER wide, and shares everything else with the old version.
+ */
+pub struct BTreeMap<K: Copy Eq Ord, V: Copy> {
+  root: @BNode<K, V>
+}
+
+pure fn BTreeMap<K: Copy Eq Ord, V: Copy>() -> BTreeMap<K, V> {
+  BTreeMap { root: bnode(~[], ~[], ~[]) }
+}
+
+pure fn bnode<K: Copy Eq Ord, V: Copy>(keys: ~[K], values: ~[V], children: ~[@BNode<K, V>]) -> @BNode<K, V> {
+  let mut size = keys.len();
+  for vec::each(children) |child| {
+    size += child.size;
+  }
+  @BNode { keys: keys, values: values, children: children, size: size }
+}
+
+// binary search: (true, i) if keys[i] == k, else (false, i) where k would be inserted.
+pure fn search<K: Copy Eq Ord>(keys: &[K], k: &K) -> (bool, uint) {
+  let mut lo = 0;
+  let mut hi = keys.len();
+  while lo < hi {
+    let mid = (lo + hi) / 2;
+    if keys[mid] < *k {
+      lo = mid + 1;
+    } else if keys[mid] > *k {
+      hi = mid;
+    } else {
+      return (true, mid);
+    }
+  }
+  (false, lo)
+}
+
+impl<K: Copy Eq Ord, V: Copy> @BNode<K, V> {
+  pure fn is_leaf() -> bool {
+    self.children.is_empty()
+  }
+
+  pure fn get(k: &K) -> Option<V> {
+    let mut cur = self;
+    loop {
+      match search(cur.keys, k) {
+        (true, i) => return Some(cur.values[i]),
+        (false, i) => {
+          if cur.is_leaf() {
+            return None;
+          }
+          cur = cur.children[i];
+        }
+      }
+    }
+  }
+
+  /**
+   * Returns the new subtree, whether a new key was added, and the median
+   * and right half if the node overflowed and had to split.
+   */
+  pure fn insert(k: K, v: V) -> (@BNode<K, V>, bool, Option<(K, V, @BNode<K, V>)>) {
+    match search(self.keys, &k) {
+      (true, i) => {
+        (bnode(copy self.keys, replace_at(self.values, i, v), copy self.children), false, None)
+      }
+      (false, i) => {
+        if self.is_leaf() {
+          let n = bnode(insert_at(self.keys, i, k), insert_at(self.values, i, v), ~[]);
+          let (left, split) = n.split_if_full();
+          (left, true, split)
+        } else {
+          let (child, added, split) = self.children[i].insert(k, v);
+          let children = replace_at(self.children, i, child);
+          match split {
+            None => (bnode(copy self.keys, copy self.values, children), added, None),
+            Some((mk, mv, right)) => {
+              let n = bnode(insert_at(self.keys, i, mk),
+                            insert_at(self.values, i, mv),
+                            insert_at(children, i + 1, right));
+              let (left, split) = n.split_if_full();
+              (left, added, split)
+            }
+          }
+        }
+      }
+    }
+  }
+
+  priv pure fn split_if_full() -> (@BNode<K, V>, Option<(K, V, @BNode<K, V>)>) {
+    let n = self.keys.len();
+    if n <= BTREE_ORDER {
+      return (self, None);
+    }
+
+    let mid = n / 2;
+    let (left_children, right_children) = if self.is_leaf() {
+      (~[], ~[])
+    } else {
+      (sub(self.children, 0, mid + 1), sub(self.children, mid + 1, self.children.len()))
+    };
+    let left = bnode(sub(self.keys, 0, mid), sub(self.values, 0, mid), left_children);
+    let right = bnode(sub(self.keys, mid + 1, n), sub(self.values, mid + 1, n), right_children);
+    (left, Some((self.keys[mid], self.values[mid], right)))
+  }
+
+  // the new subtree, or None if `k` wasn't there. The result may be one key short.
+  pure fn remove(k: &K) -> Option<@BNode<K, V>> {
+    match search(self.keys, k) {
+      (true, i) => {
+        if self.is_leaf() {
+          Some(bnode(remove_at(self.keys, i), remove_at(self.values, i), ~[]))
+        } else {
+          // swap in the largest entry of the left child, then take it out of there.
+          let (pk, pv) = self.children[i].max_entry();
+          let child = self.children[i].remove(&pk).get();
+          Some(bnode(replace_at(self.keys, i, pk),
+                     replace_at(self.values, i, pv),
+                     replace_at(self.children, i, child)).fix_child(i))
+        }
+      }
+      (false, i) => {
+        if self.is_leaf() {
+          return None;
+        }
+        match self.children[i].remove(k) {
+          None => None,
+          Some(child) => {
+            Some(bnode(copy self.keys, copy self.values,
+                       replace_at(self.children, i, child)).fix_child(i))
+          }
+        }
+      }
+    }
+  }
+
+  // tops children[i] back up to BTREE_MIN keys by borrowing from or merging with a sibling.
+  priv pure fn fix_child(i: uint) -> @BNode<K, V> {
+    let child = self.children[i];
+    if child.keys.len() >= BTREE_MIN {
+      return self;
+    }
+
+    if i > 0 && self.children[i - 1].keys.len() > BTREE_MIN {
+      let left = self.children[i - 1];
+      let last = left.keys.len() - 1;
+      let new_left = bnode(sub(left.keys, 0, last), sub(left.values, 0, last),
+                           if left.is_leaf() { ~[] } else { sub(left.children, 0, last + 1) });
+      let new_child = bnode(insert_at(child.keys, 0, self.keys[i - 1]),
+                            insert_at(child.values, 0, self.values[i - 1]),
+                            if child.is_leaf() { ~[] } else { insert_at(child.children, 0, left.children[last + 1]) });
+      return bnode(replace_at(self.keys, i - 1, left.keys[last]),
+                   replace_at(self.values, i - 1, left.values[last]),
+                   replace_at(replace_at(self.children, i - 1, new_left), i, new_child));
+    }
+
+    if i + 1 < self.children.len() && self.children[i + 1].keys.len() > BTREE_MIN {
+      let right = self.children[i + 1];
+      let new_right = bnode(remove_at(right.keys, 0), remove_at(right.values, 0),
+                            if right.is_leaf() { ~[] } else { remove_at(right.children, 0) });
+      let mut child_keys = copy child.keys;
+      let mut child_values = copy child.values;
+      let mut child_children = copy child.children;
+      child_keys.push(self.keys[i]);
+      child_values.push(self.values[i]);
+      if !right.is_leaf() {
+        child_children.push(right.children[0]);
+      }
+      let new_child = bnode(child_keys, child_values, child_children);
+      return bnode(replace_at(self.keys, i, right.keys[0]),
+                   replace_at(self.values, i, right.values[0]),
+                   replace_at(replace_at(self.children, i, new_child), i + 1, new_right));
+    }
+
+    // neither sibling can spare a key: merge with one of them.
+    let j = if i > 0 { i - 1 } else { i };
+    let left = self.children[j];
+    let right = self.children[j + 1];
+    let mut keys = copy left.keys;
+    keys.push(self.keys[j]);
+    let mut values = copy left.values;
+    values.push(self.values[j]);
+    let merged = bnode(keys + right.keys, values + right.values, left.children + right.children);
+
+    bnode(remove_at(self.keys, j), remove_at(self.values, j),
+          replace_at(remove_at(self.children, j + 1), j, merged))
+  }
+
+  pure fn min_entry() -> (K, V) {
+    let mut cur = self;
+    while !cur.is_leaf() {
+      cur = cur.children[0];
+    }
+    (cur.keys[0], cur.values[0])
+  }
+
+  pure fn max_entry() -> (K, V) {
+    let mut cur = self;
+    while !cur.is_leaf() {
+      cur = cur.children[cur.children.len() - 1];
+    }
+    let last = cur.keys.len() - 1;
+    (cur.keys[last], cur.values[last])
+  }
+
+  // in-order walk of the entries with lo <= key < hi; either bound may be
+  // absent. Returns false once `f` or the upper bound stops the walk.
+  pure fn traverse(lo: Option<&K>, hi: Option<&K>, f: fn(&K, &V) -> bool) -> bool {
+    let n = self.keys.len();
+    let start = match lo { Some(k) => { let (_, i) = search(self.keys, k); i }, None => 0 };
+
+    for uint::range(start, n + 1) |i| {
+      if !self.is_leaf() && !self.children[i].traverse(lo, hi, f) {
+        return false;
+      }
+      if i == n {
+        break;
+      }
+      match hi {
+        Some(h) => if self.keys[i] >= *h { return false; },
+        None => ()
+      }
+      if !f(&self.keys[i], &self.values[i]) {
+        return false;
+      }
+    }
+    true
+  }
+}
+
+impl<K: Copy Eq Ord, V: Copy> BTreeMap<K, V> : PersistentMap<K, V> {
+  pure fn get(k: K) -> Option<V> {
+    self.root.get(&k)
+  }
+
+  pure fn put(k: K, v: V) -> BTreeMap<K, V> {
+    match self.root.insert(k, v) {
+      (root, _, None) => BTreeMap { root: root },
+      (left, _, Some((mk, mv, right))) => BTreeMap { root: bnode(~[mk], ~[mv], ~[left, right]) }
+    }
+  }
+
+  pure fn delete(k: K) -> BTreeMap<K, V> {
+    match self.root.remove(&k) {
+      None => self,
+      Some(root) => {
+        if root.keys.is_empty() && !root.is_leaf() {
+          BTreeMap { root: root.children[0] }
+        } else {
+          BTreeMap { root: root }
+        }
+      }
+    }
+  }
+
+  pure fn len() -> uint {
+    self.root.size
+  }
+
+  pure fn is_empty() -> bool {
+    self.root.size == 0
+  }
+
+  pure fn contains_key(k: K) -> bool {
+    self.get(k).is_some()
+  }
+
+  pure fn iter(f: fn(&K, &V) -> bool) {
+    self.root.traverse(None, None, f);
+  }
+
+  pure fn keys(f: fn(&K) -> bool) {
+    self.root.traverse(None, None, |k, _| f(k));
+  }
+
+  pure fn values(f: fn(&V) -> bool) {
+    self.root.traverse(None, None, |_, v| f(v));
+  }
+
+  pure fn insert_returning_old(k: K, v: V) -> (Option<V>, BTreeMap<K, V>) {
+    (self.get(k), self.put(k, v))
+  }
+
+  // the nodes are shallow enough that a lookup followed by a write is
+  // cheaper than threading the closure through a split or a merge.
+  pure fn update(k: K, f: fn(Option<&V>) -> Option<V>) -> BTreeMap<K, V> {
+    let current = self.get(k);
+    let result = match current {
+      Some(ref v) => f(Some(v)),
+      None => f(None)
+    };
+
+    match (current, result) {
+      (_, Some(v)) => self.put(k, v),
+      (Some(_), None) => self.delete(k),
+      (None, None) => self
+    }
+  }
+
+  pure fn alter(k: K, f: fn(&V) -> V) -> BTreeMap<K, V> {
+    self.update(k, |current| current.map(|v| f(*v)))
+  }
+
+  pure fn insert_with(k: K, v: V, f: fn(&V, &V) -> V) -> BTreeMap<K, V> {
+    self.update(k, |current| match current {
+      Some(old) => Some(f(old, &v)),
+      None => Some(v)
+    })
+  }
+
+  pure fn get_or_insert_with(k: K, f: fn() -> V) -> (V, BTreeMap<K, V>) {
+    match self.get(k) {
+      Some(v) => (v, self),
+      None => {
+        let v = f();
+        (v, self.put(k, v))
+      }
+    }
+  }
+}
+
+impl<K: Copy Eq Ord, V: Copy> BTreeMap<K, V> : OrderedPersistentMap<K, V> {
+  pure fn min() -> Option<(K, V)> {
+    if self.is_empty() { None } else { Some(self.root.min_entry()) }
+  }
+
+  pure fn max() -> Option<(K, V)> {
+    if self.is_empty() { None } else { Some(self.root.max_entry()) }
+  }
+
+  pure fn floor(k: K) -> Option<(K, V)> {
+    let mut best = None;
+    let mut cur = self.root;
+    loop {
+      match search(cur.keys, &k) {
+        (true, i) => return Some((cur.keys[i], cur.values[i])),
+        (false, i) => {
+          if i > 0 {
+            best = Some((cur.keys[i - 1], cur.values[i - 1]));
+          }
+          if cur.is_leaf() {
+            return best;
+          }
+          cur = cur.children[i];
+        }
+      }
+    }
+  }
+
+  pure fn ceiling(k: K) -> Option<(K, V)> {
+    let mut best = None;
+    let mut cur = self.root;
+    loop {
+      match search(cur.keys, &k) {
+        (true, i) => return Some((cur.keys[i], cur.values[i])),
+        (false, i) => {
+          if i < cur.keys.len() {
+            best = Some((cur.keys[i], cur.values[i]));
+          }
+          if cur.is_leaf() {
+            return best;
+          }
+          cur = cur.children[i];
+        }
+      }
+    }
+  }
+
+  pure fn range(lo: K, hi: K, f: fn(&K, &V) -> bool) {
+    self.root.traverse(Some(&lo), Some(&hi), f);
+  }
+}
+
+
+#[test]
+fn test_splits_and_merges() {
+  let mut m = BTreeMap();
+  for uint::range(0, 5000) |i| {
+    m = m.put((i * 7919) % 5000, i);
+  }
+  assert(m.len() == 5000);
+
+  let mut expected = 0;
+  for m.keys |k| {
+    assert(*k == expected);
+    expected += 1;
+  }
+  assert(expected == 5000);
+
+  let full = m;
+  for uint::range(0, 5000) |i| {
+    if i % 3 != 0 {
+      m = m.delete(i);
+    }
+  }
+  assert(m.len() == 1667);
+  for uint::range(0, 5000) |i| {
+    assert(m.contains_key(i) == (i % 3 == 0));
+    assert(full.get(i).is_some());
+  }
+
+  for uint::range(0, 5000) |i| {
+    m = m.delete(i);
+  }
+  assert(m.is_empty());
+  assert(full.len() == 5000);
+}
@@ -0,0 +1,72 @@
+/**
+ * Little-endian encoding helpers shared by the modules that serialize
+ * themselves to bytes.
+ */
+
+fn push_u16_le(out: &mut ~[u8], x: u16) {
+  out.push((x & 0xff) as u8);
+  out.push((x >> 8) as u8);
+}
+
+fn push_u32_le(out: &mut ~[u8], x: u32) {
+  for uint::range(0, 4) |i| {
+    out.push(((x >> (8 * i as u32)) & 0xff) as u8);
+  }
+}
+
+fn push_u64_le(out: &mut ~[u8], x: u64) {
+  for uint::range(0, 8) |i| {
+    out.push(((x >> (8 * i as u64)) & 0xff) as u8);
+  }
+}
+
+// reads a u16 at *pos and moves *pos past it, or None if the buffer is too short.
+fn read_u16_le(buf: &[u8], pos: &mut uint) -> Option<u16> {
+  if *pos + 2 > buf.len() {
+    return None;
+  }
+  let x = (buf[*pos] as u16) | ((buf[*pos + 1] as u16) << 8);
+  *pos += 2;
+  Some(x)
+}
+
+fn read_u32_le(buf: &[u8], pos: &mut uint) -> Option<u32> {
+  if *pos + 4 > buf.len() {
+    return None;
+  }
+  let mut x = 0u32;
+  for uint::range(0, 4) |i| {
+    x |= (buf[*pos + i] as u32) << (8 * i as u32);
+  }
+  *pos += 4;
+  Some(x)
+}
+
+fn read_u64_le(buf: &[u8], pos: &mut uint) -> Option<u64> {
+  if *pos + 8 > buf.len() {
+    return None;
+  }
+  let mut x = 0u64;
+  for uint::range(0, 8) |i| {
+    x |= (buf[*pos + i] as u64) << (8 * i as u64);
+  }
+  *pos += 8;
+  Some(x)
+}
+
+#[test]
+fn test_round_trip() {
+  let mut out = ~[];
+  push_u16_le(&mut out, 0xBEEF);
+  push_u32_le(&mut out, 0xDEADBEEF);
+  push_u64_le(&mut out, 0x0123456789ABCDEF);
+  assert(out.len() == 14);
+  assert(out[0] == 0xEF);
+  assert(out[2] == 0xEF);
+
+  let mut pos = 0;
+  assert(read_u16_le(out, &mut pos) == Some(0xBEEF));
+  assert(read_u32_le(out, &mut pos) == Some(0xDEADBEEF));
+  assert(read_u64_le(out, &mut pos) == Some(0x0123456789ABCDEF));
+  assert(read_u32_le(out, &mut pos) == None);
+}
@@ -0,0 +1,352 @@
+use fnv1a::fnv1a_64;
+use red_black_tree::{RBMap, RBMap_empty};
+use persistent_map::*;
+
+/**
+ * A consistent-hash ring with virtual nodes, built on RBMap. Each node is
+ * placed on the ring at `weight * replicas` points,
+ * fnv1a_64(node_id ++ "#" ++ replica), and a key belongs to the first point
+ * at or after its own hash, wrapping around past the largest point.
+ *
+ * Adding or removing a node returns a new ring and leaves the old one
+ * valid, so two versions can be compared to see which keys moved.
+ * Removing a node takes its points out of the tree with RBMap::remove,
+ * so a ring that sees a lot of churn doesn't fill up with deleted points.
+ */
+pub struct HashRing {
+  points: @RBMap<u64, ~str>,
+  weights: @RBMap<~str, uint>,
+  // the nodes that also hash to a point someone else already owns, in id order.
+  collided: @RBMap<u64, ~[~str]>,
+  replicas: uint
+}
+
+// `replicas` is the number of points a node of weight 1 gets.
+pure fn HashRing(replicas: uint) -> HashRing {
+  HashRing { points: RBMap_empty(), weights: RBMap_empty(), collided: RBMap_empty(), replicas: replicas }
+}
+
+pure fn point_hash(node: &str, replica: uint) -> u64 {
+  fnv1a_64(str::to_bytes(node) + str::to_bytes(fmt!("#%u", replica)))
+}
+
+/**
+ * How many of a set of sample keys are owned by a different node in two
+ * versions of a ring.
+ */
+pub struct MoveReport {
+  total: uint,
+  moved: uint
+}
+
+impl MoveReport {
+  pure fn fraction() -> f64 {
+    if self.total == 0 { 0f64 } else { (self.moved as f64) / (self.total as f64) }
+  }
+}
+
+impl HashRing {
+  // adds `node` with the given weight, or reweights it if it is already there.
+  pure fn add_node(node: &str, weight: uint) -> HashRing {
+    let ring = self.remove_node(node);
+    let name = str::from_slice(node);
+    let mut points = ring.points;
+    let mut collided = ring.collided;
+    for uint::range(0, weight * self.replicas) |replica| {
+      let h = point_hash(node, replica);
+      // on the rare collision the point stays with its owner, and `node`
+      // waits in line for it.
+      if points.contains_key(h) {
+        let waiting = match collided.get(h) { Some(names) => names, None => ~[] };
+        collided = collided.put(h, with_name(waiting, copy name));
+      } else {
+        points = points.put(h, copy name);
+      }
+    }
+    HashRing {
+      points: points,
+      weights: ring.weights.put(name, weight),
+      collided: collided,
+      replicas: self.replicas
+    }
+  }
+
+  /**
+   * Drops `node` and its points. A point it owned that another node also
+   * hashes to goes to the waiting node with the smallest id, as if that
+   * node had been added first.
+   */
+  pure fn remove_node(node: &str) -> HashRing {
+    let name = str::from_slice(node);
+    let weight = match self.weights.get(copy name) {
+      Some(w) => w,
+      None => return self
+    };
+    let mut points = self.points;
+    let mut collided = self.collided;
+    for uint::range(0, weight * self.replicas) |replica| {
+      let h = point_hash(node, replica);
+      let waiting = match collided.get(h) { Some(names) => names, None => ~[] };
+      if points.get(h) == Some(copy name) {
+        if waiting.is_empty() {
+          points = points.remove(h);
+        } else {
+          points = points.put(h, copy waiting[0]);
+          collided = with_waiting(collided, h, vec::slice(waiting, 1, waiting.len()));
+        }
+      } else {
+        collided = with_waiting(collided, h, vec::filter(waiting, |n| *n != name));
+      }
+    }
+    HashRing {
+      points: points,
+      weights: self.weights.remove(name),
+      collided: collided,
+      replicas: self.replicas
+    }
+  }
+
+  pure fn weight_of(node: &str) -> Option<uint> {
+    self.weights.get(str::from_slice(node))
+  }
+
+  // number of nodes on the ring.
+  pure fn len() -> uint {
+    self.weights.len()
+  }
+
+  pure fn is_empty() -> bool {
+    self.weights.is_empty()
+  }
+
+  // number of virtual node points on the ring.
+  pure fn num_points() -> uint {
+    self.points.len()
+  }
+
+  // visits every node and its weight, in node id order.
+  pure fn each_node(f: fn(&~str, &uint) -> bool) {
+    self.weights.iter(f);
+  }
+
+  // the node owning the first point at or after `h`, wrapping around.
+  pure fn node_for_hash(h: u64) -> Option<~str> {
+    match self.points.ceiling(h) {
+      Some((_, node)) => Some(node),
+      None => match self.points.min() {
+        Some((_, node)) => Some(node),
+        None => None
+      }
+    }
+  }
+
+  pure fn node_for(key: &[u8]) -> Option<~str> {
+    self.node_for_hash(fnv1a_64(vec::from_slice(key)))
+  }
+
+  // counts how many of `keys` are owned by a different node in `other`.
+  pure fn moved_keys(other: &HashRing, keys: &[~[u8]]) -> MoveReport {
+    let mut moved = 0;
+    for vec::each(keys) |key| {
+      if self.node_for(*key) != other.node_for(*key) {
+        moved += 1;
+      }
+    }
+    MoveReport { total: keys.len(), moved: moved }
+  }
+
+  /**
+   * The exact fraction of the hash space owned by a different node in
+   * `other`. Walks the points of both rings in order; between two
+   * consecutive points neither ring changes owner, so each gap is owned
+   * wholly by one node in each ring.
+   */
+  pure fn moved_fraction(other: &HashRing) -> f64 {
+    let mut bounds = ~[];
+    for self.points.keys |h| { bounds.push(*h); }
+    for other.points.keys |h| { bounds.push(*h); }
+    if bounds.is_empty() {
+      return 0f64;
+    }
+    let sorted = std::sort::merge_sort(|a, b| *a <= *b, bounds);
+    let mut distinct = ~[];
+    for vec::each(sorted) |h| {
+      if distinct.is_empty() || distinct[distinct.len() - 1] != *h {
+        distinct.push(*h);
+      }
+    }
+
+    let n = distinct.len();
+    let mut moved = 0f64;
+    for uint::range(0, n) |i| {
+      // the gap (prev, h], which wraps past zero for the first point.
+      let h = distinct[i];
+      let prev = distinct[(i + n - 1) % n];
+      if self.node_for_hash(h) != other.node_for_hash(h) {
+        moved += gap_length(prev, h);
+      }
+    }
+    moved / 18446744073709551616f64
+  }
+}
+
+// `names` with `name` added, keeping them sorted.
+pure fn with_name(names: ~[~str], name: ~str) -> ~[~str] {
+  let mut result = ~[];
+  let mut placed = false;
+  for vec::each(names) |n| {
+    if !placed && name < *n {
+      result.push(copy name);
+      placed = true;
+    }
+    result.push(copy *n);
+  }
+  if !placed {
+    result.push(name);
+  }
+  result
+}
+
+// `collided` with the nodes waiting for `h` set to `names`.
+pure fn with_waiting(collided: @RBMap<u64, ~[~str]>, h: u64, names: ~[~str]) -> @RBMap<u64, ~[~str]> {
+  if names.is_empty() {
+    collided.remove(h)
+  } else {
+    collided.put(h, names)
+  }
+}
+
+// the number of hashes in (from, to] going round the ring; a full turn when they meet.
+pure fn gap_length(from: u64, to: u64) -> f64 {
+  if from == to { 18446744073709551616f64 } else { (to - from) as f64 }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use memory::rbmap_sharing_stats;
+
+  fn sample_keys(n: uint) -> ~[~[u8]] {
+    let mut keys = ~[];
+    for uint::range(0, n) |i| {
+      keys.push(str::to_bytes(fmt!("key-%u", i)));
+    }
+    keys
+  }
+
+  fn count_for(ring: &HashRing, keys: &[~[u8]], node: &str) -> uint {
+    let mut n = 0;
+    for vec::each(keys) |key| {
+      if ring.node_for(*key) == Some(str::from_slice(node)) {
+        n += 1;
+      }
+    }
+    n
+  }
+
+  #[test]
+  fn test_lookup_and_wrap() {
+    let empty = HashRing(10);
+    assert(empty.node_for(str::to_bytes("anything")).is_none());
+
+    let ring = empty.add_node("a", 1);
+    assert(ring.node_for(str::to_bytes("anything")) == Some(~"a"));
+
+    // a hash past the last point wraps to the first.
+    let last = ring.points.max().get();
+    let first = ring.points.min().get();
+    let (last_hash, _) = last;
+    let (_, first_node) = first;
+    if last_hash < 0xffffffffffffffff {
+      assert(ring.node_for_hash(last_hash + 1) == Some(first_node));
+    }
+    assert(ring.node_for_hash(last_hash) == Some(~"a"));
+  }
+
+  #[test]
+  fn test_versions_are_persistent() {
+    let v1 = HashRing(50).add_node("a", 1).add_node("b", 1);
+    let v2 = v1.add_node("c", 1);
+    let v3 = v2.remove_node("a");
+
+    assert(v1.len() == 2 && v2.len() == 3 && v3.len() == 2);
+    assert(v1.num_points() == 100);
+    assert(v3.num_points() == 100);
+    assert(v3.weight_of("a").is_none());
+    assert(v1.weight_of("a") == Some(1));
+
+    let keys = sample_keys(1000);
+    assert(count_for(&v3, keys, "a") == 0);
+    assert(v2.remove_node("c").moved_keys(&v1, keys).moved == 0);
+  }
+
+  #[test]
+  fn test_weighted_distribution() {
+    let ring = HashRing(100).add_node("small", 1).add_node("big", 3);
+    let keys = sample_keys(10000);
+    let small = count_for(&ring, keys, "small");
+    let big = count_for(&ring, keys, "big");
+    assert(small + big == 10000);
+    // expect a 1:3 split, give or take.
+    assert(small > 1500 && small < 3500);
+
+    let even = ring.add_node("big", 1);
+    assert(even.num_points() == 200);
+    let small = count_for(&even, keys, "small");
+    assert(small > 4000 && small < 6000);
+  }
+
+  #[test]
+  fn test_adding_a_node_moves_a_fair_share() {
+    let mut ring = HashRing(100);
+    for vec::each(~[~"a", ~"b", ~"c", ~"d"]) |node| {
+      ring = ring.add_node(*node, 1);
+    }
+    let grown = ring.add_node("e", 1);
+
+    let keys = sample_keys(10000);
+    let report = ring.moved_keys(&grown, keys);
+    assert(report.total == 10000);
+    // every moved key went to the new node; about a fifth of them should move.
+    assert(report.moved == count_for(&grown, keys, "e"));
+    assert(report.fraction() > 0.12 && report.fraction() < 0.28);
+
+    let exact = ring.moved_fraction(&grown);
+    assert(exact > 0.12 && exact < 0.28);
+    assert(ring.moved_fraction(&ring) == 0f64);
+  }
+
+  #[test]
+  fn test_churn_leaves_no_deleted_points() {
+    let mut ring = HashRing(20).add_node("a", 1).add_node("b", 1);
+    for uint::range(0, 50) |i| {
+      ring = ring.add_node(fmt!("n%u", i % 5), 1 + i % 3).remove_node(fmt!("n%u", (i + 2) % 5));
+    }
+    // every node in the point tree is a live point.
+    let stats = rbmap_sharing_stats(~[ring.points]);
+    assert(stats.unique_nodes == ring.num_points());
+    assert(rbmap_sharing_stats(~[ring.weights]).unique_nodes == ring.len());
+    assert(ring.collided.is_empty());
+  }
+
+  #[test]
+  fn test_removed_node_gives_back_collided_points() {
+    let ring = HashRing(10).add_node("a", 1);
+    // fake "c" and then "b" having also hashed to one of a's points.
+    let h = point_hash("a", 0);
+    let collided = HashRing {
+      points: ring.points,
+      weights: ring.weights.put(~"b", 1).put(~"c", 1),
+      collided: ring.collided.put(h, with_name(with_name(~[], ~"c"), ~"b")),
+      replicas: 10
+    };
+    assert(collided.node_for_hash(h) == Some(~"a"));
+
+    let after = collided.remove_node("a");
+    assert(after.node_for_hash(h) == Some(~"b"));
+    assert(after.num_points() == 1);
+    assert(after.collided.get(h) == Some(~[~"c"]));
+    // the version it came from still has a's point.
+    assert(collided.node_for_hash(h) == Some(~"a"));
+  }
+}
@@ -0,0 +1,199 @@
+use core::cmp::{Eq, Ord};
+use augmented_tree::{AugMap, AugNode, ANode, ALeaf, Augment};
+
+/**
+ * A closed interval [lo, hi]. Intervals sort by lo, then by hi.
+ */
+pub struct Interval<T: Copy Eq Ord> {
+  lo: T,
+  hi: T
+}
+
+pure fn Interval<T: Copy Eq Ord>(lo: T, hi: T) -> Interval<T> {
+  Interval { lo: lo, hi: hi }
+}
+
+impl<T: Copy Eq Ord> Interval<T> : Eq {
+  pure fn eq(&self, other: &Interval<T>) -> bool {
+    self.lo == other.lo && self.hi == other.hi
+  }
+
+  pure fn ne(&self, other: &Interval<T>) -> bool { !self.eq(other) }
+}
+
+impl<T: Copy Eq Ord> Interval<T> : Ord {
+  pure fn lt(&self, other: &Interval<T>) -> bool {
+    self.lo < other.lo || (self.lo == other.lo && self.hi < other.hi)
+  }
+
+  pure fn le(&self, other: &Interval<T>) -> bool { !other.lt(self) }
+  pure fn gt(&self, other: &Interval<T>) -> bool { other.lt(self) }
+  pure fn ge(&self, other: &Interval<T>) -> bool { !self.lt(other) }
+}
+
+impl<T: Copy Eq Ord> Interval<T> {
+  pure fn overlaps(lo: &T, hi: &T) -> bool {
+    self.lo <= *hi && *lo <= self.hi
+  }
+}
+
+// keeps the largest hi endpoint in each subtree.
+pub enum MaxEndOf {
+  MaxEnd
+}
+
+impl<T: Copy Eq Ord, V> MaxEndOf : Augment<Interval<T>, V, Option<T>> {
+  pure fn empty() -> Option<T> { None }
+
+  pure fn single(k: &Interval<T>, _v: &V) -> Option<T> { Some(k.hi) }
+
+  pure fn merge(a: &Option<T>, b: &Option<T>) -> Option<T> {
+    match (*a, *b) {
+      (Some(x), Some(y)) => Some(if x < y { y } else { x }),
+      (None, y) => y,
+      (x, None) => x
+    }
+  }
+}
+
+/**
+ * A purely functional interval tree: an AugMap keyed by interval that
+ * keeps the largest endpoint of every subtree. Finding the k intervals
+ * that overlap a query skips every subtree that ends before the query
+ * starts or begins after it ends, for O(log n + k) node visits on a
+ * balanced tree.
+ */
+pub struct IntervalTree<T: Copy Eq Ord, V: Copy> {
+  map: AugMap<Interval<T>, V, Option<T>, MaxEndOf>
+}
+
+pure fn IntervalTree<T: Copy Eq Ord, V: Copy>() -> IntervalTree<T, V> {
+  IntervalTree { map: AugMap(MaxEnd) }
+}
+
+impl<T: Copy Eq Ord, V: Copy> IntervalTree<T, V> {
+  pure fn insert(lo: T, hi: T, v: V) -> IntervalTree<T, V> {
+    IntervalTree { map: self.map.put(Interval(lo, hi), v) }
+  }
+
+  pure fn remove(lo: T, hi: T) -> IntervalTree<T, V> {
+    IntervalTree { map: self.map.delete(Interval(lo, hi)) }
+  }
+
+  pure fn get(lo: T, hi: T) -> Option<V> {
+    self.map.get(Interval(lo, hi))
+  }
+
+  // visits every interval overlapping [lo, hi], ordered by start.
+  pure fn overlapping(lo: T, hi: T, f: fn(&Interval<T>, &V) -> bool) {
+    walk_overlapping(self.map.root, &lo, &hi, f);
+  }
+
+  // visits every interval containing `point`.
+  pure fn stabbing(point: T, f: fn(&Interval<T>, &V) -> bool) {
+    self.overlapping(point, point, f);
+  }
+
+  // the largest endpoint of any interval in the tree.
+  pure fn max_end() -> Option<T> {
+    self.map.summary()
+  }
+}
+
+pure fn walk_overlapping<T: Copy Eq Ord, V: Copy>(t: @AugNode<Interval<T>, V, Option<T>>, lo: &T, hi: &T, f: fn(&Interval<T>, &V) -> bool) -> bool {
+  match t {
+    @ALeaf => true,
+    @ANode(_, left, key, maybe_value, right, max_end) => {
+      // nothing below here reaches as far as `lo`.
+      match max_end {
+        None => return true,
+        Some(end) => if end < *lo { return true; }
+      }
+
+      if (!walk_overlapping(left, lo, hi, f)) {
+        return false;
+      }
+
+      // this interval and everything to its right start after `hi`.
+      if (key.lo > *hi) {
+        return true;
+      }
+
+      match maybe_value {
+        Some(value) => {
+          if (key.overlaps(lo, hi) && !f(&key, &value)) {
+            return false;
+          }
+        }
+        None => ()
+      }
+
+      walk_overlapping(right, lo, hi, f)
+    }
+  }
+}
+
+
+#[test]
+fn test_overlapping() {
+  let tree = IntervalTree()
+    .insert(15, 20, ~"a")
+    .insert(10, 30, ~"b")
+    .insert(17, 19, ~"c")
+    .insert(5, 20, ~"d")
+    .insert(12, 15, ~"e")
+    .insert(30, 40, ~"f");
+
+  let mut found : ~[~str] = ~[];
+  for tree.overlapping(18, 25) |_, v| {
+    found.push(copy *v);
+  }
+  assert(found == ~[~"d", ~"b", ~"a", ~"c"]);
+
+  found = ~[];
+  for tree.stabbing(30) |_, v| {
+    found.push(copy *v);
+  }
+  assert(found == ~[~"b", ~"f"]);
+
+  found = ~[];
+  for tree.overlapping(41, 50) |_, v| {
+    found.push(copy *v);
+  }
+  assert(found.is_empty());
+
+  assert(tree.max_end() == Some(40));
+  assert(tree.remove(30, 40).max_end() == Some(30));
+}
+
+#[test]
+fn test_matches_naive_scan() {
+  let mut tree = IntervalTree();
+  let mut all : ~[(int, int)] = ~[];
+  let mut seed = 17;
+  for uint::range(0, 200) |i| {
+    seed = (seed * 1103515245 + 12345) % 2147483648;
+    let lo = seed % 1000;
+    let hi = lo + (seed / 1000) % 50;
+    tree = tree.insert(lo, hi, i);
+    all.push((lo, hi));
+  }
+
+  for uint::range(0, 1000) |q| {
+    let a = q as int;
+    let b = a + 10;
+    let mut n = 0;
+    for tree.overlapping(a, b) |_, _| { n += 1; }
+
+    let mut expected = 0;
+    let mut seen = ~[];
+    for vec::each(all) |iv| {
+      let (lo, hi) = *iv;
+      if lo <= b && a <= hi && !vec::contains(seen, iv) {
+        expected += 1;
+        seen.push(*iv);
+      }
+    }
+    assert(n == expected);
+  }
+}
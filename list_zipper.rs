@@ -36,6 +36,16 @@ fn ListZipper_from_vec<E: Copy>(items: ~[E]) -> ListZipper<E> {
   ListZipper { hd: @Nil, tail: list::from_vec(items) }
 }
 
+// a zipper over `items` that has moved right `position` times.
+fn ListZipper_from_vec_at<E: Copy>(items: ~[E], position: uint) -> ListZipper<E> {
+  assert(position <= items.len());
+  let mut hd = @Nil;
+  for uint::range(0, position) |i| {
+    hd = @Cons(items[i], hd);
+  }
+  ListZipper { hd: hd, tail: list::from_vec(vec::slice(items, position, items.len())) }
+}
+
 // this needs to be given back to the list module
 pure fn iter<T>(l: @List<T>, f: fn(&T)) {
     let mut cur = l;
@@ -109,6 +119,11 @@ impl<E: Copy> ListZipper<E> {
     }
   }
 
+  // how many items lie before the gap; the cursor is the last of them.
+  pure fn position() -> uint {
+    list::len(self.hd)
+  }
+
   pure fn cursor() -> Option<E> {
     match self {
       ListZipper { hd:@Nil, _ } => None,
@@ -238,3 +253,12 @@ fn test_from_vec() {
   assert(zipper.to_vec() == ~[1, 2, 5]);
 }
 
+#[test]
+fn test_from_vec_at() {
+  let zipper = ListZipper_from_vec_at(~[1, 2, 3, 4], 2);
+  assert(zipper.position() == 2);
+  assert(zipper.cursor() == Some(2));
+  assert(zipper.to_vec() == ~[1, 2, 3, 4]);
+  assert(zipper.go_right().cursor() == Some(3));
+  assert(ListZipper_from_vec_at(~[1, 2], 0).at_begin());
+}
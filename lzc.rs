@@ -26,4 +26,42 @@ fn test_lzc32_1() {
   assert(lzc32(0xFFFFFF) == 8);
   assert(lzc32(0x7FFFFFFF) == 1);
   assert(lzc32(0xFFFFFFFF) == 0);
-}
\ No newline at end of file
+}
+
+/**
+ * Counts the number of leading zero bits in an unsigned 64-bit integer.
+ */
+pure fn lzc64(m: u64) -> u32 {
+  let hi = (m >> 32) as u32;
+  if hi != 0 {
+    lzc32(hi)
+  } else {
+    32 + lzc32(m as u32)
+  }
+}
+
+#[test]
+fn test_lzc64() {
+  assert(lzc64(0x0) == 64);
+  assert(lzc64(0x1) == 63);
+  assert(lzc64(0xFFFFFFFF) == 32);
+  assert(lzc64(0x100000000) == 31);
+  assert(lzc64(0xFFFFFFFFFFFFFFFF) == 0);
+}
+
+/**
+ * Counts the number of trailing zero bits in an unsigned 32-bit integer.
+ * The bits below the lowest set bit are the only ones set in !x & (x - 1).
+ */
+pure fn tzc32(m: u32) -> u32 {
+  ctpop32(!m & (m - 1))
+}
+
+#[test]
+fn test_tzc32() {
+  assert(tzc32(0x0) == 32);
+  assert(tzc32(0x1) == 0);
+  assert(tzc32(0x8) == 3);
+  assert(tzc32(0xFF00) == 8);
+  assert(tzc32(0x80000000) == 31);
+}
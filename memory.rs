@@ -0,0 +1,261 @@
+/**
+ * Memory accounting for the persistent structures.
+ *
+ * Sizes are the bytes of the managed boxes a structure is built from,
+ * each box counted once: its header plus the node inside. Anything the
+ * keys, values or elements own themselves (the bytes of a ~str, say) is
+ * not followed, so for such types these are lower bounds.
+ */
+use core::cmp::{Eq, Ord};
+use std::list;
+use std::list::{List, Cons, Nil};
+use tree::{Tree, Tree_, Empty_};
+use red_black_tree::{RBMap, Leaf, RBMap_empty};
+use pairing_heap::{PairingHeap, HeapNode, PairingHeapCell, Comparator};
+use list_zipper::ListZipper;
+use persistent_map::*;
+
+pub trait HeapSize {
+  // bytes of managed boxes reachable from this value.
+  fn heap_size() -> uint;
+}
+
+// a managed box holding a T: reference count, type descriptor and the two
+// links of the task's box list, followed by the T.
+pure fn box_size<T>() -> uint {
+  4 * sys::size_of::<uint>() + sys::size_of::<T>()
+}
+
+/**
+ * How much a collection of versions of one structure share.
+ *
+ * `logical_nodes` is what the versions would hold between them if each
+ * were a separate copy; `unique_nodes` is what they actually hold.
+ */
+pub struct SharingStats {
+  versions: uint,
+  unique_nodes: uint,
+  logical_nodes: uint,
+  // nodes on the longest path from any version's root.
+  max_depth: uint,
+  // bytes of all the distinct boxes, empty subtrees included.
+  heap_size: uint
+}
+
+impl SharingStats {
+  // logical nodes per unique node: 1.0 for no sharing at all.
+  pure fn sharing_ratio() -> f64 {
+    if self.unique_nodes == 0 {
+      1.0
+    } else {
+      (self.logical_nodes as f64) / (self.unique_nodes as f64)
+    }
+  }
+}
+
+/**
+ * A walk over the nodes reachable from several roots that visits each
+ * distinct box once, remembering the node count and height of every
+ * subtree under it. Versions that share most of their nodes are walked in
+ * time proportional to their unique nodes, not their logical ones.
+ */
+struct SharingWalk {
+  // box address to (nodes, height) of the subtree there.
+  mut seen: @RBMap<uint, (uint, uint)>,
+  mut unique_nodes: uint,
+  mut heap_size: uint
+}
+
+pure fn SharingWalk() -> SharingWalk {
+  SharingWalk { seen: RBMap_empty(), unique_nodes: 0, heap_size: 0 }
+}
+
+impl SharingWalk {
+  fn rbmap<K: Copy Eq Ord, V: Copy>(t: @RBMap<K, V>) -> (uint, uint) {
+    let addr = ptr::to_uint(ptr::addr_of(&*t));
+    match self.seen.get(addr) {
+      Some(known) => return known,
+      None => ()
+    }
+
+    self.heap_size += box_size::<RBMap<K, V>>();
+    let shape = match t {
+      @Leaf => (0, 0),
+      @Tree(_, left, _, _, right, _) => {
+        let (ln, lh) = self.rbmap(left);
+        let (rn, rh) = self.rbmap(right);
+        self.unique_nodes += 1;
+        (ln + rn + 1, uint::max(lh, rh) + 1)
+      }
+    };
+    self.seen = self.seen.put(addr, shape);
+    shape
+  }
+
+  fn tree<K: Copy Eq Ord, V: Copy>(t: Tree<K, V>) -> (uint, uint) {
+    match t {
+      // empty trees aren't boxed.
+      Empty_ => (0, 0),
+      Tree_(node) => {
+        let addr = ptr::to_uint(ptr::addr_of(&*node));
+        match self.seen.get(addr) {
+          Some(known) => return known,
+          None => ()
+        }
+
+        let (ln, lh) = self.tree(node.left);
+        let (rn, rh) = self.tree(node.right);
+        self.unique_nodes += 1;
+        self.heap_size += box_size::<{key: K, value: V, left: Tree<K, V>, right: Tree<K, V>, size: uint}>();
+        let shape = (ln + rn + 1, uint::max(lh, rh) + 1);
+        self.seen = self.seen.put(addr, shape);
+        shape
+      }
+    }
+  }
+}
+
+fn rbmap_sharing_stats<K: Copy Eq Ord, V: Copy>(versions: &[@RBMap<K, V>]) -> SharingStats {
+  let walk = SharingWalk();
+  let shapes = vec::map(versions, |v| walk.rbmap(*v));
+  stats_of(&walk, shapes)
+}
+
+fn tree_sharing_stats<K: Copy Eq Ord, V: Copy>(versions: &[Tree<K, V>]) -> SharingStats {
+  let walk = SharingWalk();
+  let shapes = vec::map(versions, |v| walk.tree(*v));
+  stats_of(&walk, shapes)
+}
+
+pure fn stats_of(walk: &SharingWalk, shapes: &[(uint, uint)]) -> SharingStats {
+  let mut logical_nodes = 0;
+  let mut max_depth = 0;
+  for vec::each(shapes) |shape| {
+    let (nodes, height) = *shape;
+    logical_nodes += nodes;
+    max_depth = uint::max(max_depth, height);
+  }
+  SharingStats {
+    versions: shapes.len(),
+    unique_nodes: walk.unique_nodes,
+    logical_nodes: logical_nodes,
+    max_depth: max_depth,
+    heap_size: walk.heap_size
+  }
+}
+
+impl<K: Copy Eq Ord, V: Copy> @RBMap<K, V> : HeapSize {
+  fn heap_size() -> uint {
+    rbmap_sharing_stats(~[self]).heap_size
+  }
+}
+
+impl<K: Copy Eq Ord, V: Copy> Tree<K, V> : HeapSize {
+  fn heap_size() -> uint {
+    tree_sharing_stats(~[self]).heap_size
+  }
+}
+
+// a heap never shares cells within itself, so no need to remember them.
+impl<E: Copy, C: Copy Comparator<E>> PairingHeap<E, C> : HeapSize {
+  fn heap_size() -> uint {
+    let mut size = 0;
+    let mut stack = ~[];
+    match self.root {
+      PairingHeapCell(_, child, sibling) => {
+        stack.push(child);
+        stack.push(sibling);
+      }
+      _ => ()
+    }
+    while stack.len() > 0 {
+      let cell = stack.pop();
+      size += box_size::<HeapNode<E>>();
+      match *cell {
+        PairingHeapCell(_, child, sibling) => {
+          stack.push(child);
+          stack.push(sibling);
+        }
+        _ => ()
+      }
+    }
+    size
+  }
+}
+
+impl<E: Copy> ListZipper<E> : HeapSize {
+  fn heap_size() -> uint {
+    // both lists end in a boxed Nil of their own.
+    (list::len(self.hd) + list::len(self.tail) + 2) * box_size::<List<E>>()
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use tree;
+  use tree::Tree;
+  use red_black_tree::{RBMap, RBMap_empty};
+  use pairing_heap::{PairingHeap, MinOrder, Empty, Heap};
+  use list_zipper::ListZipper_from_vec;
+  use persistent_map::*;
+
+  #[test]
+  fn test_sharing_stats() {
+    let mut base = RBMap_empty();
+    for uint::range(0, 1024) |i| {
+      base = base.put(i, i);
+    }
+    let alone = rbmap_sharing_stats(~[base]);
+    assert(alone.unique_nodes == 1024);
+    assert(alone.logical_nodes == 1024);
+    assert(alone.sharing_ratio() == 1.0);
+    // 1024 keys need at least 11 levels, and a red-black tree never goes
+    // past twice that.
+    assert(alone.max_depth >= 11 && alone.max_depth <= 2 * 11);
+    assert(alone.heap_size == base.heap_size());
+
+    let mut versions = ~[base];
+    for uint::range(0, 10) |i| {
+      versions.push(versions[i].put(i * 100, 0));
+    }
+    let shared = rbmap_sharing_stats(versions);
+    assert(shared.versions == 11);
+    assert(shared.logical_nodes == 11 * 1024);
+    // each new version copies one path, plus a node or two for rebalancing.
+    assert(shared.unique_nodes <= 1024 + 10 * (alone.max_depth + 2));
+    assert(shared.sharing_ratio() > 5.0);
+    assert(shared.heap_size < 2 * alone.heap_size);
+
+    assert(rbmap_sharing_stats::<uint, uint>(~[]).sharing_ratio() == 1.0);
+  }
+
+  #[test]
+  fn test_tree_stats() {
+    let mut t = tree::Empty();
+    for vec::each(~[4, 2, 6, 1, 3, 5, 7]) |k| {
+      t = t.insert(*k, ());
+    }
+    let t2 = t.insert(8, ());
+    let stats = tree_sharing_stats(~[t, t2]);
+    assert(stats.logical_nodes == 15);
+    // t2 copies the path 4, 6, 7 and adds 8.
+    assert(stats.unique_nodes == 11);
+    assert(stats.max_depth == 4);
+    assert(t.heap_size() * 11 == stats.heap_size * 7);
+  }
+
+  #[test]
+  fn test_heap_and_zipper_sizes() {
+    let empty : PairingHeap<int, MinOrder> = Empty();
+    let one = empty.insert(1);
+    let two = one.insert(2);
+    assert(empty.heap_size() == 0);
+    assert(two.heap_size() > one.heap_size());
+
+    let z = ListZipper_from_vec(~[1, 2, 3]).go_right();
+    let z2 = z.go_right();
+    assert(z.heap_size() == z2.heap_size());
+    assert(ListZipper_from_vec(~[1, 2, 3, 4]).heap_size() > z.heap_size());
+  }
+}
@@ -0,0 +1,182 @@
+use core::cmp::{Eq, Ord};
+use std::list;
+use std::list::{List, Cons, Nil};
+use red_black_tree::{RBMap, RBMap_empty};
+use persistent_map::PersistentMap;
+
+/**
+ * A purely functional map from each key to any number of values, built on
+ * RBMap. Every operation returns a new version and leaves the old one
+ * valid.
+ *
+ * The values under a key are kept in a cons list, most recently inserted
+ * first.
+ */
+pub struct RBMultiMap<K: Copy Eq Ord, V: Copy Eq> {
+  map: @RBMap<K, @List<V>>,
+  size: uint
+}
+
+pure fn RBMultiMap<K: Copy Eq Ord, V: Copy Eq>() -> RBMultiMap<K, V> {
+  RBMultiMap { map: RBMap_empty(), size: 0 }
+}
+
+// returns `values` without its first occurrence of `v`, or None if `v` isn't there.
+pure fn remove_first<V: Copy Eq>(values: @List<V>, v: &V) -> Option<@List<V>> {
+  match values {
+    @Nil => None,
+    @Cons(x, rest) => {
+      if (x == *v) {
+        Some(rest)
+      } else {
+        match remove_first(rest, v) {
+          Some(remaining) => Some(@Cons(x, remaining)),
+          None => None
+        }
+      }
+    }
+  }
+}
+
+impl<K: Copy Eq Ord, V: Copy Eq> RBMultiMap<K, V> {
+  pure fn insert(k: K, v: V) -> RBMultiMap<K, V> {
+    let values = match self.map.get(k) {
+      Some(values) => values,
+      None => @Nil
+    };
+    RBMultiMap { map: self.map.put(k, @Cons(v, values)), size: self.size + 1 }
+  }
+
+  // removes the most recently inserted occurrence of `v` under `k`.
+  pure fn remove_one(k: K, v: V) -> RBMultiMap<K, V> {
+    match self.map.get(k) {
+      None => self,
+      Some(values) => {
+        match remove_first(values, &v) {
+          None => self,
+          Some(@Nil) => RBMultiMap { map: self.map.delete(k), size: self.size - 1 },
+          Some(remaining) => RBMultiMap { map: self.map.put(k, remaining), size: self.size - 1 }
+        }
+      }
+    }
+  }
+
+  pure fn remove_all(k: K) -> RBMultiMap<K, V> {
+    let n = self.count(k);
+    if (n == 0) {
+      self
+    } else {
+      RBMultiMap { map: self.map.delete(k), size: self.size - n }
+    }
+  }
+
+  // visits every value stored under `k`, most recently inserted first.
+  pure fn get_all(k: K, f: fn(&V) -> bool) {
+    match self.map.get(k) {
+      Some(values) => {
+        let mut cur = values;
+        loop {
+          match cur {
+            @Cons(ref v, rest) => {
+              if (!f(v)) { break; }
+              cur = rest;
+            }
+            @Nil => break
+          }
+        }
+      }
+      None => ()
+    }
+  }
+
+  pure fn count(k: K) -> uint {
+    match self.map.get(k) {
+      Some(values) => list::len(values),
+      None => 0
+    }
+  }
+
+  pure fn contains_key(k: K) -> bool {
+    self.map.get(k).is_some()
+  }
+
+  // total number of values across all keys.
+  pure fn len() -> uint {
+    self.size
+  }
+
+  pure fn is_empty() -> bool {
+    self.size == 0
+  }
+
+  // visits every (key, value) pair in ascending key order.
+  pure fn each(f: fn(&K, &V) -> bool) {
+    do self.map.traverse |k, values| {
+      let mut cur = *values;
+      let mut go_on = true;
+      loop {
+        match cur {
+          @Cons(ref v, rest) => {
+            if (!f(k, v)) { go_on = false; break; }
+            cur = rest;
+          }
+          @Nil => break
+        }
+      }
+      go_on
+    };
+  }
+}
+
+
+#[test]
+fn test_insert_and_get_all() {
+  let v1 = RBMultiMap().insert("stevej", 1).insert("thatstacy", 2).insert("stevej", 3);
+
+  let mut values : ~[int] = ~[];
+  for v1.get_all("stevej") |v| {
+    values.push(*v);
+  }
+
+  assert(values == ~[3, 1]);
+  assert(v1.count("stevej") == 2);
+  assert(v1.count("thatstacy") == 1);
+  assert(v1.count("jeremy") == 0);
+  assert(v1.len() == 3);
+}
+
+#[test]
+fn test_remove_one_and_all() {
+  let v1 = RBMultiMap().insert(1, 10).insert(1, 20).insert(1, 10).insert(2, 30);
+
+  let v2 = v1.remove_one(1, 10);
+  assert(v2.count(1) == 2);
+  assert(v2.len() == 3);
+
+  let v3 = v2.remove_one(1, 99);
+  assert(v3.len() == 3);
+
+  let v4 = v3.remove_all(1);
+  assert(!v4.contains_key(1));
+  assert(v4.len() == 1);
+
+  let v5 = v4.remove_one(2, 30);
+  assert(v5.is_empty());
+  assert(!v5.contains_key(2));
+
+  // older versions are untouched.
+  assert(v1.count(1) == 3);
+  assert(v2.count(1) == 2);
+}
+
+#[test]
+fn test_ordered_traversal() {
+  let mm = RBMultiMap().insert(3, 'c').insert(1, 'a').insert(2, 'b').insert(1, 'z');
+
+  let mut pairs : ~[(int, char)] = ~[];
+  for mm.each |k, v| {
+    pairs.push((*k, *v));
+  }
+
+  assert(pairs == ~[(1, 'z'), (1, 'a'), (2, 'b'), (3, 'c')]);
+}
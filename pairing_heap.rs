@@ -1,119 +1,318 @@
 use core::cmp::{Eq, Ord};
-use std::list;
-use std::list::{List, Cons, Nil};
+use transient::TransientHeap;
 
 /**
  * A purely functional Pairing Heap [FSST86]
  *
- * Our implementation uses Linked List (cons cells) so may not be the
- * fastest way to implement this in Rust.
+ * Children are kept in left-child, right-sibling form: each cell points at
+ * its first child and at its next sibling, so a node's children need no
+ * separate list and merge_pairs can walk them with a loop instead of
+ * recursing once per pair.
  *
  * This implementation is a port of the Standard ML found in Okasaki's
  * Purely Functional Data Structures.
+ *
+ * The order elements leave the heap in is decided by a Comparator, so the
+ * same heap serves as a min-heap, a max-heap or a by-key priority queue.
  */
-#[deriving_eq]
-pub enum PairingHeap<E: Copy Eq Ord> {
+pub enum HeapNode<E: Copy> {
   Empty_,
-  PairingHeapCell(
-    E,
-    @List<PairingHeap<E>>
-  )
+  //              elem first child    next sibling
+  PairingHeapCell(E,   @HeapNode<E>,  @HeapNode<E>)
+}
+
+pub struct PairingHeap<E: Copy, C: Copy Comparator<E>> {
+  cmp: C,
+  root: HeapNode<E>
+}
+
+/**
+ * Decides which of two elements leaves the heap first. `le(a, b)` must
+ * return true if `a` may come out no later than `b`.
+ */
+pub trait Comparator<E> {
+  pure fn le(a: &E, b: &E) -> bool;
+}
+
+// smallest element first, using E's natural ordering.
+pub enum MinOrder {
+  MinFirst
+}
+
+// largest element first, using E's natural ordering.
+pub enum MaxOrder {
+  MaxFirst
+}
+
+// flips the ordering of another Comparator.
+pub struct Reverse<C> {
+  inner: C
+}
+
+// orders elements by a key extracted from them, smallest key first.
+pub struct KeyOrder<E, K> {
+  key: @pure fn(&E) -> K
+}
+
+impl<E: Ord> MinOrder : Comparator<E> {
+  pure fn le(a: &E, b: &E) -> bool {
+    (*a).le(b)
+  }
+}
+
+impl<E: Ord> MaxOrder : Comparator<E> {
+  pure fn le(a: &E, b: &E) -> bool {
+    (*b).le(a)
+  }
+}
+
+impl<E, C: Comparator<E>> Reverse<C> : Comparator<E> {
+  pure fn le(a: &E, b: &E) -> bool {
+    self.inner.le(b, a)
+  }
+}
+
+impl<E, K: Ord> KeyOrder<E, K> : Comparator<E> {
+  pure fn le(a: &E, b: &E) -> bool {
+    (self.key)(a).le(&(self.key)(b))
+  }
+}
+
+pure fn Reverse<C>(inner: C) -> Reverse<C> {
+  Reverse { inner: inner }
+}
+
+pure fn KeyOrder<E, K: Ord>(key: @pure fn(&E) -> K) -> KeyOrder<E, K> {
+  KeyOrder { key: key }
 }
 
-pub trait Heap<E: Copy Eq Ord> {
+pub trait Heap<E: Copy> {
   // returns true if the Heap is empty.
   pure fn is_empty(&self) -> bool;
 
   // returns a new Heap with the element inserted.
   pure fn insert(elem: E) -> self;
 
-  // returns the minimum element without a modified heap
-  pure fn find_min() -> Option<E>;
+  // returns the first element under the Heap's ordering without
+  // modifying the heap.
+  pure fn peek() -> Option<E>;
+
+  // returns the first element and a new Heap without that element.
+  pure fn pop() -> (Option<E>, self);
+}
+
 
-  // returns the minimum element and a new Heap without that element.
-  pure fn delete_min() -> (Option<E>, self);
+pure fn PairingHeap_with_comparator<E: Copy, C: Copy Comparator<E>>(cmp: C) -> PairingHeap<E, C> {
+  PairingHeap { cmp: cmp, root: Empty_ }
 }
 
+pure fn Empty<E: Copy Ord>() -> PairingHeap<E, MinOrder> {
+  PairingHeap_with_comparator(MinFirst)
+}
 
-pure fn Empty<E: Copy Eq Ord>() -> PairingHeap<E> {
-  Empty_
+pure fn PairingHeap<E: Copy Ord>(initial_value: E) -> PairingHeap<E, MinOrder> {
+  PairingHeap {
+    cmp: MinFirst,
+    root: PairingHeapCell(initial_value, @Empty_, @Empty_)
+  }
 }
 
-pure fn PairingHeap<E: Copy Eq Ord>(initial_value: E) -> PairingHeap<E> {
-  PairingHeapCell(
-    initial_value,
-    @Nil
-  )
+pure fn MaxHeap<E: Copy Ord>() -> PairingHeap<E, MaxOrder> {
+  PairingHeap_with_comparator(MaxFirst)
 }
 
-/*
-impl<E: Copy Eq Ord> PairingHeap<E> : Eq {
-  pure fn eq(other: &PairingHeap<E>) -> bool {
-    match (self, *other) {
-      (Empty_, Empty_) => {true}
-      (PairingHeapCell(headA, restA), PairingHeapCell(headB, restB)) => {
-        (headA == headB) && (restA == restB)
+// links two heaps, ignoring whatever siblings their roots had.
+pure fn merge_nodes<E: Copy, C: Comparator<E>>(cmp: &C, a: HeapNode<E>, b: HeapNode<E>) -> HeapNode<E> {
+  match (a, b) {
+    (Empty_, b) => { b }
+    (a, Empty_) => { a }
+    (PairingHeapCell(headA, childA, _), PairingHeapCell(headB, childB, _)) => {
+      if (cmp.le(&headA, &headB)) {
+        PairingHeapCell(
+          headA,
+          @PairingHeapCell(headB, childB, childA),
+          @Empty_
+        )
+      } else {
+        PairingHeapCell(
+          headB,
+          @PairingHeapCell(headA, childA, childB),
+          @Empty_
+        )
       }
-      //(Empty_, PairingHeapCell(_)) => {false} // why are these unreachable?
-      //(PairingHeapCell(_), Empty) => {false}
-      (_, _) => {false}
     }
   }
+}
 
-  pure fn ne(other: &PairingHeap<E>) -> bool { !(self).eq(other) }
-}*/
-
-impl<E: Copy Eq Ord> PairingHeap<E> {
-  pure fn merge(other: PairingHeap<E>) -> PairingHeap<E> {
-    match (self, other) {
-      (Empty_, b) => { b }
-      (a, Empty_) => { a }
-      (x@PairingHeapCell(headA, restA), y@PairingHeapCell(headB, restB)) => {
-        if (headA.le(&headB)) {
-          PairingHeapCell(
-            headA,
-            @Cons(y, restA)
-          )
-        } else {
-          PairingHeapCell(
-            headB,
-            @Cons(x, restB)
-          )
+/**
+ * The two-pass pairing: merge the siblings starting at `first` in pairs
+ * from left to right, then fold the pairs together from right to left.
+ * Both passes are loops, so a root with a million children is fine.
+ */
+pure fn merge_pairs<E: Copy, C: Comparator<E>>(cmp: &C, first: @HeapNode<E>) -> HeapNode<E> {
+  let mut pairs : ~[HeapNode<E>] = ~[];
+  let mut cur = first;
+
+  loop {
+    match *cur {
+      Empty_ => break,
+      PairingHeapCell(_, _, next) => {
+        match *next {
+          Empty_ => {
+            pairs.push(*cur);
+            break;
+          }
+          PairingHeapCell(_, _, rest) => {
+            pairs.push(merge_nodes(cmp, *cur, *next));
+            cur = rest;
+          }
         }
       }
     }
   }
 
-  pure fn merge_pairs(heaps: @List<PairingHeap<E>>) -> PairingHeap<E> {
-    match heaps {
-      @Cons(a, @Cons(b, xs)) => {a.merge(b).merge(self.merge_pairs(xs))}
-      @Cons(elem, @Nil) => {elem}
-      @Nil => {Empty()}
+  let mut result = Empty_;
+  while pairs.len() > 0 {
+    result = merge_nodes(cmp, pairs.pop(), result);
+  }
+
+  return result;
+}
+
+impl<E: Copy, C: Copy Comparator<E>> PairingHeap<E, C> {
+  pure fn merge(other: PairingHeap<E, C>) -> PairingHeap<E, C> {
+    PairingHeap {
+      cmp: self.cmp,
+      root: merge_nodes(&self.cmp, self.root, other.root)
+    }
+  }
+
+  // visits every element once, in no particular order, without popping.
+  pure fn each_unordered(f: fn(&E) -> bool) {
+    let mut stack = ~[@self.root];
+    while stack.len() > 0 {
+      match *stack.pop() {
+        Empty_ => (),
+        PairingHeapCell(ref elem, child, sibling) => {
+          if !f(elem) {
+            return;
+          }
+          stack.push(sibling);
+          stack.push(child);
+        }
+      }
     }
   }
 
+  // a mutable builder for a batch of inserts, starting from this heap.
+  pure fn transient() -> TransientHeap<E, C> {
+    TransientHeap(self)
+  }
 }
 
-impl<E: Copy Eq Ord> PairingHeap<E> : Heap<E> {
+impl<E: Copy, C: Copy Comparator<E>> PairingHeap<E, C> : Heap<E> {
   pure fn is_empty(&self) -> bool {
-    *self == Empty_
+    match self.root {
+      Empty_ => { true }
+      PairingHeapCell(*) => { false }
+    }
   }
 
-  pure fn insert(e: E) -> PairingHeap<E> {
-    self.merge(PairingHeap(e))
+  pure fn insert(e: E) -> PairingHeap<E, C> {
+    PairingHeap {
+      cmp: self.cmp,
+      root: merge_nodes(&self.cmp, self.root, PairingHeapCell(e, @Empty_, @Empty_))
+    }
   }
 
-  pure fn find_min() -> Option<E> {
-    match self {
+  pure fn peek() -> Option<E> {
+    match self.root {
       Empty_ => { None }
-      PairingHeapCell(head, _) => { Some(head) }
+      PairingHeapCell(head, _, _) => { Some(head) }
     }
   }
 
-  pure fn delete_min() -> (Option<E>, PairingHeap<E>) {
-    match self {
+  pure fn pop() -> (Option<E>, PairingHeap<E, C>) {
+    match self.root {
       Empty_ => {(None, self)}
-      PairingHeapCell(head, rest) => {(Some(head), self.merge_pairs(rest))}
+      PairingHeapCell(head, children, _) => {
+        (Some(head), PairingHeap { cmp: self.cmp, root: merge_pairs(&self.cmp, children) })
+      }
+    }
+  }
+}
+
+
+// an element tagged with the position it was inserted at.
+pub struct Stamped<E> {
+  elem: E,
+  seq: u64
+}
+
+// orders by another Comparator, breaking ties by insertion order.
+pub struct StableOrder<C> {
+  inner: C
+}
+
+impl<E, C: Comparator<E>> StableOrder<C> : Comparator<Stamped<E>> {
+  pure fn le(a: &Stamped<E>, b: &Stamped<E>) -> bool {
+    let a_first = self.inner.le(&a.elem, &b.elem);
+    let b_first = self.inner.le(&b.elem, &a.elem);
+
+    if (a_first && b_first) {
+      a.seq <= b.seq
+    } else {
+      a_first
+    }
+  }
+}
+
+/**
+ * A PairingHeap where elements that compare equal leave in the order they
+ * were inserted. Each insert is stamped with a sequence number kept by the
+ * heap itself, so E doesn't need to carry one.
+ */
+pub struct StableHeap<E: Copy, C: Copy Comparator<E>> {
+  heap: PairingHeap<Stamped<E>, StableOrder<C>>,
+  next_seq: u64
+}
+
+pure fn StableHeap_with_comparator<E: Copy, C: Copy Comparator<E>>(cmp: C) -> StableHeap<E, C> {
+  StableHeap {
+    heap: PairingHeap_with_comparator(StableOrder { inner: cmp }),
+    next_seq: 0
+  }
+}
+
+pure fn StableHeap<E: Copy Ord>() -> StableHeap<E, MinOrder> {
+  StableHeap_with_comparator(MinFirst)
+}
+
+impl<E: Copy, C: Copy Comparator<E>> StableHeap<E, C> : Heap<E> {
+  pure fn is_empty(&self) -> bool {
+    self.heap.is_empty()
+  }
+
+  pure fn insert(e: E) -> StableHeap<E, C> {
+    StableHeap {
+      heap: self.heap.insert(Stamped { elem: e, seq: self.next_seq }),
+      next_seq: self.next_seq + 1
+    }
+  }
+
+  pure fn peek() -> Option<E> {
+    match self.heap.peek() {
+      Some(stamped) => { Some(stamped.elem) }
+      None => { None }
+    }
+  }
+
+  pure fn pop() -> (Option<E>, StableHeap<E, C>) {
+    match self.heap.pop() {
+      (Some(stamped), rest) => {
+        (Some(stamped.elem), StableHeap { heap: rest, next_seq: self.next_seq })
+      }
+      (None, _) => { (None, self) }
     }
   }
 }
@@ -125,7 +324,7 @@ fn test_heap_create() {
   assert(!heap.is_empty());
 
   // inference fails on this without a type declaration.
-  let heap : PairingHeap<()> = Empty();
+  let heap : PairingHeap<(), MinOrder> = Empty();
   assert(heap.is_empty());
 }
 
@@ -134,7 +333,7 @@ fn test_delete_last_item() {
   let v1 = PairingHeap(1);
   assert(!v1.is_empty());
 
-  let (_, v2) = v1.delete_min();
+  let (_, v2) = v1.pop();
   assert(v2.is_empty());
 }
 
@@ -142,52 +341,52 @@ fn test_delete_last_item() {
 fn test_heap_insert() {
   let v1 = PairingHeap(10);
   let v2 = v1.insert(1);
-  let (one, v3) = v2.delete_min();
-  let (ten, v4) = v3.delete_min();
-  let (e, v5) = v4.delete_min();
+  let (one, v3) = v2.pop();
+  let (ten, v4) = v3.pop();
+  let (e, v5) = v4.pop();
 
   assert(one == Some(1));
   assert(ten == Some(10));
-  assert(v4 == Empty_);
+  assert(v4.is_empty());
   assert(e == None);
-  assert(v5 == Empty_);
+  assert(v5.is_empty());
 }
 
 #[test]
 fn test_heap_insert_delete_interleaved() {
   let v1 = PairingHeap(10);
-  let (a, v2) = v1.delete_min();
+  let (a, v2) = v1.pop();
   assert(a == Some(10));
-  assert(v2 == Empty_);
+  assert(v2.is_empty());
 
   let v3 = v2.insert(9);
   let v4 = v3.insert(8);
   let v5 = v4.insert(11);
 
-  let (b, v6) = v5.delete_min();
+  let (b, v6) = v5.pop();
   assert(b == Some(8));
 
   let v7 = v6.insert(7);
   let v8 = v7.insert(12);
 
-  let x = v8.find_min();
+  let x = v8.peek();
   assert(x == Some(7));
 
-  let (c, v9) = v8.delete_min();
+  let (c, v9) = v8.pop();
   assert(c == Some(7));
 
-  let (d, v10) = v9.delete_min();
+  let (d, v10) = v9.pop();
   assert(d == Some(9));
 
-  let (e, v11) = v10.delete_min();
+  let (e, v11) = v10.pop();
   assert(e == Some(11));
 
-  let (f, v12) = v11.delete_min();
+  let (f, v12) = v11.pop();
   assert(f == Some(12));
 
-  let (g, v13) = v12.delete_min();
+  let (g, v13) = v12.pop();
   assert(g == None);
-  assert(v13 == Empty_);
+  assert(v13.is_empty());
 }
 
 #[test]
@@ -195,15 +394,111 @@ fn test_immutable_heap() {
   let heap = PairingHeap(10);
   let x1 = heap.insert(1);
 
-  assert(x1.find_min() == Some(1));
-  assert(heap.find_min() == Some(10));
+  assert(x1.peek() == Some(1));
+  assert(heap.peek() == Some(10));
 
-  let (a, v1) = heap.delete_min();
+  let (a, v1) = heap.pop();
   assert(a == Some(10));
-  assert(v1 == Empty_);
+  assert(v1.is_empty());
 
-  let (b, x2) = x1.delete_min();
+  let (b, x2) = x1.pop();
   assert(b == Some(1));
-  assert(x2 != Empty_);
+  assert(!x2.is_empty());
+}
+
+#[test]
+fn test_max_heap() {
+  let v1 = MaxHeap().insert(3).insert(10).insert(1).insert(7);
+
+  let (a, v2) = v1.pop();
+  let (b, v3) = v2.pop();
+  let (c, v4) = v3.pop();
+  let (d, v5) = v4.pop();
+
+  assert(a == Some(10));
+  assert(b == Some(7));
+  assert(c == Some(3));
+  assert(d == Some(1));
+  assert(v5.is_empty());
+}
+
+#[test]
+fn test_reverse_matches_max_heap() {
+  let v1 = PairingHeap_with_comparator(Reverse(MinFirst));
+  let v2 = v1.insert(2).insert(5).insert(4);
+
+  assert(v2.peek() == Some(5));
+  let (_, v3) = v2.pop();
+  assert(v3.peek() == Some(4));
+}
+
+#[test]
+fn test_key_order() {
+  pure fn second(pair: &(~str, int)) -> int {
+    match *pair { (_, n) => n }
+  }
+
+  let v1 = PairingHeap_with_comparator(KeyOrder(second));
+  let v2 = v1.insert((~"low", 1)).insert((~"high", 9)).insert((~"mid", 5));
+
+  let (a, v3) = v2.pop();
+  let (b, v4) = v3.pop();
+  let (c, _) = v4.pop();
+
+  assert(a == Some((~"low", 1)));
+  assert(b == Some((~"mid", 5)));
+  assert(c == Some((~"high", 9)));
+}
+
+#[test]
+fn test_drain_million_elements() {
+  let n = 1000000;
+  let mut heap = Empty();
+
+  // every insert becomes another child of the root.
+  for uint::range(0, n) |i| {
+    heap = heap.insert(i);
+  }
+
+  for uint::range(0, n) |i| {
+    let (x, rest) = heap.pop();
+    assert(x == Some(i));
+    heap = rest;
+  }
+
+  assert(heap.is_empty());
 }
 
+#[test]
+fn test_stable_heap_fifo_among_equals() {
+  pure fn priority(job: &(int, ~str)) -> int {
+    match *job { (p, _) => p }
+  }
+
+  let v1 = StableHeap_with_comparator(KeyOrder(priority));
+  let v2 = v1.insert((2, ~"first")).insert((1, ~"urgent")).insert((2, ~"second"));
+  let v3 = v2.insert((2, ~"third")).insert((1, ~"also urgent"));
+
+  let (a, v4) = v3.pop();
+  let (b, v5) = v4.pop();
+  let (c, v6) = v5.pop();
+  let (d, v7) = v6.pop();
+  let (e, v8) = v7.pop();
+
+  assert(a == Some((1, ~"urgent")));
+  assert(b == Some((1, ~"also urgent")));
+  assert(c == Some((2, ~"first")));
+  assert(d == Some((2, ~"second")));
+  assert(e == Some((2, ~"third")));
+  assert(v8.is_empty());
+}
+
+#[test]
+fn test_stable_heap_persistence() {
+  let v1 = StableHeap().insert(5).insert(5);
+  let (_, v2) = v1.pop();
+  let v3 = v2.insert(1);
+
+  assert(v1.peek() == Some(5));
+  assert(v3.peek() == Some(1));
+}
@@ -0,0 +1,55 @@
+use core::cmp::{Eq, Ord};
+
+/**
+ * A purely functional map. Every operation that changes the map returns a
+ * new version and leaves the old one valid.
+ */
+pub trait PersistentMap<K: Copy Eq Ord, V: Copy> {
+  pure fn get(k: K) -> Option<V>;
+  pure fn put(k: K, v: V) -> self;
+  pure fn delete(k: K) -> self;
+
+  // number of keys with a value.
+  pure fn len() -> uint;
+  pure fn is_empty() -> bool;
+  pure fn contains_key(k: K) -> bool;
+
+  // visit every entry, key or value until `f` returns false.
+  pure fn iter(f: fn(&K, &V) -> bool);
+  pure fn keys(f: fn(&K) -> bool);
+  pure fn values(f: fn(&V) -> bool);
+
+  // puts `v` under `k` and returns the value it replaced.
+  pure fn insert_returning_old(k: K, v: V) -> (Option<V>, self);
+
+  // descends to `k` once and lets `f` decide from the current value
+  // whether `k` is inserted, replaced (Some) or removed (None).
+  pure fn update(k: K, f: fn(Option<&V>) -> Option<V>) -> self;
+
+  // replaces the value under `k` with `f` of it; does nothing if `k` is absent.
+  pure fn alter(k: K, f: fn(&V) -> V) -> self;
+
+  // inserts `v`, or `f(old, v)` if `k` already has a value.
+  pure fn insert_with(k: K, v: V, f: fn(&V, &V) -> V) -> self;
+
+  // returns the value under `k`, inserting `f()` first if it is absent.
+  pure fn get_or_insert_with(k: K, f: fn() -> V) -> (V, self);
+}
+
+/**
+ * A PersistentMap that keeps its keys sorted. `iter`, `keys` and `values`
+ * visit entries in ascending key order.
+ */
+pub trait OrderedPersistentMap<K: Copy Eq Ord, V: Copy> : PersistentMap<K, V> {
+  pure fn min() -> Option<(K, V)>;
+  pure fn max() -> Option<(K, V)>;
+
+  // the entry with the greatest key <= k.
+  pure fn floor(k: K) -> Option<(K, V)>;
+
+  // the entry with the least key >= k.
+  pure fn ceiling(k: K) -> Option<(K, V)>;
+
+  // visits the entries with lo <= key < hi in ascending order.
+  pure fn range(lo: K, hi: K, f: fn(&K, &V) -> bool);
+}
@@ -0,0 +1,359 @@
+use ctpop::ctpop32;
+use vec_util::{insert_at, remove_at, replace_at};
+
+/**
+ * A node of a compressed radix tree. `prefix` is the label on the edge
+ * leading into the node. Which first bytes have a child is recorded in a
+ * 256-bit bitmap, and `children` holds only the children present, in
+ * byte order, so a child's slot is the number of set bits below its byte.
+ */
+pub struct RNode<V: Copy> {
+  prefix: ~[u8],
+  value: Option<V>,
+  bitmap: ~[u32],
+  children: ~[@RNode<V>]
+}
+
+/**
+ * A purely functional compressed radix tree (Patricia trie) over byte
+ * string keys. Chains of single-child nodes are collapsed into one edge,
+ * iteration is in lexicographic byte order, and prefix queries only visit
+ * the subtree under the prefix.
+ */
+pub struct RadixTree<V: Copy> {
+  root: @RNode<V>,
+  size: uint
+}
+
+pure fn RadixTree<V: Copy>() -> RadixTree<V> {
+  RadixTree { root: rnode(~[], None, empty_bitmap(), ~[]), size: 0 }
+}
+
+pure fn empty_bitmap() -> ~[u32] {
+  ~[0, 0, 0, 0, 0, 0, 0, 0]
+}
+
+pure fn rnode<V: Copy>(prefix: ~[u8], value: Option<V>, bitmap: ~[u32], children: ~[@RNode<V>]) -> @RNode<V> {
+  @RNode { prefix: prefix, value: value, bitmap: bitmap, children: children }
+}
+
+// the bit for byte b within its bitmap word.
+pure fn bit(b: u8) -> u32 {
+  1u32 << ((b & 31) as u32)
+}
+
+// the length of the longest common prefix of a and b[from..].
+pure fn common_prefix(a: &[u8], b: &[u8], from: uint) -> uint {
+  let mut n = 0;
+  while n < a.len() && from + n < b.len() && a[n] == b[from + n] {
+    n += 1;
+  }
+  n
+}
+
+impl<V: Copy> @RNode<V> {
+  pure fn has_child(b: u8) -> bool {
+    self.bitmap[(b >> 5) as uint] & bit(b) != 0
+  }
+
+  // the slot in `children` for a child starting with byte b.
+  pure fn slot(b: u8) -> uint {
+    let word = (b >> 5) as uint;
+    let mut n = 0;
+    for uint::range(0, word) |i| {
+      n += ctpop32(self.bitmap[i]);
+    }
+    (n + ctpop32(self.bitmap[word] & (bit(b) - 1))) as uint
+  }
+
+  pure fn child(b: u8) -> Option<@RNode<V>> {
+    if self.has_child(b) { Some(self.children[self.slot(b)]) } else { None }
+  }
+
+  pure fn with_prefix(prefix: ~[u8]) -> @RNode<V> {
+    rnode(prefix, self.value, copy self.bitmap, copy self.children)
+  }
+
+  pure fn with_value(value: Option<V>) -> @RNode<V> {
+    rnode(copy self.prefix, value, copy self.bitmap, copy self.children)
+  }
+
+  // replaces or adds the child whose prefix starts with its first byte.
+  pure fn with_child(child: @RNode<V>) -> @RNode<V> {
+    let b = child.prefix[0];
+    if self.has_child(b) {
+      rnode(copy self.prefix, self.value, copy self.bitmap, replace_at(self.children, self.slot(b), child))
+    } else {
+      let word = (b >> 5) as uint;
+      let bitmap = replace_at(self.bitmap, word, self.bitmap[word] | bit(b));
+      rnode(copy self.prefix, self.value, bitmap, insert_at(self.children, self.slot(b), child))
+    }
+  }
+
+  pure fn without_child(b: u8) -> @RNode<V> {
+    let word = (b >> 5) as uint;
+    let bitmap = replace_at(self.bitmap, word, self.bitmap[word] & !bit(b));
+    rnode(copy self.prefix, self.value, bitmap, remove_at(self.children, self.slot(b)))
+  }
+
+  // key[..pos] has been matched on the way down to this node.
+  pure fn insert(key: &[u8], pos: uint, v: V) -> (@RNode<V>, bool) {
+    if pos == key.len() {
+      return (self.with_value(Some(v)), self.value.is_none());
+    }
+
+    match self.child(key[pos]) {
+      None => {
+        let leaf = rnode(vec::slice(key, pos, key.len()), Some(v), empty_bitmap(), ~[]);
+        (self.with_child(leaf), true)
+      }
+      Some(child) => {
+        let common = common_prefix(child.prefix, key, pos);
+        if common == child.prefix.len() {
+          let (new_child, added) = child.insert(key, pos + common, v);
+          return (self.with_child(new_child), added);
+        }
+
+        // the key leaves the child's edge part way along: split the edge.
+        let lower = child.with_prefix(vec::slice(child.prefix, common, child.prefix.len()));
+        let mid = rnode(vec::slice(child.prefix, 0, common), None, empty_bitmap(), ~[]).with_child(lower);
+        let (mid, _) = mid.insert(key, pos + common, v);
+        (self.with_child(mid), true)
+      }
+    }
+  }
+
+  // the new node (None if nothing is left of it), or self if key wasn't there.
+  pure fn remove(key: &[u8], pos: uint) -> (Option<@RNode<V>>, bool) {
+    if pos == key.len() {
+      if self.value.is_none() {
+        return (Some(self), false);
+      }
+      return (self.with_value(None).compact(), true);
+    }
+
+    match self.child(key[pos]) {
+      None => (Some(self), false),
+      Some(child) => {
+        if common_prefix(child.prefix, key, pos) < child.prefix.len() {
+          return (Some(self), false);
+        }
+        match child.remove(key, pos + child.prefix.len()) {
+          (_, false) => (Some(self), false),
+          (Some(new_child), true) => (self.with_child(new_child).compact(), true),
+          (None, true) => (self.without_child(key[pos]).compact(), true)
+        }
+      }
+    }
+  }
+
+  // drops a valueless leaf, and folds a valueless node into its only child.
+  priv pure fn compact() -> Option<@RNode<V>> {
+    if self.value.is_some() || self.prefix.is_empty() {
+      return Some(self);
+    }
+    match self.children.len() {
+      0 => None,
+      1 => {
+        let only = self.children[0];
+        Some(only.with_prefix(self.prefix + only.prefix))
+      }
+      _ => Some(self)
+    }
+  }
+
+  // in-order walk; `path` is the key up to and including this node's prefix.
+  pure fn traverse(path: &[u8], f: fn(&[u8], &V) -> bool) -> bool {
+    match self.value {
+      Some(ref v) => if !f(path, v) { return false; },
+      None => ()
+    }
+    for vec::each(self.children) |child| {
+      if !child.traverse(path + child.prefix, f) {
+        return false;
+      }
+    }
+    true
+  }
+}
+
+impl<V: Copy> RadixTree<V> {
+  pure fn len() -> uint {
+    self.size
+  }
+
+  pure fn is_empty() -> bool {
+    self.size == 0
+  }
+
+  pure fn get(key: &[u8]) -> Option<V> {
+    let mut node = self.root;
+    let mut pos = 0;
+    loop {
+      if pos == key.len() {
+        return node.value;
+      }
+      match node.child(key[pos]) {
+        None => return None,
+        Some(child) => {
+          if common_prefix(child.prefix, key, pos) < child.prefix.len() {
+            return None;
+          }
+          pos += child.prefix.len();
+          node = child;
+        }
+      }
+    }
+  }
+
+  pure fn contains_key(key: &[u8]) -> bool {
+    self.get(key).is_some()
+  }
+
+  pure fn insert(key: &[u8], v: V) -> RadixTree<V> {
+    let (root, added) = self.root.insert(key, 0, v);
+    RadixTree { root: root, size: if added { self.size + 1 } else { self.size } }
+  }
+
+  pure fn remove(key: &[u8]) -> RadixTree<V> {
+    match self.root.remove(key, 0) {
+      (Some(root), true) => RadixTree { root: root, size: self.size - 1 },
+      _ => self
+    }
+  }
+
+  // visits every entry in lexicographic key order.
+  pure fn each(f: fn(&[u8], &V) -> bool) {
+    self.root.traverse(~[], f);
+  }
+
+  // visits every entry whose key starts with `prefix`, in key order.
+  pure fn prefix_iter(prefix: &[u8], f: fn(&[u8], &V) -> bool) {
+    let mut node = self.root;
+    let mut pos = 0;
+    while pos < prefix.len() {
+      match node.child(prefix[pos]) {
+        None => return,
+        Some(child) => {
+          let common = common_prefix(child.prefix, prefix, pos);
+          if pos + common < prefix.len() && common < child.prefix.len() {
+            return;
+          }
+          pos += child.prefix.len();
+          node = child;
+        }
+      }
+    }
+    // `pos` may run past the end of `prefix` when it ends inside an edge.
+    node.traverse(vec::slice(prefix, 0, prefix.len()) + vec::slice(node.prefix, node.prefix.len() - (pos - prefix.len()), node.prefix.len()), f);
+  }
+
+  // the entry with the longest key that is a prefix of `key`.
+  pure fn longest_prefix_match(key: &[u8]) -> Option<(~[u8], V)> {
+    let mut best = match self.root.value {
+      Some(v) => Some((~[], v)),
+      None => None
+    };
+    let mut node = self.root;
+    let mut pos = 0;
+    while pos < key.len() {
+      match node.child(key[pos]) {
+        None => break,
+        Some(child) => {
+          if common_prefix(child.prefix, key, pos) < child.prefix.len() {
+            break;
+          }
+          pos += child.prefix.len();
+          node = child;
+          match node.value {
+            Some(v) => best = Some((vec::slice(key, 0, pos), v)),
+            None => ()
+          }
+        }
+      }
+    }
+    best
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  fn k(s: &str) -> ~[u8] {
+    str::to_bytes(s)
+  }
+
+  #[test]
+  fn test_insert_get_remove() {
+    let v1 = RadixTree().insert(k("stevej"), 150).insert(k("thatstacy"), 187);
+    let v2 = v1.insert(k("steve"), 1).insert(k("stevejobs"), 2).insert(k(""), 0);
+
+    assert(v2.len() == 5);
+    assert(v2.get(k("stevej")) == Some(150));
+    assert(v2.get(k("steve")) == Some(1));
+    assert(v2.get(k("stevejobs")) == Some(2));
+    assert(v2.get(k("")) == Some(0));
+    assert(v2.get(k("stev")) == None);
+    assert(v2.get(k("stevejo")) == None);
+    assert(v1.get(k("steve")) == None);
+
+    let v3 = v2.remove(k("stevej")).remove(k("nobody"));
+    assert(v3.len() == 4);
+    assert(v3.get(k("stevej")) == None);
+    assert(v3.get(k("stevejobs")) == Some(2));
+    assert(v2.get(k("stevej")) == Some(150));
+
+    let v4 = v3.remove(k("steve")).remove(k("stevejobs")).remove(k("thatstacy")).remove(k(""));
+    assert(v4.is_empty());
+  }
+
+  #[test]
+  fn test_ordered_iteration() {
+    let mut t = RadixTree();
+    // a raw 0xff byte, not the two-byte UTF-8 encoding of U+00FF.
+    for vec::each(~[k("b"), k("abc"), k("a"), k("ab"), k("ba"), ~[0xffu8], k("abd")]) |key| {
+      t = t.insert(copy *key, key.len());
+    }
+
+    let mut keys : ~[~[u8]] = ~[];
+    for t.each |key, _| {
+      keys.push(vec::slice(key, 0, key.len()));
+    }
+    assert(keys == ~[k("a"), k("ab"), k("abc"), k("abd"), k("b"), k("ba"), ~[0xffu8]]);
+  }
+
+  #[test]
+  fn test_prefix_iter() {
+    let t = RadixTree().insert(k("romane"), 1).insert(k("romanus"), 2)
+                       .insert(k("romulus"), 3).insert(k("rubens"), 4).insert(k("ruber"), 5);
+
+    let mut found : ~[int] = ~[];
+    for t.prefix_iter(k("rom")) |_, v| { found.push(*v); }
+    assert(found == ~[1, 2, 3]);
+
+    found = ~[];
+    for t.prefix_iter(k("roma")) |key, v| {
+      assert(key.len() >= 4);
+      found.push(*v);
+    }
+    assert(found == ~[1, 2]);
+
+    found = ~[];
+    for t.prefix_iter(k("rube")) |_, v| { found.push(*v); }
+    assert(found == ~[4, 5]);
+
+    found = ~[];
+    for t.prefix_iter(k("x")) |_, v| { found.push(*v); }
+    assert(found.is_empty());
+  }
+
+  #[test]
+  fn test_longest_prefix_match() {
+    let t = RadixTree().insert(k("10."), 1).insert(k("10.1."), 2).insert(k("10.1.2."), 3);
+
+    assert(t.longest_prefix_match(k("10.1.2.3")) == Some((k("10.1.2."), 3)));
+    assert(t.longest_prefix_match(k("10.1.9.9")) == Some((k("10.1."), 2)));
+    assert(t.longest_prefix_match(k("10.9")) == Some((k("10."), 1)));
+    assert(t.longest_prefix_match(k("11")) == None);
+  }
+}
@@ -0,0 +1,63 @@
+use core::cmp::{Eq, Ord};
+use red_black_tree::{RBMap, RBMap_empty};
+use sorted_set::{SortedSet, SortedSet_from_vec};
+
+/**
+ * A purely functional sorted set on an RBMap, so it shares the balancing
+ * code in red_black_tree.rs. The set operations are SortedSet's.
+ */
+pub type RBSet<K> = SortedSet<K, @RBMap<K, ()>>;
+
+pure fn RBSet<K: Copy Eq Ord>() -> RBSet<K> {
+  SortedSet { map: RBMap_empty() }
+}
+
+fn RBSet_from_vec<K: Copy Eq Ord>(items: &[K]) -> RBSet<K> {
+  SortedSet_from_vec(RBSet(), items)
+}
+
+
+#[test]
+fn test_insert_contains_remove() {
+  let v1 = RBSet().insert("stevej").insert("thatstacy");
+  assert(v1.contains("stevej"));
+  assert(v1.contains("thatstacy"));
+  assert(!v1.contains("jeremy"));
+
+  let v2 = v1.remove("stevej");
+  assert(!v2.contains("stevej"));
+  assert(v1.contains("stevej"));
+  assert(v2.len() == 1);
+}
+
+#[test]
+fn test_ordered_iteration() {
+  let set = RBSet_from_vec(~[4, 1, 5, 3, 2, 4]);
+  assert(set.to_vec() == ~[1, 2, 3, 4, 5]);
+  assert(RBSet::<int>().is_empty());
+}
+
+#[test]
+fn test_set_algebra() {
+  let a = RBSet_from_vec(~[1, 2, 3, 4]);
+  let b = RBSet_from_vec(~[3, 4, 5]);
+
+  assert(a.union(&b).to_vec() == ~[1, 2, 3, 4, 5]);
+  assert(a.intersection(&b).to_vec() == ~[3, 4]);
+  assert(a.difference(&b).to_vec() == ~[1, 2]);
+  assert(a.symmetric_difference(&b).to_vec() == ~[1, 2, 5]);
+
+  assert(RBSet_from_vec(~[2, 3]).is_subset(&a));
+  assert(!b.is_subset(&a));
+  assert(a.is_superset(&RBSet_from_vec(~[1, 4])));
+  assert(a.is_disjoint(&RBSet_from_vec(~[7, 8])));
+  assert(!a.is_disjoint(&b));
+}
+
+#[test]
+fn test_absent_keys_leave_the_set_alone() {
+  let a = RBSet_from_vec(~[1, 2, 3]);
+  assert(box::ptr_eq(a.remove(9).map, a.map));
+  assert(box::ptr_eq(a.difference(&RBSet_from_vec(~[7, 8])).map, a.map));
+  assert(a.difference(&RBSet_from_vec(~[2, 8])).to_vec() == ~[1, 3]);
+}
@@ -1,80 +1,582 @@
 use core::cmp::{Eq, Ord};
 use iter::BaseIter;
+use std::list::{List, Cons, Nil};
+use persistent_map::{PersistentMap, OrderedPersistentMap};
+use transient::TransientRBMap;
 
-pub trait PersistentMap<K: Copy Eq Ord, V: Copy> {
-  pure fn get(k: K) -> Option<V>;
-  pure fn put(k: K, v: V) -> self;
-  pure fn delete(k: K) -> self;
-}
-
-enum RBColor {
+pub enum RBColor {
   Red,
   Black
 }
 
 pub enum RBMap<K: Copy Eq Ord, V: Copy> {
-  //   color    left         k  value       right
-  Tree(RBColor, @RBMap<K,V>, K, Option<V>, @RBMap<K,V>),
+  //   color    left         k  value       right        live entries in subtree
+  Tree(RBColor, @RBMap<K,V>, K, Option<V>, @RBMap<K,V>, uint),
   Leaf
 }
 
+// number of live entries under `t`. Deleted keys stay behind with no
+// value and aren't counted.
+pure fn subtree_len<K: Copy Eq Ord, V: Copy>(t: @RBMap<K, V>) -> uint {
+  match t {
+    @Leaf => 0,
+    @Tree(_, _, _, _, _, n) => n
+  }
+}
+
+// builds a tree node, working out its subtree count from its children.
+pure fn node<K: Copy Eq Ord, V: Copy>(c: RBColor, l: @RBMap<K,V>, k: K, v: Option<V>, r: @RBMap<K,V>) -> @RBMap<K,V> {
+  let here = if v.is_some() { 1 } else { 0 };
+  @Tree(c, l, k, v, r, subtree_len(l) + here + subtree_len(r))
+}
+
 pure fn RBMap<K: Copy Eq Ord, V: Copy>(key: K, value: V) -> @RBMap<K, V> {
-  @Tree(Red, @Leaf, key, Some(value), @Leaf)
+  node(Red, @Leaf, key, Some(value), @Leaf)
+}
+
+pure fn RBMap_empty<K: Copy Eq Ord, V: Copy>() -> @RBMap<K, V> {
+  @Leaf
+}
+
+/**
+ * Builds a tree from entries sorted by strictly increasing key, in O(n).
+ * Splitting at the middle entry fills every level but the deepest, so
+ * coloring the full levels Black and the partly filled bottom level Red
+ * gives every path the same number of black nodes.
+ */
+pure fn RBMap_from_sorted<K: Copy Eq Ord, V: Copy>(entries: &[(K, V)]) -> @RBMap<K, V> {
+  // the depth of the bottom level, if it isn't full.
+  let mut red_depth = 0;
+  while (1 << (red_depth + 1)) - 1 <= entries.len() {
+    red_depth += 1;
+  }
+  build_sorted(entries, 0, entries.len(), 0, red_depth)
+}
+
+pure fn build_sorted<K: Copy Eq Ord, V: Copy>(entries: &[(K, V)], lo: uint, hi: uint, depth: uint, red_depth: uint) -> @RBMap<K, V> {
+  if lo == hi {
+    return @Leaf;
+  }
+  let mid = (lo + hi) / 2;
+  let (k, v) = entries[mid];
+  let color = if depth == red_depth { Red } else { Black };
+  node(color, build_sorted(entries, lo, mid, depth + 1, red_depth), k, Some(v),
+       build_sorted(entries, mid + 1, hi, depth + 1, red_depth))
+}
+
+/**
+ * Builds and takes apart the nodes of a red-black tree of T. RBMap's own
+ * nodes and AugMap's summary-carrying ones both go through modify_in and
+ * the two balance functions below by way of this, so there is one copy of
+ * the insertion and balancing code.
+ */
+pub trait RBNodes<K, V, T> {
+  // a node with these parts, with whatever else it keeps worked out.
+  pure fn node(c: RBColor, l: T, k: K, v: Option<V>, r: T) -> T;
+
+  // the parts of `t`, or None if it is a leaf.
+  pure fn expose(t: &T) -> Option<(RBColor, T, K, Option<V>, T)>;
+}
+
+// the plain RBMap nodes.
+pub enum PlainNodes {
+  Plain
+}
+
+impl<K: Copy Eq Ord, V: Copy> PlainNodes : RBNodes<K, V, @RBMap<K, V>> {
+  pure fn node(c: RBColor, l: @RBMap<K, V>, k: K, v: Option<V>, r: @RBMap<K, V>) -> @RBMap<K, V> {
+    node(c, l, k, v, r)
+  }
+
+  pure fn expose(t: &@RBMap<K, V>) -> Option<(RBColor, @RBMap<K, V>, K, Option<V>, @RBMap<K, V>)> {
+    match *t {
+      @Leaf => None,
+      @Tree(c, l, k, v, r, _) => Some((c, l, k, v, r))
+    }
+  }
+}
+
+// the parts of `t` below its color, if it is a red node.
+pure fn red_parts<K: Copy Eq Ord, V: Copy, T: Copy, B: RBNodes<K, V, T>>(b: &B, t: &T) -> Option<(T, K, Option<V>, T)> {
+  match b.expose(t) {
+    Some((Red, l, k, v, r)) => Some((l, k, v, r)),
+    _ => None
+  }
+}
+
+// sets `k` to `new_value` under `t`, rebalancing on the way back up.
+pure fn modify_in<K: Copy Eq Ord, V: Copy, T: Copy, B: RBNodes<K, V, T>>(b: &B, t: T, k: K, new_value: Option<V>) -> T {
+  match b.expose(&t) {
+    None => b.node(Red, t, k, new_value, t),
+    Some((color, left, key, original_value, right)) => {
+      if (k.lt(&key)) {
+        balance_left(b, color, modify_in(b, left, k, new_value), key, original_value, right)
+      } else if (k == key) {
+        b.node(color, left, k, new_value, right)
+      } else {
+        balance_right(b, color, left, key, original_value, modify_in(b, right, k, new_value))
+      }
+    }
+  }
+}
+
+// a black node whose left child is red and has a red child of its own
+// becomes a red node with two black children, whichever side the
+// grandchild is on.
+pure fn balance_left<K: Copy Eq Ord, V: Copy, T: Copy, B: RBNodes<K, V, T>>(b: &B, c: RBColor, l: T, k: K, v: Option<V>, r: T) -> T {
+  match c {
+    Black => match red_parts(b, &l) {
+      Some((ll, yK, yV, lr)) => {
+        match red_parts(b, &ll) {
+          Some((t1, xK, xV, t2)) =>
+            return b.node(Red, b.node(Black, t1, xK, xV, t2), yK, yV, b.node(Black, lr, k, v, r)),
+          None => ()
+        }
+        match red_parts(b, &lr) {
+          Some((t2, zK, zV, t3)) =>
+            return b.node(Red, b.node(Black, ll, yK, yV, t2), zK, zV, b.node(Black, t3, k, v, r)),
+          None => ()
+        }
+      }
+      None => ()
+    },
+    Red => ()
+  }
+  b.node(c, l, k, v, r)
+}
+
+// the mirror image of balance_left.
+pure fn balance_right<K: Copy Eq Ord, V: Copy, T: Copy, B: RBNodes<K, V, T>>(b: &B, c: RBColor, l: T, k: K, v: Option<V>, r: T) -> T {
+  match c {
+    Black => match red_parts(b, &r) {
+      Some((rl, yK, yV, rr)) => {
+        match red_parts(b, &rl) {
+          Some((t2, xK, xV, t3)) =>
+            return b.node(Red, b.node(Black, l, k, v, t2), xK, xV, b.node(Black, t3, yK, yV, rr)),
+          None => ()
+        }
+        match red_parts(b, &rr) {
+          Some((t3, zK, zV, t4)) =>
+            return b.node(Red, b.node(Black, l, k, v, rl), yK, yV, b.node(Black, t3, zK, zV, t4)),
+          None => ()
+        }
+      }
+      None => ()
+    },
+    Red => ()
+  }
+  b.node(c, l, k, v, r)
+}
+
+pure fn is_red<K: Copy Eq Ord, V: Copy>(t: @RBMap<K, V>) -> bool {
+  match t {
+    @Tree(Red, _, _, _, _, _) => true,
+    _ => false
+  }
+}
+
+pure fn blacken<K: Copy Eq Ord, V: Copy>(t: @RBMap<K, V>) -> @RBMap<K, V> {
+  match t {
+    @Tree(Red, l, k, v, r, n) => @Tree(Black, l, k, v, r, n),
+    _ => t
+  }
+}
+
+// black nodes on the way down to a leaf, which is the same on every path.
+pure fn black_height<K: Copy Eq Ord, V: Copy>(t: @RBMap<K, V>) -> uint {
+  let mut h = 0;
+  let mut cur = t;
+  loop {
+    match cur {
+      @Leaf => return h,
+      @Tree(c, left, _, _, _, _) => {
+        match c { Black => h += 1, Red => () }
+        cur = left;
+      }
+    }
+  }
+}
+
+/**
+ * Joins `l`, the entry k, and `r`, where every key in `l` is below k and
+ * every key in `r` above it. The shorter tree hangs off a red node at the
+ * first black node of matching black height down the taller tree's inner
+ * spine, and the way back up is rebalanced exactly as after an insert.
+ */
+pure fn join<K: Copy Eq Ord, V: Copy>(l: @RBMap<K, V>, k: K, v: Option<V>, r: @RBMap<K, V>) -> @RBMap<K, V> {
+  let l = blacken(l);
+  let r = blacken(r);
+  let hl = black_height(l);
+  let hr = black_height(r);
+  if hl > hr {
+    blacken(join_right(l, hl, k, v, r, hr))
+  } else if hl < hr {
+    blacken(join_left(l, hl, k, v, r, hr))
+  } else {
+    node(Black, l, k, v, r)
+  }
+}
+
+// `t` has black height ht >= hr, and `r` has a black root.
+pure fn join_right<K: Copy Eq Ord, V: Copy>(t: @RBMap<K, V>, ht: uint, k: K, v: Option<V>, r: @RBMap<K, V>, hr: uint) -> @RBMap<K, V> {
+  if ht == hr && !is_red(t) {
+    return node(Red, t, k, v, r);
+  }
+  match t {
+    @Tree(c, a, x, xv, b, _) => {
+      let hb = match c { Black => ht - 1, Red => ht };
+      balance_right(&Plain, c, a, x, xv, join_right(b, hb, k, v, r, hr))
+    }
+    @Leaf => fail ~"join_right: black heights out of step"
+  }
+}
+
+// `t` has black height ht >= hl, and `l` has a black root.
+pure fn join_left<K: Copy Eq Ord, V: Copy>(l: @RBMap<K, V>, hl: uint, k: K, v: Option<V>, t: @RBMap<K, V>, ht: uint) -> @RBMap<K, V> {
+  if ht == hl && !is_red(t) {
+    return node(Red, l, k, v, t);
+  }
+  match t {
+    @Tree(c, a, x, xv, b, _) => {
+      let ha = match c { Black => ht - 1, Red => ht };
+      balance_left(&Plain, c, join_left(l, hl, k, v, a, ha), x, xv, b)
+    }
+    @Leaf => fail ~"join_left: black heights out of step"
+  }
+}
+
+// the entries of `t` below `k` and those above it, leaving out `k` itself.
+pure fn split_key<K: Copy Eq Ord, V: Copy>(t: @RBMap<K, V>, k: K) -> (@RBMap<K, V>, @RBMap<K, V>) {
+  match t {
+    @Leaf => (t, t),
+    @Tree(_, left, key, maybe_value, right, _) => {
+      if (k.lt(&key)) {
+        let (a, b) = split_key(left, k);
+        (a, join(b, key, maybe_value, right))
+      } else if (k.gt(&key)) {
+        let (a, b) = split_key(right, k);
+        (join(left, key, maybe_value, a), b)
+      } else {
+        (left, right)
+      }
+    }
+  }
 }
 
 impl<K: Copy Eq Ord, V: Copy> @RBMap<K, V> {
+  // the root is always made black, or nothing would ever be.
   pure fn modify(k: K, new_value: Option<V>) -> @RBMap<K, V> {
+    blacken(modify_in(&Plain, self, k, new_value))
+  }
+
+  pure fn balanceLeft(c: RBColor, l: @RBMap<K,V>, k: K, v: Option<V>, r: @RBMap<K,V>) -> @RBMap<K,V> {
+    balance_left(&Plain, c, l, k, v, r)
+  }
+
+  pure fn balanceRight(c: RBColor, l: @RBMap<K,V>, k: K, v: Option<V>, r: @RBMap<K,V>) -> @RBMap<K,V> {
+    balance_right(&Plain, c, l, k, v, r)
+  }
+
+  /**
+   * Calls `f` on every live entry in ascending key order until it returns
+   * false. Returns false if `f` stopped the walk early.
+   */
+  pure fn traverse(f: fn(&K, &V) -> bool) -> bool {
     match self {
-      @Leaf => @Tree(Red, self, k, copy new_value, self),
-      @Tree(color, left, key, original_value, right) => {
-        if (k.lt(&key)) {
-          self.balanceLeft(color, left.modify(k, new_value), key, original_value, right)
-        } else if (k == key) {
-          @Tree(color, left, k, copy new_value, right)
+      @Leaf => true,
+      @Tree(_, left, key, maybe_value, right, _) => {
+        if (!left.traverse(f)) {
+          return false;
+        }
+        match maybe_value {
+          Some(value) => if (!f(&key, &value)) { return false; },
+          None => ()
+        }
+        right.traverse(f)
+      }
+    }
+  }
+
+  // like traverse, in descending key order.
+  pure fn traverse_rev(f: fn(&K, &V) -> bool) -> bool {
+    match self {
+      @Leaf => true,
+      @Tree(_, left, key, maybe_value, right, _) => {
+        if (!right.traverse_rev(f)) {
+          return false;
+        }
+        match maybe_value {
+          Some(value) => if (!f(&key, &value)) { return false; },
+          None => ()
+        }
+        left.traverse_rev(f)
+      }
+    }
+  }
+
+  // like traverse, skipping subtrees that can't hold keys in [lo, hi).
+  pure fn traverse_range(lo: &K, hi: &K, f: fn(&K, &V) -> bool) -> bool {
+    match self {
+      @Leaf => true,
+      @Tree(_, left, key, maybe_value, right, _) => {
+        if (key.gt(lo) && !left.traverse_range(lo, hi, f)) {
+          return false;
+        }
+        if (key.ge(lo) && key.lt(hi)) {
+          match maybe_value {
+            Some(value) => if (!f(&key, &value)) { return false; },
+            None => ()
+          }
+        }
+        if (key.lt(hi)) {
+          right.traverse_range(lo, hi, f)
         } else {
-          self.balanceRight(color, left, key, original_value, right.modify(k, new_value))
+          true
         }
       }
     }
   }
 
-  pure fn balanceLeft(c: RBColor, l: @RBMap<K,V>, k: K, v: Option<V>, r: @RBMap<K,V>) -> @RBMap<K,V> {
-    match (c, l, k, v, r) {
-      (Black,@Tree(Red,@Tree(Red,a,xK,xV,b),yK,yV,c),zK,zV,d) =>
-        @Tree(Red,@Tree(Black,a,xK,xV,b),yK,yV,@Tree(Black,c,zK,zV,d)),
-      (c,a,xK,xV,b) =>
-        @Tree(c,a,xK,xV,b)
+  // the entry at position `i` in key order, counting from 0.
+  pure fn nth(i: uint) -> Option<(K, V)> {
+    match self {
+      @Leaf => None,
+      @Tree(_, left, key, maybe_value, right, _) => {
+        let left_len = subtree_len(left);
+        if (i < left_len) {
+          return left.nth(i);
+        }
+        match maybe_value {
+          Some(value) => {
+            if (i == left_len) {
+              Some((key, value))
+            } else {
+              right.nth(i - left_len - 1)
+            }
+          }
+          None => right.nth(i - left_len)
+        }
+      }
     }
   }
 
-  pure fn balanceRight(c: RBColor, l: @RBMap<K,V>, k: K, v: Option<V>, r: @RBMap<K,V>) -> @RBMap<K,V> {
-    match (c, l, k, v, r) {
-      (Black, @Tree(Red, t1, k1, v1, t2), k0, v0, @Tree(Red, t3, k2, v2, t4)) =>
-        @Tree(Red, @Tree(Black, t1, k1, v1, t2), k0, v0, @Tree(Black, t3, k2, v2, t4)),
-      (c, l, k, v, @Tree(Red, t1, k1, v1, t2)) =>
-        @Tree(c, @Tree(Red, l, k, v, t1), k1, v1, t2),
-      (c,a,xK,xV,b) =>
-        @Tree(c,a,xK,xV,b)
+  // the position of `k` in key order, if it is present.
+  pure fn index_of(k: K) -> Option<uint> {
+    let mut before = 0;
+    let mut cur = self;
+
+    loop {
+      match cur {
+        @Leaf => return None,
+        @Tree(_, left, key, maybe_value, right, _) => {
+          if (k.lt(&key)) {
+            cur = left;
+          } else if (k.gt(&key)) {
+            before += subtree_len(left) + if maybe_value.is_some() { 1 } else { 0 };
+            cur = right;
+          } else {
+            return match maybe_value {
+              Some(_) => Some(before + subtree_len(left)),
+              None => None
+            };
+          }
+        }
+      }
     }
   }
 
-  pure fn real_each(f: fn(&(&self/K, &self/V)) -> bool, descend: bool) {
-    match *self {
-      Leaf => (),
-      Tree(_, left, key, maybe_value, right) => ()
+  /**
+   * Splits into the first `i` entries and the rest. Each subtree the cut
+   * path leaves behind is joined back on with the node above it, so both
+   * halves are balanced red-black trees, in O(log^2 n).
+   */
+  pure fn split_at(i: uint) -> (@RBMap<K, V>, @RBMap<K, V>) {
+    match self {
+      @Leaf => (self, self),
+      @Tree(_, left, key, maybe_value, right, n) => {
+        if (i == 0) {
+          return (@Leaf, self);
+        } else if (i >= n) {
+          return (self, @Leaf);
+        }
+
+        let left_len = subtree_len(left);
+        if (i <= left_len) {
+          let (a, b) = left.split_at(i);
+          (a, join(b, key, maybe_value, right))
+        } else {
+          let here = if maybe_value.is_some() { 1 } else { 0 };
+          let (a, b) = right.split_at(i - left_len - here);
+          (join(left, key, maybe_value, a), b)
+        }
+      }
+    }
+  }
+
+  /**
+   * Takes `k` out of the tree altogether, where delete leaves a node with
+   * no value behind. The tree is split around `k` and the halves joined
+   * back under the first key of the upper one, in O(log^2 n).
+   */
+  pure fn remove(k: K) -> @RBMap<K, V> {
+    if !self.has_node(k) {
+      return self;
+    }
+    let (below, above) = split_key(self, k);
+    // the leftmost node of the upper half, deleted or not.
+    let mut first = above;
+    loop {
+      match first {
+        @Leaf => break,
+        @Tree(_, left, _, _, _, _) => match left {
+          @Leaf => break,
+          _ => first = left
+        }
+      }
+    }
+    match first {
+      @Leaf => blacken(below),
+      @Tree(_, _, key, maybe_value, _, _) => {
+        let (_, rest) = split_key(above, key);
+        join(below, key, maybe_value, rest)
+      }
+    }
+  }
+
+  // whether `k` has a node, live or deleted.
+  priv pure fn has_node(k: K) -> bool {
+    let mut cur = self;
+    loop {
+      match cur {
+        @Leaf => return false,
+        @Tree(_, left, key, _, right, _) => {
+          if (k.lt(&key)) {
+            cur = left;
+          } else if (k.gt(&key)) {
+            cur = right;
+          } else {
+            return true;
+          }
+        }
+      }
     }
   }
+
+  // a mutable builder for a batch of edits, starting from this version.
+  pure fn transient() -> TransientRBMap<K, V> {
+    TransientRBMap(self)
+  }
 }
 
 
 /**
- * A purely functional Left-Leaning Red-Black Tree.
+ * A position in an RBMap found by a single descent towards `key`. `node` is
+ * the tree holding `key`, or the leaf where it would go, and `path` lists
+ * the trees passed on the way down, nearest first, with whether we went
+ * left from each. Writing through the entry rebuilds and rebalances just
+ * that path.
+ */
+pub struct RBEntry<K: Copy Eq Ord, V: Copy> {
+  root: @RBMap<K, V>,
+  key: K,
+  node: @RBMap<K, V>,
+  path: @List<(bool, @RBMap<K, V>)>
+}
+
+impl<K: Copy Eq Ord, V: Copy> @RBMap<K, V> {
+  pure fn entry(k: K) -> RBEntry<K, V> {
+    let mut path = @Nil;
+    let mut cur = self;
+
+    loop {
+      match cur {
+        @Leaf => break,
+        @Tree(_, left, key, _, right, _) => {
+          if (k.lt(&key)) {
+            path = @Cons((true, cur), path);
+            cur = left;
+          } else if (k.gt(&key)) {
+            path = @Cons((false, cur), path);
+            cur = right;
+          } else {
+            break;
+          }
+        }
+      }
+    }
+
+    RBEntry { root: self, key: k, node: cur, path: path }
+  }
+}
+
+impl<K: Copy Eq Ord, V: Copy> RBEntry<K, V> {
+  pure fn get() -> Option<V> {
+    match self.node {
+      @Tree(_, _, _, maybe_value, _, _) => copy maybe_value,
+      @Leaf => None
+    }
+  }
+
+  pure fn set(v: V) -> @RBMap<K, V> {
+    self.rebuild(Some(v))
+  }
+
+  pure fn remove() -> @RBMap<K, V> {
+    match self.get() {
+      Some(_) => self.rebuild(None),
+      None => self.root
+    }
+  }
+
+  pure fn or_insert_with(f: fn() -> V) -> (V, @RBMap<K, V>) {
+    match self.get() {
+      Some(v) => (v, self.root),
+      None => {
+        let v = f();
+        (v, self.set(v))
+      }
+    }
+  }
+
+  priv pure fn rebuild(new_value: Option<V>) -> @RBMap<K, V> {
+    let mut result = match self.node {
+      @Leaf => node(Red, @Leaf, self.key, copy new_value, @Leaf),
+      @Tree(color, left, key, _, right, _) => node(color, left, key, copy new_value, right)
+    };
+
+    let mut cur = self.path;
+    loop {
+      match cur {
+        @Nil => break,
+        @Cons((went_left, parent), rest) => {
+          result = match parent {
+            @Tree(c, l, k, v, r, _) => {
+              if (went_left) {
+                self.root.balanceLeft(c, result, k, v, r)
+              } else {
+                self.root.balanceRight(c, l, k, v, result)
+              }
+            }
+            @Leaf => result
+          };
+          cur = rest;
+        }
+      }
+    }
+
+    return blacken(result);
+  }
+}
+
+
+/**
+ * A purely functional Red-Black Tree.
  */
 impl<K: Copy Eq Ord, V: Copy> @RBMap<K, V> : PersistentMap<K, V> {
   pure fn get(k: K) -> Option<V> {
     match self {
       @Leaf => None,
-      @Tree(_, left, key, maybe_value, right) => {
+      @Tree(_, left, key, maybe_value, right, _) => {
         if (k.lt(&key)) {
           left.get(k)
         } else if (k.gt(&key)) {
@@ -90,50 +592,176 @@ impl<K: Copy Eq Ord, V: Copy> @RBMap<K, V> : PersistentMap<K, V> {
     self.modify(k, Some(new_value))
   }
 
+  // deleting a key that isn't there returns the map itself rather than
+  // leaving a tombstone behind.
   pure fn delete(k: K) -> @RBMap<K, V> {
+    if !self.contains_key(k) {
+      return self;
+    }
     self.modify(k, None)
   }
+
+  pure fn len() -> uint {
+    subtree_len(self)
+  }
+
+  pure fn is_empty() -> bool {
+    subtree_len(self) == 0
+  }
+
+  pure fn contains_key(k: K) -> bool {
+    self.get(k).is_some()
+  }
+
+  pure fn iter(f: fn(&K, &V) -> bool) {
+    self.traverse(f);
+  }
+
+  pure fn keys(f: fn(&K) -> bool) {
+    self.traverse(|k, _| f(k));
+  }
+
+  pure fn values(f: fn(&V) -> bool) {
+    self.traverse(|_, v| f(v));
+  }
+
+  pure fn insert_returning_old(k: K, v: V) -> (Option<V>, @RBMap<K, V>) {
+    let entry = self.entry(k);
+    (entry.get(), entry.set(v))
+  }
+
+  pure fn update(k: K, f: fn(Option<&V>) -> Option<V>) -> @RBMap<K, V> {
+    let entry = self.entry(k);
+    let current = entry.get();
+    let result = match current {
+      Some(ref v) => f(Some(v)),
+      None => f(None)
+    };
+
+    match result {
+      Some(v) => entry.set(v),
+      None => entry.remove()
+    }
+  }
+
+  pure fn alter(k: K, f: fn(&V) -> V) -> @RBMap<K, V> {
+    self.update(k, |current| current.map(|v| f(*v)))
+  }
+
+  pure fn insert_with(k: K, v: V, f: fn(&V, &V) -> V) -> @RBMap<K, V> {
+    self.update(k, |current| match current {
+      Some(old) => Some(f(old, &v)),
+      None => Some(v)
+    })
+  }
+
+  pure fn get_or_insert_with(k: K, f: fn() -> V) -> (V, @RBMap<K, V>) {
+    self.entry(k).or_insert_with(f)
+  }
 }
 
-impl<K: Copy Eq Ord, V: Copy> RBMap<K, V>: BaseIter<(&K, &V)> {
-  pure fn size_hint(&self) -> Option<uint> {
-    None
+impl<K: Copy Eq Ord, V: Copy> @RBMap<K, V> : OrderedPersistentMap<K, V> {
+  pure fn min() -> Option<(K, V)> {
+    let mut found = None;
+    self.traverse(|k, v| { found = Some((*k, *v)); false });
+    found
   }
 
-  pure fn each(&self, f: fn(&(&self/K, &self/V)) -> bool) {
+  pure fn max() -> Option<(K, V)> {
+    let mut found = None;
+    self.traverse_rev(|k, v| { found = Some((*k, *v)); false });
+    found
+  }
+
+  pure fn floor(k: K) -> Option<(K, V)> {
+    match self {
+      @Leaf => None,
+      @Tree(_, left, key, maybe_value, right, _) => {
+        if (k.lt(&key)) {
+          return left.floor(k);
+        }
+        if (key.lt(&k)) {
+          match right.floor(k) {
+            None => (),
+            found => return found
+          }
+        }
+        // deleted keys stay behind with no value, so keep looking left.
+        match maybe_value {
+          Some(value) => Some((key, value)),
+          None => left.floor(k)
+        }
+      }
+    }
+  }
+
+  pure fn ceiling(k: K) -> Option<(K, V)> {
+    match self {
+      @Leaf => None,
+      @Tree(_, left, key, maybe_value, right, _) => {
+        if (k.gt(&key)) {
+          return right.ceiling(k);
+        }
+        if (key.gt(&k)) {
+          match left.ceiling(k) {
+            None => (),
+            found => return found
+          }
+        }
+        match maybe_value {
+          Some(value) => Some((key, value)),
+          None => right.ceiling(k)
+        }
+      }
+    }
+  }
+
+  pure fn range(lo: K, hi: K, f: fn(&K, &V) -> bool) {
+    self.traverse_range(&lo, &hi, f);
+  }
+}
+
+impl<K: Copy Eq Ord, V: Copy> RBMap<K, V> {
+  // calls `f` on each live entry in order; false once `f` has stopped the walk.
+  priv pure fn each_while(&self, f: fn(&(&self/K, &self/V)) -> bool) -> bool {
     match *self {
-      Leaf => (),
-      Tree(_, ref left, ref key, ref maybe_value, ref right) => {
-        self.real_each(f, true);
+      Leaf => true,
+      Tree(_, ref left, ref key, ref maybe_value, ref right, _) => {
         let left: &self/@RBMap<K,V> = left;
         let key: &self/K = key;
-        unsafe {
-          io::println(fmt!("entering for %?", *key));
-        }
         let maybe_value: &self/Option<V> = maybe_value;
         let right: &self/@RBMap<K,V> = right;
-        left.each(f);
+        if !left.each_while(f) {
+          return false;
+        }
         match *maybe_value {
           Some(ref value) => {
             let value: &self/V = value;
             if !f(&(key, value)) {
-              unsafe {
-                io::println(fmt!("exiting early on key: %?", *key));
-              }
+              return false;
             }
           }
           None => ()
         };
-        unsafe {
-          io::println(fmt!("descending key %?", *key));
-        }
-
-        right.each(f);
+        right.each_while(f)
       }
     }
   }
 }
 
+impl<K: Copy Eq Ord, V: Copy> RBMap<K, V>: BaseIter<(&K, &V)> {
+  pure fn size_hint(&self) -> Option<uint> {
+    match *self {
+      Leaf => Some(0),
+      Tree(_, _, _, _, _, n) => Some(n)
+    }
+  }
+
+  pure fn each(&self, f: fn(&(&self/K, &self/V)) -> bool) {
+    self.each_while(f);
+  }
+}
+
 
 #[test]
 fn test_rb_tree() {
@@ -206,5 +834,252 @@ fn test_for() {
   }
   v5.each(|z| t(n, z));
 
-  assert(*n == 5);
+  // 1 and 2 were counted, and the walk stopped at 3.
+  assert(*n == 3);
+}
+
+#[test]
+fn test_update() {
+  let v1 = RBMap("stevej", 150).put("thatstacy", 187);
+
+  let v2 = v1.update("stevej", |v| v.map(|n| *n + 1));
+  assert(v2.get("stevej") == Some(151));
+  assert(v1.get("stevej") == Some(150));
+
+  let v3 = v2.update("jeremy", |v| match v { Some(n) => Some(*n), None => Some(16) });
+  assert(v3.get("jeremy") == Some(16));
+
+  let v4 = v3.update("thatstacy", |_| None);
+  assert(v4.get("thatstacy") == None);
+  assert(v4.get("jeremy") == Some(16));
+
+  // removing an absent key hands back the same map.
+  let v5 = v4.update("ev", |_| None);
+  assert(box::ptr_eq(v4, v5));
+}
+
+#[test]
+fn test_alter_and_insert_with() {
+  let v1 = RBMap(1, 10);
+
+  let v2 = v1.alter(1, |n| *n * 2).alter(2, |n| *n * 2);
+  assert(v2.get(1) == Some(20));
+  assert(v2.get(2) == None);
+
+  let v3 = v2.insert_with(1, 5, |old, new| *old + *new).insert_with(3, 5, |old, new| *old + *new);
+  assert(v3.get(1) == Some(25));
+  assert(v3.get(3) == Some(5));
+}
+
+#[test]
+fn test_entry() {
+  let v1 = RBMap(4, 0).put(2, 0).put(6, 0);
+
+  let (a, v2) = v1.get_or_insert_with(5, || 55);
+  assert(a == 55);
+  assert(v2.get(5) == Some(55));
+
+  let (b, v3) = v2.get_or_insert_with(5, || 99);
+  assert(b == 55);
+  assert(box::ptr_eq(v2, v3));
+
+  let entry = v3.entry(2);
+  assert(entry.get() == Some(0));
+  assert(entry.set(7).get(2) == Some(7));
+  assert(entry.remove().get(2) == None);
+  assert(v3.get(2) == Some(0));
+}
+
+#[test]
+fn test_len_tracks_puts_and_deletes() {
+  let v1 = RBMap(1, 0).put(2, 0).put(3, 0);
+  assert(v1.len() == 3);
+  assert(v1.put(2, 9).len() == 3);
+
+  let v2 = v1.delete(2).delete(7);
+  assert(v2.len() == 2);
+  assert(box::ptr_eq(v2.delete(7), v2));
+  assert(v2.size_hint() == Some(2));
+  assert(v1.len() == 3);
+}
+
+#[test]
+fn test_positional_indexing() {
+  let mut m = RBMap_empty();
+  for uint::range(0, 50) |i| {
+    m = m.put(i * 2, i);
+  }
+  m = m.delete(10);
+
+  assert(m.len() == 49);
+  assert(m.nth(0) == Some((0, 0)));
+  assert(m.nth(4) == Some((8, 4)));
+  assert(m.nth(5) == Some((12, 6)));
+  assert(m.nth(48) == Some((98, 49)));
+  assert(m.nth(49) == None);
+
+  assert(m.index_of(12) == Some(5));
+  assert(m.index_of(10) == None);
+  assert(m.index_of(11) == None);
+
+  for uint::range(0, 49) |i| {
+    match m.nth(i) {
+      Some((k, _)) => assert(m.index_of(k) == Some(i)),
+      None => fail
+    }
+  }
+}
+
+#[test]
+fn test_split_at() {
+  let mut m = RBMap_empty();
+  for uint::range(0, 20) |i| {
+    m = m.put(i, i);
+  }
+
+  let (a, b) = m.split_at(7);
+  assert(a.len() == 7);
+  assert(b.len() == 13);
+  assert(a.max() == Some((6, 6)));
+  assert(b.min() == Some((7, 7)));
+  assert(b.nth(0) == Some((7, 7)));
+
+  let (c, d) = m.split_at(0);
+  assert(c.is_empty());
+  assert(d.len() == 20);
+}
+
+// the black height of `t`, failing if a red node has a red child or the heights differ.
+#[cfg(test)]
+fn check_black_height(t: @RBMap<uint, uint>, parent_red: bool) -> uint {
+  match t {
+    @Leaf => 1,
+    @Tree(color, left, _, _, right, _) => {
+      let red = match color { Red => true, Black => false };
+      assert(!(red && parent_red));
+      let l = check_black_height(left, red);
+      assert(l == check_black_height(right, red));
+      if red { l } else { l + 1 }
+    }
+  }
+}
+
+#[cfg(test)]
+fn depth(t: @RBMap<uint, uint>) -> uint {
+  match t {
+    @Leaf => 0,
+    @Tree(_, left, _, _, right, _) => 1 + uint::max(depth(left), depth(right))
+  }
+}
+
+#[test]
+fn test_puts_keep_balance() {
+  let mut m = RBMap_empty();
+  for uint::range(0, 1000) |i| {
+    m = m.put(i, i);
+  }
+  // black root, no red node with a red child, and so at most 2 log2(n + 1) deep.
+  assert(!is_red(m));
+  check_black_height(m, false);
+  assert(depth(m) <= 20);
+
+  let mut seed = 7u;
+  let mut r = RBMap_empty();
+  for uint::range(0, 5000) |i| {
+    seed = (seed * 1103515245 + 12345) % 2147483648;
+    r = r.put(seed % 100000, i);
+    if i % 3 == 0 {
+      r = r.entry(seed % 1000).set(i);
+    }
+  }
+  check_black_height(r, false);
+  assert(depth(r) <= 2 * 13);
+}
+
+#[test]
+fn test_split_at_keeps_balance() {
+  let mut m = RBMap_empty();
+  for uint::range(0, 100) |i| {
+    m = m.put(i * 7 % 100, i);
+  }
+  let m = m.delete(50);
+  let sorted = RBMap_from_sorted(vec::from_fn(77, |i| (i, i)));
+
+  for vec::each(~[m, sorted]) |t| {
+    for uint::range(0, t.len() + 1) |i| {
+      let (a, b) = t.split_at(i);
+      check_black_height(a, false);
+      check_black_height(b, false);
+      assert(a.len() == i);
+      assert(b.len() == t.len() - i);
+      if i > 0 {
+        assert(a.max() == t.nth(i - 1));
+      }
+      assert(b.min() == t.nth(i));
+      // and the halves keep their balance as they change.
+      check_black_height(a.put(1000, 0).put(0, 0), false);
+      check_black_height(b.put(1000, 0).put(0, 0), false);
+    }
+  }
+}
+
+#[test]
+fn test_remove_drops_the_node() {
+  let mut m = RBMap_empty();
+  for uint::range(0, 200) |i| {
+    m = m.put(i, i);
+  }
+  let deleted = m.delete(50);
+  let mut cur = deleted;
+  for uint::range(0, 200) |i| {
+    if i % 3 == 0 {
+      cur = cur.remove(i);
+      check_black_height(cur, false);
+      assert(!is_red(cur));
+    }
+  }
+  // 67 multiples of 3 gone, and 50 only ever had a deleted node.
+  assert(cur.len() == 200 - 67 - 1);
+  assert(cur.get(3) == None);
+  assert(cur.get(4) == Some(4));
+  assert(cur.get(50) == None);
+  assert(cur.remove(50).len() == cur.len());
+  assert(box::ptr_eq(cur.remove(3), cur));
+
+  // nothing is left behind, not even the deleted node.
+  let mut gone = cur.remove(50);
+  for uint::range(0, 200) |i| {
+    gone = gone.remove(i);
+  }
+  assert(gone.is_empty());
+  match gone {
+    @Leaf => (),
+    _ => fail ~"remove left a node behind"
+  }
+  assert(m.len() == 200);
+}
+
+#[test]
+fn test_from_sorted() {
+  for uint::range(0, 70) |n| {
+    let mut entries = ~[];
+    for uint::range(0, n) |i| {
+      entries.push((i * 2, i));
+    }
+    let m = RBMap_from_sorted(entries);
+    check_black_height(m, false);
+    assert(m.len() == n);
+    for uint::range(0, n) |i| {
+      assert(m.get(i * 2) == Some(i));
+      assert(m.get(i * 2 + 1) == None);
+    }
+    // and the tree keeps its balance as it changes, wherever the new keys
+    // land under the red bottom level.
+    let mut grown = m;
+    for uint::range(0, n + 1) |i| {
+      grown = grown.put(i * 2 + 1, i);
+      check_black_height(grown, false);
+    }
+    assert(grown.len() == 2 * n + 1);
+  }
 }
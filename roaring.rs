@@ -0,0 +1,997 @@
+use ctpop::ctpop32;
+use bit_set::{count_words, each_set_bit};
+use bytes::{push_u16_le, push_u32_le, read_u16_le, read_u32_le};
+
+// the most values an array container holds before it becomes a bitmap.
+const ARRAY_MAX : uint = 4096;
+const BITMAP_WORDS : uint = 2048;
+
+// the cookies at the start of the portable serialization format.
+const SERIAL_COOKIE_NO_RUNCONTAINER : u32 = 12346;
+const SERIAL_COOKIE : u32 = 12347;
+// with run containers, the offset header is only written for this many containers or more.
+const NO_OFFSET_THRESHOLD : uint = 4;
+
+/**
+ * The low 16 bits of the values that share one high 16-bit key. Sparse
+ * chunks are sorted arrays, dense ones are 65536-bit bitmaps, and long
+ * runs of consecutive values can be kept as (start, length - 1) pairs.
+ */
+pub enum Container {
+  ArrayContainer(~[u16]),
+  BitmapContainer(~[u32], uint),
+  RunContainer(~[(u16, u16)])
+}
+
+// whether `x` is in the sorted array `a`, and where it is or would go.
+pure fn search_u16(a: &[u16], x: u16) -> (bool, uint) {
+  let mut lo = 0;
+  let mut hi = a.len();
+  while lo < hi {
+    let mid = (lo + hi) / 2;
+    if a[mid] < x {
+      lo = mid + 1;
+    } else {
+      hi = mid;
+    }
+  }
+  (lo < a.len() && a[lo] == x, lo)
+}
+
+pure fn words_of_array(a: &[u16]) -> ~[u32] {
+  let mut words = vec::from_elem(BITMAP_WORDS, 0u32);
+  for vec::each(a) |x| {
+    words[(*x as uint) / 32] |= 1 << ((*x as u32) % 32);
+  }
+  words
+}
+
+// picks an array or a bitmap for `words` by cardinality.
+pure fn container_of_words(words: ~[u32]) -> Container {
+  let card = count_words(words);
+  if card > ARRAY_MAX {
+    return BitmapContainer(words, card);
+  }
+  let mut a = ~[];
+  each_set_bit(words, 0, |i| { a.push(i as u16); true });
+  ArrayContainer(a)
+}
+
+pure fn container_of_array(a: ~[u16]) -> Container {
+  if a.len() > ARRAY_MAX {
+    let words = words_of_array(a);
+    BitmapContainer(words, a.len())
+  } else {
+    ArrayContainer(a)
+  }
+}
+
+// merges sorted (start, end) intervals that overlap or touch into runs.
+pure fn coalesce(intervals: &[(uint, uint)]) -> ~[(u16, u16)] {
+  let mut runs = ~[];
+  let mut i = 0;
+  while i < intervals.len() {
+    let (start, mut end) = intervals[i];
+    i += 1;
+    while i < intervals.len() && intervals[i].first() <= end + 1 {
+      end = uint::max(end, intervals[i].second());
+      i += 1;
+    }
+    runs.push((start as u16, (end - start) as u16));
+  }
+  runs
+}
+
+pure fn union_runs(a: &[(u16, u16)], b: &[(u16, u16)]) -> ~[(u16, u16)] {
+  let mut intervals = ~[];
+  let mut i = 0;
+  let mut j = 0;
+  while i < a.len() || j < b.len() {
+    let take_a = j == b.len() || (i < a.len() && a[i].first() <= b[j].first());
+    let (s, l) = if take_a { i += 1; a[i - 1] } else { j += 1; b[j - 1] };
+    intervals.push((s as uint, (s as uint) + (l as uint)));
+  }
+  coalesce(intervals)
+}
+
+pure fn intersect_runs(a: &[(u16, u16)], b: &[(u16, u16)]) -> ~[(u16, u16)] {
+  let mut runs = ~[];
+  let mut i = 0;
+  let mut j = 0;
+  while i < a.len() && j < b.len() {
+    let (s1, l1) = a[i];
+    let (s2, l2) = b[j];
+    let e1 = (s1 as uint) + (l1 as uint);
+    let e2 = (s2 as uint) + (l2 as uint);
+    let start = uint::max(s1 as uint, s2 as uint);
+    let end = uint::min(e1, e2);
+    if start <= end {
+      runs.push((start as u16, (end - start) as u16));
+    }
+    if e1 < e2 { i += 1; } else { j += 1; }
+  }
+  runs
+}
+
+impl Container {
+  pure fn cardinality() -> uint {
+    match self {
+      ArrayContainer(ref a) => a.len(),
+      BitmapContainer(_, card) => card,
+      RunContainer(ref runs) => {
+        let mut n = 0;
+        for vec::each(*runs) |r| {
+          n += (r.second() as uint) + 1;
+        }
+        n
+      }
+    }
+  }
+
+  pure fn contains(x: u16) -> bool {
+    match self {
+      ArrayContainer(ref a) => search_u16(*a, x).first(),
+      BitmapContainer(ref words, _) => words[(x as uint) / 32] & (1 << ((x as u32) % 32)) != 0,
+      RunContainer(ref runs) => {
+        // the last run starting at or before x.
+        let mut lo = 0;
+        let mut hi = runs.len();
+        while lo < hi {
+          let mid = (lo + hi) / 2;
+          if runs[mid].first() <= x { lo = mid + 1; } else { hi = mid; }
+        }
+        if lo == 0 {
+          return false;
+        }
+        let (s, l) = runs[lo - 1];
+        (x as uint) <= (s as uint) + (l as uint)
+      }
+    }
+  }
+
+  // visits the values in ascending order.
+  pure fn each(f: fn(u16) -> bool) -> bool {
+    match self {
+      ArrayContainer(ref a) => {
+        for vec::each(*a) |x| {
+          if !f(*x) { return false; }
+        }
+        true
+      }
+      BitmapContainer(ref words, _) => each_set_bit(*words, 0, |i| f(i as u16)),
+      RunContainer(ref runs) => {
+        for vec::each(*runs) |r| {
+          let (s, l) = *r;
+          for uint::range(s as uint, (s as uint) + (l as uint) + 1) |x| {
+            if !f(x as u16) { return false; }
+          }
+        }
+        true
+      }
+    }
+  }
+
+  pure fn to_words() -> ~[u32] {
+    match self {
+      ArrayContainer(ref a) => words_of_array(*a),
+      BitmapContainer(ref words, _) => copy *words,
+      RunContainer(_) => {
+        let mut words = vec::from_elem(BITMAP_WORDS, 0u32);
+        for self.each |x| {
+          words[(x as uint) / 32] |= 1 << ((x as u32) % 32);
+        }
+        words
+      }
+    }
+  }
+
+  pure fn to_runs() -> ~[(u16, u16)] {
+    match self {
+      RunContainer(ref runs) => copy *runs,
+      _ => {
+        let mut points = ~[];
+        for self.each |x| {
+          points.push((x as uint, x as uint));
+        }
+        coalesce(points)
+      }
+    }
+  }
+
+  // adds `x` in place; an array that outgrows ARRAY_MAX becomes a bitmap.
+  // Returns true if `x` wasn't already there.
+  fn insert(&mut self, x: u16) -> bool {
+    let mut replacement = None;
+    match *self {
+      ArrayContainer(ref mut a) => {
+        let (found, i) = search_u16(*a, x);
+        if found {
+          return false;
+        }
+        if a.len() == ARRAY_MAX {
+          let mut words = words_of_array(*a);
+          words[(x as uint) / 32] |= 1 << ((x as u32) % 32);
+          replacement = Some(BitmapContainer(words, ARRAY_MAX + 1));
+        } else {
+          a.push(x);
+          let mut j = a.len() - 1;
+          while j > i {
+            a[j] = a[j - 1];
+            j -= 1;
+          }
+          a[i] = x;
+        }
+      }
+      BitmapContainer(ref mut words, ref mut card) => {
+        let bit = 1 << ((x as u32) % 32);
+        if words[(x as uint) / 32] & bit != 0 {
+          return false;
+        }
+        words[(x as uint) / 32] |= bit;
+        *card += 1;
+      }
+      RunContainer(ref runs) => {
+        if self.contains(x) {
+          return false;
+        }
+        replacement = Some(RunContainer(union_runs(*runs, ~[(x, 0)])));
+      }
+    }
+    match replacement {
+      Some(c) => *self = c,
+      None => ()
+    }
+    true
+  }
+
+  // takes `x` out in place; it may be left empty, and a bitmap that drops
+  // to ARRAY_MAX values becomes an array. Returns true if `x` was there.
+  fn remove(&mut self, x: u16) -> bool {
+    if !self.contains(x) {
+      return false;
+    }
+    let mut replacement = None;
+    match *self {
+      ArrayContainer(ref mut a) => {
+        let (_, i) = search_u16(*a, x);
+        for uint::range(i, a.len() - 1) |j| {
+          a[j] = a[j + 1];
+        }
+        a.pop();
+      }
+      BitmapContainer(ref mut words, ref mut card) => {
+        words[(x as uint) / 32] &= !(1 << ((x as u32) % 32));
+        *card -= 1;
+        if *card <= ARRAY_MAX {
+          replacement = Some(container_of_words(copy *words));
+        }
+      }
+      RunContainer(ref runs) => {
+        let mut out = ~[];
+        for vec::each(*runs) |r| {
+          let (s, l) = *r;
+          let (start, end) = (s as uint, (s as uint) + (l as uint));
+          let xu = x as uint;
+          if xu < start || xu > end {
+            out.push(*r);
+          } else {
+            if xu > start { out.push((s, (xu - 1 - start) as u16)); }
+            if xu < end { out.push(((xu + 1) as u16, (end - xu - 1) as u16)); }
+          }
+        }
+        replacement = Some(RunContainer(out));
+      }
+    }
+    match replacement {
+      Some(c) => *self = c,
+      None => ()
+    }
+    true
+  }
+
+  // the number of values <= x.
+  pure fn rank(x: u16) -> uint {
+    match self {
+      ArrayContainer(ref a) => {
+        let (found, i) = search_u16(*a, x);
+        if found { i + 1 } else { i }
+      }
+      BitmapContainer(ref words, _) => {
+        let w = (x as uint) / 32;
+        let mut n = 0;
+        for uint::range(0, w) |i| {
+          n += ctpop32(words[i]) as uint;
+        }
+        let below = (x as u32) % 32;
+        let mask = if below == 31 { 0xffffffff } else { (1 << (below + 1)) - 1 };
+        n + (ctpop32(words[w] & mask) as uint)
+      }
+      RunContainer(ref runs) => {
+        let mut n = 0;
+        for vec::each(*runs) |r| {
+          let (s, l) = *r;
+          if x < s { break; }
+          n += uint::min(x as uint - (s as uint), l as uint) + 1;
+        }
+        n
+      }
+    }
+  }
+
+  // the k-th (from 0) smallest value; k must be below the cardinality.
+  pure fn select(k: uint) -> u16 {
+    match self {
+      ArrayContainer(ref a) => a[k],
+      _ => {
+        let mut remaining = k;
+        let mut found = 0u16;
+        for self.each |x| {
+          if remaining == 0 {
+            found = x;
+            break;
+          }
+          remaining -= 1;
+        }
+        found
+      }
+    }
+  }
+
+  pure fn union(other: &Container) -> Container {
+    match self {
+      ArrayContainer(ref a) => match *other {
+        ArrayContainer(ref b) => {
+          let mut out = ~[];
+          let mut i = 0;
+          let mut j = 0;
+          while i < a.len() || j < b.len() {
+            if j == b.len() || (i < a.len() && a[i] < b[j]) {
+              out.push(a[i]); i += 1;
+            } else if i == a.len() || b[j] < a[i] {
+              out.push(b[j]); j += 1;
+            } else {
+              out.push(a[i]); i += 1; j += 1;
+            }
+          }
+          container_of_array(out)
+        }
+        BitmapContainer(*) => other.union(&self),
+        RunContainer(_) => container_of_words(vec::map2(self.to_words(), other.to_words(), |x, y| *x | *y))
+      },
+      BitmapContainer(ref words, card) => match *other {
+        ArrayContainer(ref b) => {
+          let mut w = copy *words;
+          let mut n = card;
+          for vec::each(*b) |x| {
+            let bit = 1 << ((*x as u32) % 32);
+            if w[(*x as uint) / 32] & bit == 0 {
+              w[(*x as uint) / 32] |= bit;
+              n += 1;
+            }
+          }
+          BitmapContainer(w, n)
+        }
+        _ => container_of_words(vec::map2(*words, other.to_words(), |x, y| *x | *y))
+      },
+      RunContainer(ref runs) => match *other {
+        RunContainer(ref theirs) => RunContainer(union_runs(*runs, *theirs)),
+        _ => other.union(&self)
+      }
+    }
+  }
+
+  pure fn intersection(other: &Container) -> Container {
+    match self {
+      ArrayContainer(ref a) => {
+        ArrayContainer(vec::filter(*a, |x| other.contains(*x)))
+      }
+      BitmapContainer(ref words, _) => match *other {
+        ArrayContainer(_) => other.intersection(&self),
+        _ => container_of_words(vec::map2(*words, other.to_words(), |x, y| *x & *y))
+      },
+      RunContainer(ref runs) => match *other {
+        RunContainer(ref theirs) => RunContainer(intersect_runs(*runs, *theirs)),
+        ArrayContainer(_) => other.intersection(&self),
+        BitmapContainer(*) => other.intersection(&self)
+      }
+    }
+  }
+
+  pure fn difference(other: &Container) -> Container {
+    match self {
+      ArrayContainer(ref a) => ArrayContainer(vec::filter(*a, |x| !other.contains(*x))),
+      _ => container_of_words(vec::map2(self.to_words(), other.to_words(), |x, y| *x & !*y))
+    }
+  }
+
+  pure fn symmetric_difference(other: &Container) -> Container {
+    container_of_words(vec::map2(self.to_words(), other.to_words(), |x, y| *x ^ *y))
+  }
+
+  // bytes taken by the container's body in the serialized format.
+  pure fn serialized_size() -> uint {
+    match self {
+      ArrayContainer(ref a) => 2 * a.len(),
+      BitmapContainer(*) => 4 * BITMAP_WORDS,
+      RunContainer(ref runs) => 2 + 4 * runs.len()
+    }
+  }
+
+  pure fn is_run() -> bool {
+    match self {
+      RunContainer(_) => true,
+      _ => false
+    }
+  }
+
+  // the smallest of the three representations; ties go to array or bitmap.
+  pure fn optimize() -> Container {
+    let runs = self.to_runs();
+    let card = self.cardinality();
+    let plain_size = if card > ARRAY_MAX { 4 * BITMAP_WORDS } else { 2 * card };
+    if 2 + 4 * runs.len() < plain_size {
+      RunContainer(runs)
+    } else {
+      match self {
+        RunContainer(_) => container_of_words(self.to_words()),
+        _ => copy self
+      }
+    }
+  }
+}
+
+/**
+ * A compressed bitmap over u32 values [Lemire et al. 2016]. Values are
+ * split by their high 16 bits into chunks, and each chunk keeps its low
+ * 16 bits in whichever container suits it, so sparse and dense regions
+ * both stay small and set operations mostly run a word at a time.
+ *
+ * `to_bytes` and `RoaringBitmap_from_bytes` speak the portable Roaring
+ * serialization format, so bitmaps can be exchanged with the Java, C and
+ * Go implementations.
+ */
+pub struct RoaringBitmap {
+  mut keys: ~[u16],
+  mut containers: ~[Container]
+}
+
+pure fn RoaringBitmap() -> RoaringBitmap {
+  RoaringBitmap { keys: ~[], containers: ~[] }
+}
+
+fn RoaringBitmap_from_vec(values: &[u32]) -> RoaringBitmap {
+  let bitmap = RoaringBitmap();
+  for vec::each(values) |x| {
+    bitmap.insert(*x);
+  }
+  bitmap
+}
+
+pure fn high(x: u32) -> u16 { (x >> 16) as u16 }
+pure fn low(x: u32) -> u16 { (x & 0xffff) as u16 }
+
+impl RoaringBitmap {
+  priv pure fn find(key: u16) -> (bool, uint) {
+    search_u16(self.keys, key)
+  }
+
+  // returns true if `x` wasn't already there.
+  fn insert(x: u32) -> bool {
+    let (found, i) = self.find(high(x));
+    if found {
+      return self.containers[i].insert(low(x));
+    }
+    self.keys = vec::slice(self.keys, 0, i) + ~[high(x)] + vec::slice(self.keys, i, self.keys.len());
+    self.containers = vec::slice(self.containers, 0, i) + ~[ArrayContainer(~[low(x)])] +
+      vec::slice(self.containers, i, self.containers.len());
+    true
+  }
+
+  // returns true if `x` was there.
+  fn remove(x: u32) -> bool {
+    let (found, i) = self.find(high(x));
+    if !found || !self.containers[i].remove(low(x)) {
+      return false;
+    }
+    if self.containers[i].cardinality() == 0 {
+      self.keys = vec::slice(self.keys, 0, i) + vec::slice(self.keys, i + 1, self.keys.len());
+      self.containers = vec::slice(self.containers, 0, i) + vec::slice(self.containers, i + 1, self.containers.len());
+    }
+    true
+  }
+
+  pure fn contains(x: u32) -> bool {
+    let (found, i) = self.find(high(x));
+    found && self.containers[i].contains(low(x))
+  }
+
+  pure fn len() -> uint {
+    let mut n = 0;
+    for vec::each(self.containers) |c| {
+      n += c.cardinality();
+    }
+    n
+  }
+
+  pure fn is_empty() -> bool {
+    self.keys.is_empty()
+  }
+
+  pure fn min() -> Option<u32> {
+    self.select(0)
+  }
+
+  pure fn max() -> Option<u32> {
+    if self.is_empty() {
+      return None;
+    }
+    let last = self.containers.len() - 1;
+    let c = &self.containers[last];
+    Some(((self.keys[last] as u32) << 16) | (c.select(c.cardinality() - 1) as u32))
+  }
+
+  // visits the values in ascending order.
+  pure fn each(f: fn(u32) -> bool) {
+    for uint::range(0, self.keys.len()) |i| {
+      let base = (self.keys[i] as u32) << 16;
+      if !self.containers[i].each(|x| f(base | (x as u32))) {
+        return;
+      }
+    }
+  }
+
+  // the number of values <= x.
+  pure fn rank(x: u32) -> uint {
+    let mut n = 0;
+    for uint::range(0, self.keys.len()) |i| {
+      if self.keys[i] < high(x) {
+        n += self.containers[i].cardinality();
+      } else {
+        if self.keys[i] == high(x) {
+          n += self.containers[i].rank(low(x));
+        }
+        break;
+      }
+    }
+    n
+  }
+
+  // the k-th (from 0) smallest value.
+  pure fn select(k: uint) -> Option<u32> {
+    let mut remaining = k;
+    for uint::range(0, self.keys.len()) |i| {
+      let card = self.containers[i].cardinality();
+      if remaining < card {
+        return Some(((self.keys[i] as u32) << 16) | (self.containers[i].select(remaining) as u32));
+      }
+      remaining -= card;
+    }
+    None
+  }
+
+  /**
+   * Walks the keys of both bitmaps in order and combines the containers
+   * that share a key with `both`. Containers only in self (or only in
+   * other) are kept when `keep_left` (or `keep_right`) is set.
+   */
+  priv pure fn merge(other: &RoaringBitmap, keep_left: bool, keep_right: bool,
+                     both: fn(&Container, &Container) -> Container) -> RoaringBitmap {
+    let mut keys = ~[];
+    let mut containers = ~[];
+    let mut i = 0;
+    let mut j = 0;
+    while i < self.keys.len() || j < other.keys.len() {
+      if j == other.keys.len() || (i < self.keys.len() && self.keys[i] < other.keys[j]) {
+        if keep_left {
+          keys.push(self.keys[i]);
+          containers.push(copy self.containers[i]);
+        }
+        i += 1;
+      } else if i == self.keys.len() || other.keys[j] < self.keys[i] {
+        if keep_right {
+          keys.push(other.keys[j]);
+          containers.push(copy other.containers[j]);
+        }
+        j += 1;
+      } else {
+        let c = both(&self.containers[i], &other.containers[j]);
+        if c.cardinality() > 0 {
+          keys.push(self.keys[i]);
+          containers.push(c);
+        }
+        i += 1;
+        j += 1;
+      }
+    }
+    RoaringBitmap { keys: keys, containers: containers }
+  }
+
+  pure fn union(other: &RoaringBitmap) -> RoaringBitmap {
+    self.merge(other, true, true, |a, b| a.union(b))
+  }
+
+  pure fn intersection(other: &RoaringBitmap) -> RoaringBitmap {
+    self.merge(other, false, false, |a, b| a.intersection(b))
+  }
+
+  pure fn difference(other: &RoaringBitmap) -> RoaringBitmap {
+    self.merge(other, true, false, |a, b| a.difference(b))
+  }
+
+  pure fn symmetric_difference(other: &RoaringBitmap) -> RoaringBitmap {
+    self.merge(other, true, true, |a, b| a.symmetric_difference(b))
+  }
+
+  // switches every container to its smallest representation, using run containers where they win.
+  fn optimize() {
+    self.containers = vec::map(self.containers, |c| c.optimize());
+  }
+
+  pure fn to_vec() -> ~[u32] {
+    let mut v = ~[];
+    for self.each |x| { v.push(x); }
+    v
+  }
+
+  // the portable Roaring serialization of the bitmap.
+  fn to_bytes() -> ~[u8] {
+    let n = self.keys.len();
+    let has_runs = vec::any(self.containers, |c| c.is_run());
+    let mut out = ~[];
+
+    let header_size;
+    if has_runs {
+      push_u16_le(&mut out, SERIAL_COOKIE as u16);
+      push_u16_le(&mut out, (n - 1) as u16);
+      let mut flags = vec::from_elem((n + 7) / 8, 0u8);
+      for uint::range(0, n) |i| {
+        if self.containers[i].is_run() {
+          flags[i / 8] |= 1 << ((i % 8) as u8);
+        }
+      }
+      out += flags;
+      header_size = 4 + (n + 7) / 8 + 4 * n + if n >= NO_OFFSET_THRESHOLD { 4 * n } else { 0 };
+    } else {
+      push_u32_le(&mut out, SERIAL_COOKIE_NO_RUNCONTAINER);
+      push_u32_le(&mut out, n as u32);
+      header_size = 8 + 8 * n;
+    }
+
+    for uint::range(0, n) |i| {
+      push_u16_le(&mut out, self.keys[i]);
+      push_u16_le(&mut out, (self.containers[i].cardinality() - 1) as u16);
+    }
+
+    if !has_runs || n >= NO_OFFSET_THRESHOLD {
+      let mut offset = header_size;
+      for vec::each(self.containers) |c| {
+        push_u32_le(&mut out, offset as u32);
+        offset += c.serialized_size();
+      }
+    }
+
+    for vec::each(self.containers) |c| {
+      match *c {
+        ArrayContainer(ref a) => for vec::each(*a) |x| { push_u16_le(&mut out, *x); },
+        // 1024 little-endian u64s have the same bytes as these 2048 u32s.
+        BitmapContainer(ref words, _) => for vec::each(*words) |w| { push_u32_le(&mut out, *w); },
+        RunContainer(ref runs) => {
+          push_u16_le(&mut out, runs.len() as u16);
+          for vec::each(*runs) |r| {
+            push_u16_le(&mut out, r.first());
+            push_u16_le(&mut out, r.second());
+          }
+        }
+      }
+    }
+    out
+  }
+}
+
+fn RoaringBitmap_from_bytes(buf: &[u8]) -> Option<RoaringBitmap> {
+  let mut pos = 0;
+  let cookie = match read_u32_le(buf, &mut pos) { Some(c) => c, None => return None };
+
+  let n;
+  let mut run_flags = ~[];
+  if cookie == SERIAL_COOKIE_NO_RUNCONTAINER {
+    n = match read_u32_le(buf, &mut pos) { Some(n) => n as uint, None => return None };
+  } else if cookie & 0xffff == SERIAL_COOKIE {
+    n = ((cookie >> 16) as uint) + 1;
+    if pos + (n + 7) / 8 > buf.len() {
+      return None;
+    }
+    run_flags = vec::slice(buf, pos, pos + (n + 7) / 8);
+    pos += (n + 7) / 8;
+  } else {
+    return None;
+  }
+  let is_run = |i: uint| run_flags.len() > 0 && run_flags[i / 8] & (1 << ((i % 8) as u8)) != 0;
+
+  let mut keys = ~[];
+  let mut cards = ~[];
+  for uint::range(0, n) |_| {
+    match (read_u16_le(buf, &mut pos), read_u16_le(buf, &mut pos)) {
+      (Some(key), Some(card)) => {
+        // lookups binary search the keys, so they must be strictly increasing.
+        if !keys.is_empty() && key <= keys[keys.len() - 1] {
+          return None;
+        }
+        keys.push(key);
+        cards.push((card as uint) + 1);
+      }
+      _ => return None
+    }
+  }
+
+  // the offsets are only an index; the containers follow one another anyway.
+  if run_flags.is_empty() || n >= NO_OFFSET_THRESHOLD {
+    if pos + 4 * n > buf.len() {
+      return None;
+    }
+    pos += 4 * n;
+  }
+
+  let mut containers = ~[];
+  for uint::range(0, n) |i| {
+    if is_run(i) {
+      let num_runs = match read_u16_le(buf, &mut pos) { Some(r) => r as uint, None => return None };
+      let mut runs = ~[];
+      // one past the end of the last run, so runs can't overlap or touch.
+      let mut next = 0;
+      for uint::range(0, num_runs) |_| {
+        match (read_u16_le(buf, &mut pos), read_u16_le(buf, &mut pos)) {
+          (Some(s), Some(l)) => {
+            if (s as uint) < next || (s as uint) + (l as uint) > 0xffff {
+              return None;
+            }
+            next = (s as uint) + (l as uint) + 2;
+            runs.push((s, l));
+          }
+          _ => return None
+        }
+      }
+      let run = RunContainer(runs);
+      if run.cardinality() != cards[i] {
+        return None;
+      }
+      containers.push(run);
+    } else if cards[i] <= ARRAY_MAX {
+      let mut a = ~[];
+      for uint::range(0, cards[i]) |_| {
+        match read_u16_le(buf, &mut pos) {
+          Some(x) => {
+            if !a.is_empty() && x <= a[a.len() - 1] {
+              return None;
+            }
+            a.push(x);
+          }
+          None => return None
+        }
+      }
+      containers.push(ArrayContainer(a));
+    } else {
+      let mut words = ~[];
+      for uint::range(0, BITMAP_WORDS) |_| {
+        match read_u32_le(buf, &mut pos) { Some(w) => words.push(w), None => return None }
+      }
+      // the header's count is what cardinality() reports, so it has to be right.
+      if count_words(words) != cards[i] {
+        return None;
+      }
+      containers.push(BitmapContainer(words, cards[i]));
+    }
+  }
+
+  if pos != buf.len() {
+    return None;
+  }
+  Some(RoaringBitmap { keys: keys, containers: containers })
+}
+
+
+#[cfg(test)]
+mod tests {
+  use bit_set::count_words;
+  use bytes::push_u32_le;
+
+  // sparse values over the whole range, a dense chunk and a long run.
+  fn mixed(seed: uint) -> ~[u32] {
+    let mut v = ~[];
+    let mut x = seed;
+    for uint::range(0, 2000) |_| {
+      x = (x * 1103515245 + 12345) % 2147483648;
+      v.push((x as u32) * 2);
+    }
+    for uint::range(0, 10000) |_| {
+      x = (x * 1103515245 + 12345) % 2147483648;
+      v.push(0x50000 + ((x >> 8) % 40000) as u32);
+    }
+    for uint::range(0, 20000) |i| {
+      v.push(0x90000 + (seed * 1000 + i) as u32);
+    }
+    v
+  }
+
+  fn sorted_distinct(v: &[u32]) -> ~[u32] {
+    let sorted = std::sort::merge_sort(|a, b| *a <= *b, v);
+    let mut out = ~[];
+    for vec::each(sorted) |x| {
+      if out.is_empty() || out[out.len() - 1] != *x {
+        out.push(*x);
+      }
+    }
+    out
+  }
+
+  // applies `f` to membership in the sorted, distinct `a` and `b`.
+  fn naive_op(a: &[u32], b: &[u32], f: fn(bool, bool) -> bool) -> ~[u32] {
+    let mut out = ~[];
+    let mut i = 0;
+    let mut j = 0;
+    while i < a.len() || j < b.len() {
+      let x = if j == b.len() || (i < a.len() && a[i] < b[j]) { a[i] } else { b[j] };
+      let in_a = i < a.len() && a[i] == x;
+      let in_b = j < b.len() && b[j] == x;
+      if f(in_a, in_b) {
+        out.push(x);
+      }
+      if in_a { i += 1; }
+      if in_b { j += 1; }
+    }
+    out
+  }
+
+  #[test]
+  fn test_insert_contains_remove() {
+    let bitmap = RoaringBitmap();
+    assert(bitmap.insert(5));
+    assert(!bitmap.insert(5));
+    assert(bitmap.insert(0xffffffff));
+    assert(bitmap.insert(70000));
+    assert(bitmap.len() == 3);
+    assert(bitmap.to_vec() == ~[5, 70000, 0xffffffff]);
+    assert(bitmap.min() == Some(5));
+    assert(bitmap.max() == Some(0xffffffff));
+
+    // past 4096 values the chunk becomes a bitmap, and back again below it.
+    for uint::range(0, 5000) |i| {
+      bitmap.insert((i * 3) as u32);
+    }
+    match bitmap.containers[0] { BitmapContainer(*) => (), _ => fail }
+    for uint::range(0, 1000) |i| {
+      bitmap.remove((i * 3) as u32);
+    }
+    match bitmap.containers[0] { ArrayContainer(*) => (), _ => fail }
+    assert(bitmap.len() == 4001 + 2);
+    assert(bitmap.contains(5) && !bitmap.contains(3) && bitmap.contains(3000));
+
+    assert(bitmap.remove(70000));
+    assert(!bitmap.remove(70000));
+    assert(bitmap.keys.len() == 2);
+  }
+
+  #[test]
+  fn test_in_place_edits_keep_cardinality() {
+    let mut c = ArrayContainer(~[]);
+    for uint::range(0, 6000) |i| {
+      assert(c.insert((i * 7 % 65536) as u16));
+    }
+    assert(!c.insert(7));
+    for uint::range(0, 500) |i| {
+      assert(c.remove((i * 7) as u16));
+    }
+    assert(!c.remove(7));
+    match c {
+      BitmapContainer(ref words, card) => {
+        assert(card == 5500);
+        assert(card == count_words(*words));
+      }
+      _ => fail
+    }
+    assert(c.to_words() == words_of_array(vec::from_fn(5500, |i| ((i + 500) * 7) as u16)));
+  }
+
+  #[test]
+  fn test_set_operations_match_naive() {
+    let xs = sorted_distinct(mixed(1));
+    let ys = sorted_distinct(mixed(2));
+    let a = RoaringBitmap_from_vec(xs);
+    let b = RoaringBitmap_from_vec(ys);
+    assert(a.to_vec() == xs);
+
+    for uint::range(0, 2) |round| {
+      if round == 1 {
+        a.optimize();
+        b.optimize();
+        assert(vec::any(a.containers, |c| c.is_run()));
+      }
+      assert(a.union(&b).to_vec() == naive_op(xs, ys, |x, y| x || y));
+      assert(a.intersection(&b).to_vec() == naive_op(xs, ys, |x, y| x && y));
+      assert(a.difference(&b).to_vec() == naive_op(xs, ys, |x, y| x && !y));
+      assert(a.symmetric_difference(&b).to_vec() == naive_op(xs, ys, |x, y| x != y));
+      assert(a.union(&b).len() == naive_op(xs, ys, |x, y| x || y).len());
+    }
+  }
+
+  #[test]
+  fn test_rank_and_select() {
+    let xs = mixed(3);
+    let bitmap = RoaringBitmap_from_vec(xs);
+    bitmap.optimize();
+    let sorted = sorted_distinct(xs);
+
+    for vec::eachi(sorted) |i, x| {
+      if i % 7 == 0 {
+        assert(bitmap.select(i) == Some(*x));
+        assert(bitmap.rank(*x) == i + 1);
+        if i == 0 || sorted[i - 1] != *x - 1 {
+          assert(bitmap.rank(*x - 1) == i);
+        }
+      }
+    }
+    assert(bitmap.select(sorted.len()).is_none());
+    assert(bitmap.rank(0xffffffff) == sorted.len());
+  }
+
+  #[test]
+  fn test_portable_format_bytes() {
+    let small = RoaringBitmap_from_vec(~[1, 2, 3]);
+    assert(small.to_bytes() == ~[0x3a, 0x30, 0, 0,  1, 0, 0, 0,  0, 0, 2, 0,  16, 0, 0, 0,
+                                 1, 0, 2, 0, 3, 0]);
+
+    let run = RoaringBitmap();
+    for uint::range(0, 100) |i| { run.insert(i as u32); }
+    run.optimize();
+    assert(run.to_bytes() == ~[0x3b, 0x30, 0, 0,  1,  0, 0, 99, 0,  1, 0, 0, 0, 99, 0]);
+
+    assert(RoaringBitmap().to_bytes() == ~[0x3a, 0x30, 0, 0, 0, 0, 0, 0]);
+  }
+
+  #[test]
+  fn test_serialization_round_trip() {
+    let bitmap = RoaringBitmap_from_vec(mixed(4));
+    let plain = bitmap.to_bytes();
+    let restored = RoaringBitmap_from_bytes(plain).get();
+    assert(restored.to_vec() == bitmap.to_vec());
+
+    bitmap.optimize();
+    let with_runs = bitmap.to_bytes();
+    assert(with_runs.len() < plain.len());
+    let restored = RoaringBitmap_from_bytes(with_runs).get();
+    assert(restored.to_vec() == bitmap.to_vec());
+
+    assert(RoaringBitmap_from_bytes(vec::slice(plain, 0, plain.len() - 1)).is_none());
+    assert(RoaringBitmap_from_bytes(~[1, 2, 3, 4]).is_none());
+  }
+
+  #[test]
+  fn test_from_bytes_checks_the_header() {
+    let good = RoaringBitmap_from_vec(~[1, 2, 3]).to_bytes();
+    assert(RoaringBitmap_from_bytes(good).is_some());
+
+    // the array {1, 3, 2} isn't increasing.
+    let mut unsorted = copy good;
+    unsorted[18] = 3;
+    unsorted[20] = 2;
+    assert(RoaringBitmap_from_bytes(unsorted).is_none());
+
+    // a header claiming 5000 values, with a bitmap that only holds 3.
+    let mut dense = ~[0x3a, 0x30, 0, 0,  1, 0, 0, 0,  0, 0, 0x87, 0x13,  16, 0, 0, 0];
+    for uint::range(0, BITMAP_WORDS) |i| {
+      push_u32_le(&mut dense, if i == 0 { 0xe } else { 0 });
+    }
+    assert(RoaringBitmap_from_bytes(dense).is_none());
+
+    // a run container whose runs hold 100 values, under a header claiming 99.
+    let runs = RoaringBitmap();
+    for uint::range(0, 100) |i| { runs.insert(i as u32); }
+    runs.optimize();
+    let mut short = runs.to_bytes();
+    short[7] = 98;
+    assert(RoaringBitmap_from_bytes(short).is_none());
+
+    // a header that ends before its offsets.
+    assert(RoaringBitmap_from_bytes(~[0x3a, 0x30, 0, 0,  1, 0, 0, 0,  0, 0, 0, 0]).is_none());
+  }
+}
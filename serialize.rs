@@ -0,0 +1,265 @@
+/**
+ * std::serialization support for the persistent collections, compiled
+ * with `--cfg serialize`.
+ *
+ * Maps are written as a vector of (key, value) pairs in ascending key
+ * order and sets as a vector of keys, so any serializer sees an ordered
+ * map. Reading them back checks the keys are strictly increasing and, if
+ * so, builds the tree bottom up in linear time; anything else (a file
+ * written by hand, say) falls back to inserting entries one at a time.
+ *
+ * Heaps are written as their elements in no particular order. Zippers
+ * are written as (position, items), so the cursor survives a round trip.
+ */
+use core::cmp::{Eq, Ord};
+use std::serialization::{Serializer, Deserializer, Serializable, Deserializable};
+use tree;
+use tree::{Tree, Tree_from_sorted};
+use red_black_tree::{RBMap, RBMap_empty, RBMap_from_sorted};
+use pairing_heap::{PairingHeap, Heap, MinOrder, MaxOrder, MinFirst, MaxFirst, Comparator,
+                   PairingHeap_with_comparator};
+use list_zipper::{ListZipper, ListZipper_from_vec_at};
+use rb_set::RBSet;
+use tree_set::TreeSet;
+use sorted_set::SortedSet;
+use persistent_map::*;
+
+pure fn strictly_increasing<K: Copy Eq Ord, V: Copy>(entries: &[(K, V)]) -> bool {
+  for uint::range(1, entries.len()) |i| {
+    if !(entries[i - 1].first() < entries[i].first()) {
+      return false;
+    }
+  }
+  true
+}
+
+pure fn entries_of<K: Copy Eq Ord, V: Copy, M: PersistentMap<K, V>>(m: &M) -> ~[(K, V)] {
+  let mut entries = ~[];
+  for m.iter |k, v| {
+    entries.push((*k, *v));
+  }
+  entries
+}
+
+pure fn with_unit<K: Copy>(keys: &[K]) -> ~[(K, ())] {
+  vec::map(keys, |k| (*k, ()))
+}
+
+pure fn tree_of_entries<K: Copy Eq Ord, V: Copy>(entries: &[(K, V)]) -> Tree<K, V> {
+  if strictly_increasing(entries) {
+    return Tree_from_sorted(entries);
+  }
+  let mut built = tree::Empty();
+  for vec::each(entries) |e| {
+    let (k, v) = *e;
+    built = built.insert(k, v);
+  }
+  built
+}
+
+pure fn rbmap_of_entries<K: Copy Eq Ord, V: Copy>(entries: &[(K, V)]) -> @RBMap<K, V> {
+  if strictly_increasing(entries) {
+    return RBMap_from_sorted(entries);
+  }
+  let mut map = RBMap_empty();
+  for vec::each(entries) |e| {
+    let (k, v) = *e;
+    map = map.put(k, v);
+  }
+  map
+}
+
+pub impl<S: Serializer, K: Copy Eq Ord Serializable<S>, V: Copy Serializable<S>> Tree<K, V> : Serializable<S> {
+  fn serialize(&self, s: &S) {
+    entries_of(self).serialize(s)
+  }
+}
+
+pub impl<D: Deserializer, K: Copy Eq Ord Deserializable<D>, V: Copy Deserializable<D>> Tree<K, V> : Deserializable<D> {
+  static fn deserialize(&self, d: &D) -> Tree<K, V> {
+    let entries : ~[(K, V)] = Deserializable::deserialize(d);
+    tree_of_entries(entries)
+  }
+}
+
+pub impl<S: Serializer, K: Copy Eq Ord Serializable<S>, V: Copy Serializable<S>> @RBMap<K, V> : Serializable<S> {
+  fn serialize(&self, s: &S) {
+    entries_of(self).serialize(s)
+  }
+}
+
+pub impl<D: Deserializer, K: Copy Eq Ord Deserializable<D>, V: Copy Deserializable<D>> @RBMap<K, V> : Deserializable<D> {
+  static fn deserialize(&self, d: &D) -> @RBMap<K, V> {
+    let entries : ~[(K, V)] = Deserializable::deserialize(d);
+    rbmap_of_entries(entries)
+  }
+}
+
+pub impl<S: Serializer, K: Copy Eq Ord Serializable<S>, M: Copy OrderedPersistentMap<K, ()>> SortedSet<K, M> : Serializable<S> {
+  fn serialize(&self, s: &S) {
+    self.to_vec().serialize(s)
+  }
+}
+
+pub impl<D: Deserializer, K: Copy Eq Ord Deserializable<D>> RBSet<K> : Deserializable<D> {
+  static fn deserialize(&self, d: &D) -> RBSet<K> {
+    let keys : ~[K] = Deserializable::deserialize(d);
+    SortedSet { map: rbmap_of_entries(with_unit(keys)) }
+  }
+}
+
+pub impl<D: Deserializer, K: Copy Eq Ord Deserializable<D>> TreeSet<K> : Deserializable<D> {
+  static fn deserialize(&self, d: &D) -> TreeSet<K> {
+    let keys : ~[K] = Deserializable::deserialize(d);
+    SortedSet { map: tree_of_entries(with_unit(keys)) }
+  }
+}
+
+pub impl<S: Serializer, E: Copy Serializable<S>, C: Copy Comparator<E>> PairingHeap<E, C> : Serializable<S> {
+  fn serialize(&self, s: &S) {
+    let mut elems = ~[];
+    for self.each_unordered |e| {
+      elems.push(*e);
+    }
+    elems.serialize(s)
+  }
+}
+
+// the comparator isn't written out, so only the stock orders can be read back.
+fn heap_of_elems<E: Copy, C: Copy Comparator<E>>(cmp: C, elems: &[E]) -> PairingHeap<E, C> {
+  let mut heap = PairingHeap_with_comparator(cmp);
+  for vec::each(elems) |e| {
+    heap = heap.insert(*e);
+  }
+  heap
+}
+
+pub impl<D: Deserializer, E: Copy Ord Deserializable<D>> PairingHeap<E, MinOrder> : Deserializable<D> {
+  static fn deserialize(&self, d: &D) -> PairingHeap<E, MinOrder> {
+    let elems : ~[E] = Deserializable::deserialize(d);
+    heap_of_elems(MinFirst, elems)
+  }
+}
+
+pub impl<D: Deserializer, E: Copy Ord Deserializable<D>> PairingHeap<E, MaxOrder> : Deserializable<D> {
+  static fn deserialize(&self, d: &D) -> PairingHeap<E, MaxOrder> {
+    let elems : ~[E] = Deserializable::deserialize(d);
+    heap_of_elems(MaxFirst, elems)
+  }
+}
+
+pub impl<S: Serializer, E: Copy Serializable<S>> ListZipper<E> : Serializable<S> {
+  fn serialize(&self, s: &S) {
+    (self.position(), self.to_vec()).serialize(s)
+  }
+}
+
+pub impl<D: Deserializer, E: Copy Deserializable<D>> ListZipper<E> : Deserializable<D> {
+  static fn deserialize(&self, d: &D) -> ListZipper<E> {
+    let (position, items) : (uint, ~[E]) = Deserializable::deserialize(d);
+    ListZipper_from_vec_at(items, position)
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use std::ebml;
+  use std::serialization::{Serializable, Deserializable};
+  use tree;
+  use tree::{Tree, Tree_, Empty_};
+  use red_black_tree::{RBMap, RBMap_empty};
+  use pairing_heap::{PairingHeap, MaxHeap, Heap, MinOrder, MaxOrder};
+  use list_zipper::{ListZipper, ListZipper_from_vec};
+  use rb_set::{RBSet, RBSet_from_vec};
+  use tree_set::{TreeSet, TreeSet_from_vec};
+  use persistent_map::*;
+
+  fn round_trip<T: Serializable<ebml::Serializer> Deserializable<ebml::Deserializer>>(value: &T) -> T {
+    let bytes = do io::with_bytes_writer |wr| {
+      let s = ebml::Serializer(wr);
+      value.serialize(&s);
+    };
+    let d = ebml::Deserializer(ebml::Doc(@bytes));
+    Deserializable::deserialize(&d)
+  }
+
+  #[test]
+  fn test_maps_round_trip() {
+    let mut rb = RBMap_empty();
+    let mut bst = tree::Empty();
+    for uint::range(0, 500) |i| {
+      let k = ((i * 7919) % 500) as int;
+      rb = rb.put(k, ~"v" + int::str(k));
+      bst = bst.insert(k, k * 2);
+    }
+    rb = rb.delete(13);
+
+    let rb2 : @RBMap<int, ~str> = round_trip(&rb);
+    assert(rb2.len() == 499);
+    assert(entries_of(&rb2) == entries_of(&rb));
+    assert(rb2.get(13) == None);
+
+    let bst2 : Tree<int, int> = round_trip(&bst);
+    assert(bst2.len() == 500);
+    assert(entries_of(&bst2) == entries_of(&bst));
+    // a bulk built tree is as shallow as it gets: the 250th entry is the root.
+    match bst2 {
+      Tree_(node) => assert(node.key == 250),
+      Empty_ => fail
+    }
+  }
+
+  #[test]
+  fn test_unsorted_input_still_builds() {
+    let entries = ~[(3, 30), (1, 10), (2, 20), (1, 11)];
+    let m = rbmap_of_entries(entries);
+    assert(m.len() == 3);
+    assert(m.get(1) == Some(11));
+    assert(tree_of_entries(entries).get(3) == Some(30));
+  }
+
+  #[test]
+  fn test_sets_round_trip() {
+    let rb = RBSet_from_vec(~[5, 3, 9, 1]);
+    let rb2 : RBSet<int> = round_trip(&rb);
+    assert(rb2.to_vec() == ~[1, 3, 5, 9]);
+
+    let ts = TreeSet_from_vec(~[~"b", ~"a"]);
+    let ts2 : TreeSet<~str> = round_trip(&ts);
+    assert(ts2.to_vec() == ~[~"a", ~"b"]);
+  }
+
+  #[test]
+  fn test_heaps_round_trip() {
+    let mut min = PairingHeap(5);
+    let mut max = MaxHeap();
+    for vec::each(~[8, 1, 9, 3]) |x| {
+      min = min.insert(*x);
+      max = max.insert(*x);
+    }
+
+    let mut min2 : PairingHeap<int, MinOrder> = round_trip(&min);
+    let mut drained = ~[];
+    while !min2.is_empty() {
+      let (e, rest) = min2.pop();
+      drained.push(e.get());
+      min2 = rest;
+    }
+    assert(drained == ~[1, 3, 5, 8, 9]);
+
+    let max2 : PairingHeap<int, MaxOrder> = round_trip(&max);
+    assert(max2.peek() == Some(9));
+  }
+
+  #[test]
+  fn test_zipper_keeps_its_cursor() {
+    let zipper = ListZipper_from_vec(~[1, 2, 3, 4]).go_right().go_right().go_right();
+    assert(zipper.cursor() == Some(3));
+
+    let zipper2 : ListZipper<int> = round_trip(&zipper);
+    assert(zipper2.cursor() == Some(3));
+    assert(zipper2.position() == 3);
+    assert(zipper2.to_vec() == ~[1, 2, 3, 4]);
+  }
+}
@@ -0,0 +1,384 @@
+use fnv1a::fnv1a_64;
+use lzc::lzc64;
+use bytes::push_u32_le;
+use red_black_tree::{RBMap, RBMap_empty};
+use persistent_map::*;
+
+/**
+ * A HyperLogLog distinct-count estimator [Flajolet et al. 2007]. Items are
+ * hashed with FNV-1a 64; the top `precision` bits pick one of 2^precision
+ * registers and the register keeps the longest run of leading zeros seen
+ * in the rest of the hash. The standard error is about
+ * 1.04 / sqrt(2^precision), so precision 12 (4KB of registers) gives ~1.6%.
+ */
+pub struct HyperLogLog {
+  mut registers: ~[u8],
+  precision: uint
+}
+
+// `precision` must be between 4 and 16.
+pure fn HyperLogLog(precision: uint) -> HyperLogLog {
+  assert(precision >= 4 && precision <= 16);
+  HyperLogLog { registers: vec::from_elem(1 << precision, 0u8), precision: precision }
+}
+
+impl HyperLogLog {
+  fn insert(data: &[u8]) {
+    self.insert_hash(fnv1a_64(vec::from_slice(data)));
+  }
+
+  fn insert_hash(hash: u64) {
+    let p = self.precision;
+    let index = (hash >> ((64 - p) as u64)) as uint;
+    let rest = hash << (p as u64);
+    // the position of the first 1 bit among the remaining 64 - p bits.
+    let zeros = lzc64(rest) as uint;
+    let rank = (if zeros > 64 - p { 64 - p } else { zeros }) + 1;
+    if (rank as u8) > self.registers[index] {
+      self.registers[index] = rank as u8;
+    }
+  }
+
+  pure fn num_registers() -> uint {
+    self.registers.len()
+  }
+
+  /**
+   * The estimated number of distinct items inserted. The raw harmonic-mean
+   * estimate is biased upwards for small counts, so below 2.5m it is
+   * replaced with linear counting over the empty registers. A 64-bit hash
+   * makes the large-range correction of the original paper unnecessary.
+   */
+  pure fn estimate() -> f64 {
+    let m = self.num_registers() as f64;
+    let mut sum = 0f64;
+    let mut zeros = 0;
+    for vec::each(self.registers) |r| {
+      sum += 1f64 / f64::pow(2f64, *r as f64);
+      if *r == 0 {
+        zeros += 1;
+      }
+    }
+
+    let raw = alpha(self.num_registers()) * m * m / sum;
+    if raw <= 2.5 * m && zeros > 0 {
+      m * f64::ln(m / (zeros as f64))
+    } else {
+      raw
+    }
+  }
+
+  // an estimator for the union of both inputs; None if the precisions differ.
+  pure fn merge(other: &HyperLogLog) -> Option<HyperLogLog> {
+    if self.precision != other.precision {
+      return None;
+    }
+    let mut registers = ~[];
+    for uint::range(0, self.registers.len()) |i| {
+      registers.push(if self.registers[i] > other.registers[i] { self.registers[i] } else { other.registers[i] });
+    }
+    Some(HyperLogLog { registers: registers, precision: self.precision })
+  }
+}
+
+// the bias correction constant for m registers.
+pure fn alpha(m: uint) -> f64 {
+  match m {
+    16 => 0.673,
+    32 => 0.697,
+    64 => 0.709,
+    _ => 0.7213 / (1f64 + 1.079 / (m as f64))
+  }
+}
+
+/**
+ * A Count-Min sketch [Cormode, Muthukrishnan 2005]: `depth` rows of
+ * `width` counters. Each insert bumps one counter per row and an item's
+ * frequency is estimated by the smallest of its counters. Every row
+ * hashes the item with its own seed, so rows collide independently and
+ * estimates never fall below the true count and, with probability
+ * 1 - delta, exceed it by at most epsilon times the total count.
+ */
+pub struct CountMinSketch {
+  mut counters: ~[u64],
+  mut total: u64,
+  width: uint,
+  depth: uint
+}
+
+pure fn CountMinSketch(width: uint, depth: uint) -> CountMinSketch {
+  CountMinSketch {
+    counters: vec::from_elem(width * depth, 0u64),
+    total: 0,
+    width: width,
+    depth: depth
+  }
+}
+
+// a sketch with width e / epsilon and depth ln(1 / delta); both must be between 0 and 1.
+pure fn CountMinSketch_with_error(epsilon: f64, delta: f64) -> CountMinSketch {
+  // written so that NaN fails too.
+  if !(epsilon > 0f64 && epsilon < 1f64 && delta > 0f64 && delta < 1f64) {
+    fail fmt!("CountMinSketch_with_error(%?, %?): epsilon and delta must be between 0 and 1", epsilon, delta);
+  }
+  let width = f64::ceil(f64::consts::e / epsilon) as uint;
+  let depth = f64::ceil(f64::ln(1f64 / delta)) as uint;
+  CountMinSketch(width, if depth < 1 { 1 } else { depth })
+}
+
+// the counter `data` maps to in each of `depth` rows, hashing it with the row number as a seed.
+pure fn count_min_indices(data: &[u8], width: uint, depth: uint) -> ~[uint] {
+  let mut indices = ~[];
+  for uint::range(0, depth) |row| {
+    let mut seeded = ~[];
+    push_u32_le(&mut seeded, row as u32);
+    seeded += data;
+    indices.push((fnv1a_64(seeded) % (width as u64)) as uint);
+  }
+  indices
+}
+
+impl CountMinSketch {
+  fn insert(data: &[u8]) {
+    self.insert_n(data, 1);
+  }
+
+  fn insert_n(data: &[u8], count: u64) {
+    for vec::eachi(count_min_indices(data, self.width, self.depth)) |row, i| {
+      self.counters[row * self.width + *i] += count;
+    }
+    self.total += count;
+  }
+
+  pure fn estimate(data: &[u8]) -> u64 {
+    let mut best = u64::max_value;
+    for vec::eachi(count_min_indices(data, self.width, self.depth)) |row, i| {
+      let c = self.counters[row * self.width + *i];
+      if c < best {
+        best = c;
+      }
+    }
+    best
+  }
+
+  // the sum of every count inserted.
+  pure fn total() -> u64 {
+    self.total
+  }
+
+  // a sketch of both streams together; None if the shapes differ.
+  pure fn merge(other: &CountMinSketch) -> Option<CountMinSketch> {
+    if self.width != other.width || self.depth != other.depth {
+      return None;
+    }
+    let mut counters = ~[];
+    for uint::range(0, self.counters.len()) |i| {
+      counters.push(self.counters[i] + other.counters[i]);
+    }
+    Some(CountMinSketch {
+      counters: counters,
+      total: self.total + other.total,
+      width: self.width,
+      depth: self.depth
+    })
+  }
+}
+
+/**
+ * Tracks the items making up at least `phi` of a stream, using a
+ * Count-Min sketch for the frequencies and an RBMap of candidate items.
+ * Candidates that fall below the threshold are pruned as the stream grows.
+ */
+pub struct HeavyHitters {
+  sketch: CountMinSketch,
+  mut candidates: @RBMap<~[u8], u64>,
+  mut num_candidates: uint,
+  phi: f64
+}
+
+pure fn HeavyHitters(phi: f64, epsilon: f64, delta: f64) -> HeavyHitters {
+  HeavyHitters {
+    sketch: CountMinSketch_with_error(epsilon, delta),
+    candidates: RBMap_empty(),
+    num_candidates: 0,
+    phi: phi
+  }
+}
+
+impl HeavyHitters {
+  priv pure fn threshold() -> f64 {
+    self.phi * (self.sketch.total() as f64)
+  }
+
+  fn insert(data: &[u8]) {
+    self.sketch.insert(data);
+    let estimate = self.sketch.estimate(data);
+    if (estimate as f64) >= self.threshold() {
+      let (old, candidates) = self.candidates.insert_returning_old(vec::from_slice(data), estimate);
+      self.candidates = candidates;
+      if old.is_none() {
+        self.num_candidates += 1;
+      }
+    }
+    if (self.num_candidates as f64) > 2f64 / self.phi {
+      self.prune();
+    }
+  }
+
+  priv fn prune() {
+    let mut kept = RBMap_empty();
+    let mut n = 0;
+    for self.candidates.keys |key| {
+      let estimate = self.sketch.estimate(*key);
+      if (estimate as f64) >= self.threshold() {
+        kept = kept.put(copy *key, estimate);
+        n += 1;
+      }
+    }
+    self.candidates = kept;
+    self.num_candidates = n;
+  }
+
+  // visits the items currently above the threshold and their estimated counts, in key order.
+  pure fn each(f: fn(&[u8], u64) -> bool) {
+    for self.candidates.keys |key| {
+      let estimate = self.sketch.estimate(*key);
+      if (estimate as f64) >= self.threshold() {
+        if !f(*key, estimate) {
+          return;
+        }
+      }
+    }
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  fn item(i: uint) -> ~[u8] {
+    str::to_bytes(fmt!("user-%u", i))
+  }
+
+  fn relative_error(estimate: f64, exact: uint) -> f64 {
+    f64::abs(estimate - (exact as f64)) / (exact as f64)
+  }
+
+  #[test]
+  fn test_hll_accuracy() {
+    for vec::each(~[10u, 1000u, 100000u]) |n| {
+      let hll = HyperLogLog(12);
+      for uint::range(0, *n) |i| {
+        hll.insert(item(i));
+        // duplicates don't count.
+        hll.insert(item(i));
+      }
+      assert(relative_error(hll.estimate(), *n) < 0.05);
+    }
+    assert(HyperLogLog(8).estimate() == 0f64);
+  }
+
+  #[test]
+  fn test_hll_merge() {
+    let a = HyperLogLog(12);
+    let b = HyperLogLog(12);
+    for uint::range(0, 30000) |i| { a.insert(item(i)); }
+    for uint::range(20000, 50000) |i| { b.insert(item(i)); }
+
+    let both = a.merge(&b).get();
+    assert(relative_error(both.estimate(), 50000) < 0.05);
+    assert(a.merge(&HyperLogLog(10)).is_none());
+  }
+
+  #[test]
+  fn test_count_min_against_exact_counts() {
+    let epsilon = 0.001;
+    let sketch = CountMinSketch_with_error(epsilon, 0.01);
+
+    // item i appears 1000 / (i + 1) times, a Zipf-like stream.
+    let mut exact = ~[];
+    for uint::range(0, 2000) |i| {
+      let count = 1000 / (i + 1);
+      sketch.insert_n(item(i), count as u64);
+      exact.push(count);
+    }
+
+    let bound = (epsilon * (sketch.total() as f64)) as u64;
+    let mut within = 0;
+    for vec::eachi(exact) |i, count| {
+      let estimate = sketch.estimate(item(i));
+      assert(estimate >= *count as u64);
+      if estimate <= (*count as u64) + bound {
+        within += 1;
+      }
+    }
+    assert(within >= 1980);
+  }
+
+  #[test]
+  fn test_count_min_merge() {
+    let a = CountMinSketch(256, 4);
+    let b = CountMinSketch(256, 4);
+    a.insert_n(str::to_bytes("stevej"), 5);
+    b.insert_n(str::to_bytes("stevej"), 7);
+    b.insert(str::to_bytes("thatstacy"));
+
+    let both = a.merge(&b).get();
+    assert(both.total() == 13);
+    assert(both.estimate(str::to_bytes("stevej")) >= 12);
+    assert(a.merge(&CountMinSketch(128, 4)).is_none());
+  }
+
+  #[test]
+  fn test_count_min_rows_are_independent() {
+    // items that share a counter in one row mostly don't in the others.
+    let width = 64;
+    let mut shared = 0;
+    let mut pairs = 0;
+    for uint::range(0, 200) |i| {
+      let a = count_min_indices(item(i), width, 4);
+      for uint::range(i + 1, 200) |j| {
+        let b = count_min_indices(item(j), width, 4);
+        if a[0] == b[0] {
+          pairs += 1;
+          if a[1] == b[1] && a[2] == b[2] && a[3] == b[3] {
+            shared += 1;
+          }
+        }
+      }
+    }
+    assert(pairs > 0);
+    assert(shared * 10 < pairs);
+  }
+
+  #[test]
+  #[should_fail]
+  fn test_count_min_rejects_epsilon_of_one() {
+    CountMinSketch_with_error(1f64, 0.01);
+  }
+
+  #[test]
+  #[should_fail]
+  fn test_count_min_rejects_zero_delta() {
+    CountMinSketch_with_error(0.01, 0f64);
+  }
+
+  #[test]
+  fn test_heavy_hitters() {
+    let hitters = HeavyHitters(0.1, 0.001, 0.01);
+    for uint::range(0, 10000) |i| {
+      // three items make up 20% of the stream each; the rest are unique.
+      if i % 5 < 3 {
+        hitters.insert(item(i % 5));
+      } else {
+        hitters.insert(item(i));
+      }
+    }
+
+    let mut found : ~[~[u8]] = ~[];
+    for hitters.each |key, count| {
+      assert(count >= 2000);
+      found.push(vec::from_slice(key));
+    }
+    assert(found == ~[item(0), item(1), item(2)]);
+  }
+}
@@ -0,0 +1,273 @@
+use core::cmp::{Eq, Ord};
+use fnv1a::fnv1a_64;
+use bytes::{push_u16_le, push_u32_le, push_u64_le, read_u16_le, read_u32_le, read_u64_le};
+use red_black_tree::{RBMap, Tree, Leaf, RBColor, Red, Black, RBMap_empty, node};
+use persistent_map::*;
+
+const SNAPSHOT_MAGIC : u32 = 0x4e534252;  // "RBSN"
+const SNAPSHOT_VERSION : u16 = 1;
+
+/**
+ * Writes values of one type into a snapshot and reads them back. Like a
+ * Comparator, a codec is passed around as a value.
+ */
+pub trait Codec<T> {
+  fn encode(v: &T, out: &mut ~[u8]);
+  fn decode(buf: &[u8], pos: &mut uint) -> Option<T>;
+}
+
+// ints as 8 little-endian bytes.
+pub enum IntCodec {
+  Ints
+}
+
+impl IntCodec : Codec<int> {
+  fn encode(v: &int, out: &mut ~[u8]) {
+    push_u64_le(out, *v as u64);
+  }
+
+  fn decode(buf: &[u8], pos: &mut uint) -> Option<int> {
+    read_u64_le(buf, pos).map(|x| *x as int)
+  }
+}
+
+pub enum UintCodec {
+  Uints
+}
+
+impl UintCodec : Codec<uint> {
+  fn encode(v: &uint, out: &mut ~[u8]) {
+    push_u64_le(out, *v as u64);
+  }
+
+  fn decode(buf: &[u8], pos: &mut uint) -> Option<uint> {
+    read_u64_le(buf, pos).map(|x| *x as uint)
+  }
+}
+
+// strings as a u32 byte length and their UTF-8 bytes.
+pub enum StrCodec {
+  Strs
+}
+
+impl StrCodec : Codec<~str> {
+  fn encode(v: &~str, out: &mut ~[u8]) {
+    push_u32_le(out, v.len() as u32);
+    *out += str::to_bytes(*v);
+  }
+
+  fn decode(buf: &[u8], pos: &mut uint) -> Option<~str> {
+    let len = match read_u32_le(buf, pos) { Some(n) => n as uint, None => return None };
+    if *pos + len > buf.len() {
+      return None;
+    }
+    let bytes = vec::slice(buf, *pos, *pos + len);
+    if !str::is_utf8(bytes) {
+      return None;
+    }
+    *pos += len;
+    Some(str::from_bytes(bytes))
+  }
+}
+
+/**
+ * Writes a set of RBMap versions so that a node shared by several versions
+ * (or reached twice within one) is written once.
+ *
+ * Layout, all integers little-endian:
+ *
+ *   magic u32, format version u16, node count u32
+ *   nodes, children before parents, numbered from 1 in the order written:
+ *     color u8 (0 red, 1 black), has value u8, left id u32, right id u32,
+ *     key, value if present
+ *   version count u32, then each version's root id u32
+ *   fnv1a_64 of everything above, u64
+ *
+ * Id 0 stands for an empty subtree.
+ */
+struct SnapshotWriter {
+  // node address to id, for every node written so far.
+  mut ids: @RBMap<uint, u32>,
+  mut count: u32,
+  mut nodes: ~[u8]
+}
+
+impl SnapshotWriter {
+  fn write<K: Copy Eq Ord, V: Copy, KC: Codec<K>, VC: Codec<V>>(t: @RBMap<K, V>, key_codec: &KC, value_codec: &VC) -> u32 {
+    match t {
+      @Leaf => 0,
+      @Tree(color, left, key, maybe_value, right, _) => {
+        let addr = ptr::to_uint(ptr::addr_of(&*t));
+        match self.ids.get(addr) {
+          Some(id) => return id,
+          None => ()
+        }
+
+        let left_id = self.write(left, key_codec, value_codec);
+        let right_id = self.write(right, key_codec, value_codec);
+
+        self.nodes.push(match color { Red => 0, Black => 1 });
+        self.nodes.push(if maybe_value.is_some() { 1 } else { 0 });
+        push_u32_le(&mut self.nodes, left_id);
+        push_u32_le(&mut self.nodes, right_id);
+        key_codec.encode(&key, &mut self.nodes);
+        match maybe_value {
+          Some(ref value) => value_codec.encode(value, &mut self.nodes),
+          None => ()
+        }
+
+        self.count += 1;
+        self.ids = self.ids.put(addr, self.count);
+        self.count
+      }
+    }
+  }
+}
+
+fn write_snapshot<K: Copy Eq Ord, V: Copy, KC: Codec<K>, VC: Codec<V>>(versions: &[@RBMap<K, V>], key_codec: &KC, value_codec: &VC) -> ~[u8] {
+  let writer = SnapshotWriter {
+    ids: RBMap_empty(),
+    count: 0,
+    nodes: ~[]
+  };
+  let roots = vec::map(versions, |v| writer.write(*v, key_codec, value_codec));
+
+  let mut out = ~[];
+  push_u32_le(&mut out, SNAPSHOT_MAGIC);
+  push_u16_le(&mut out, SNAPSHOT_VERSION);
+  push_u32_le(&mut out, writer.count);
+  out += writer.nodes;
+  push_u32_le(&mut out, roots.len() as u32);
+  for vec::each(roots) |id| {
+    push_u32_le(&mut out, *id);
+  }
+  let checksum = fnv1a_64(copy out);
+  push_u64_le(&mut out, checksum);
+  out
+}
+
+/**
+ * Reads back the versions written by write_snapshot. Nodes that were
+ * shared when written are shared again in the result. Returns None if the
+ * checksum doesn't match or the snapshot is malformed.
+ */
+fn read_snapshot<K: Copy Eq Ord, V: Copy, KC: Codec<K>, VC: Codec<V>>(buf: &[u8], key_codec: &KC, value_codec: &VC) -> Option<~[@RBMap<K, V>]> {
+  if buf.len() < 8 {
+    return None;
+  }
+  let body = vec::slice(buf, 0, buf.len() - 8);
+  let mut end = buf.len() - 8;
+  if read_u64_le(buf, &mut end) != Some(fnv1a_64(copy body)) {
+    return None;
+  }
+
+  let mut pos = 0;
+  if read_u32_le(body, &mut pos) != Some(SNAPSHOT_MAGIC) ||
+     read_u16_le(body, &mut pos) != Some(SNAPSHOT_VERSION) {
+    return None;
+  }
+  let count = match read_u32_le(body, &mut pos) { Some(n) => n as uint, None => return None };
+
+  // nodes[id] is the node with that id; nodes[0] is the empty tree.
+  let mut nodes : ~[@RBMap<K, V>] = ~[@Leaf];
+  for uint::range(0, count) |_| {
+    if pos + 10 > body.len() {
+      return None;
+    }
+    let color = match body[pos] { 0 => Red, 1 => Black, _ => return None };
+    let has_value = body[pos + 1] != 0;
+    pos += 2;
+    let left = match read_u32_le(body, &mut pos) { Some(id) => id as uint, None => return None };
+    let right = match read_u32_le(body, &mut pos) { Some(id) => id as uint, None => return None };
+    // children always come first, so both must already be loaded.
+    if left >= nodes.len() || right >= nodes.len() {
+      return None;
+    }
+    let key = match key_codec.decode(body, &mut pos) { Some(k) => k, None => return None };
+    let value = if has_value {
+      match value_codec.decode(body, &mut pos) { Some(v) => Some(v), None => return None }
+    } else {
+      None
+    };
+    nodes.push(node(color, nodes[left], key, value, nodes[right]));
+  }
+
+  let num_roots = match read_u32_le(body, &mut pos) { Some(n) => n as uint, None => return None };
+  let mut versions = ~[];
+  for uint::range(0, num_roots) |_| {
+    match read_u32_le(body, &mut pos) {
+      Some(id) if (id as uint) < nodes.len() => versions.push(nodes[id as uint]),
+      _ => return None
+    }
+  }
+  if pos != body.len() {
+    return None;
+  }
+  Some(versions)
+}
+
+
+#[cfg(test)]
+mod tests {
+  use red_black_tree::{RBMap, Tree, Leaf, RBMap_empty};
+  use persistent_map::*;
+
+  fn entries(m: @RBMap<uint, ~str>) -> ~[(uint, ~str)] {
+    let mut v = ~[];
+    for m.iter |k, val| { v.push((*k, copy *val)); }
+    v
+  }
+
+  #[test]
+  fn test_round_trip_keeps_sharing() {
+    let mut base = RBMap_empty();
+    for uint::range(0, 1000) |i| {
+      base = base.put(i, uint::str(i));
+    }
+    let mut versions = ~[base];
+    for uint::range(0, 100) |i| {
+      versions.push(versions[i].put(i * 10, ~"changed"));
+    }
+    versions.push(versions[100].delete(500));
+
+    let one = write_snapshot(~[base], &Uints, &Strs);
+    let all = write_snapshot(versions, &Uints, &Strs);
+    // 101 more versions cost a few path copies each, not 101 more copies of the map.
+    assert(all.len() < one.len() * 3);
+
+    let loaded = read_snapshot(all, &Uints, &Strs).get();
+    assert(loaded.len() == versions.len());
+    for uint::range(0, versions.len()) |i| {
+      assert(entries(loaded[i]) == entries(versions[i]));
+    }
+    assert(loaded[101].get(500).is_none());
+    assert(loaded[0].get(30) == Some(~"30"));
+    assert(loaded[5].get(30) == Some(~"changed"));
+
+    // version 2 only changed a key in the left half of version 1, so once
+    // loaded they still share one node for the right half, which holds
+    // hundreds of entries.
+    match (loaded[1], loaded[2]) {
+      (@Tree(_, _, _, _, r1, _), @Tree(_, _, _, _, r2, _)) => {
+        assert(r1.len() > 500);
+        assert(box::ptr_eq(r1, r2));
+      }
+      _ => fail
+    }
+  }
+
+  #[test]
+  fn test_rejects_corruption() {
+    let m = RBMap_empty().put(1, 10).put(2, 20);
+    let bytes = write_snapshot(~[m, RBMap_empty()], &Uints, &Ints);
+
+    let loaded = read_snapshot(bytes, &Uints, &Ints).get();
+    assert(loaded[0].get(2) == Some(20));
+    assert(loaded[1].is_empty());
+
+    let mut corrupt = copy bytes;
+    corrupt[12] ^= 1;
+    assert(read_snapshot(corrupt, &Uints, &Ints).is_none());
+    assert(read_snapshot(vec::slice(bytes, 0, 10), &Uints, &Ints).is_none());
+  }
+}
@@ -0,0 +1,111 @@
+use core::cmp::{Eq, Ord};
+use persistent_map::*;
+
+/**
+ * A purely functional sorted set on any OrderedPersistentMap: the members
+ * are the keys of a map whose values are (), so the unit values take up
+ * no room. RBSet and TreeSet are this over an RBMap and a tree::Tree.
+ */
+pub struct SortedSet<K: Copy Eq Ord, M: Copy OrderedPersistentMap<K, ()>> {
+  map: M
+}
+
+// adds `items` to the empty set `empty` one at a time.
+fn SortedSet_from_vec<K: Copy Eq Ord, M: Copy OrderedPersistentMap<K, ()>>(empty: SortedSet<K, M>, items: &[K]) -> SortedSet<K, M> {
+  let mut set = empty;
+  for vec::each(items) |item| {
+    set = set.insert(*item);
+  }
+  return set;
+}
+
+impl<K: Copy Eq Ord, M: Copy OrderedPersistentMap<K, ()>> SortedSet<K, M> {
+  pure fn insert(k: K) -> SortedSet<K, M> {
+    SortedSet { map: self.map.put(k, ()) }
+  }
+
+  // removing a key that isn't a member returns the set itself.
+  pure fn remove(k: K) -> SortedSet<K, M> {
+    if !self.contains(k) {
+      return self;
+    }
+    SortedSet { map: self.map.delete(k) }
+  }
+
+  pure fn contains(k: K) -> bool {
+    self.map.contains_key(k)
+  }
+
+  pure fn is_empty() -> bool {
+    self.map.is_empty()
+  }
+
+  pure fn len() -> uint {
+    self.map.len()
+  }
+
+  // visits the members in ascending order.
+  pure fn each(f: fn(&K) -> bool) {
+    self.map.keys(f);
+  }
+
+  pure fn is_subset(other: &SortedSet<K, M>) -> bool {
+    for self.each |k| {
+      if !other.contains(*k) {
+        return false;
+      }
+    }
+    true
+  }
+
+  pure fn is_superset(other: &SortedSet<K, M>) -> bool {
+    other.is_subset(&self)
+  }
+
+  pure fn is_disjoint(other: &SortedSet<K, M>) -> bool {
+    for self.each |k| {
+      if other.contains(*k) {
+        return false;
+      }
+    }
+    true
+  }
+
+  fn union(other: &SortedSet<K, M>) -> SortedSet<K, M> {
+    let mut result = self;
+    for other.each |k| {
+      result = result.insert(*k);
+    }
+    return result;
+  }
+
+  fn intersection(other: &SortedSet<K, M>) -> SortedSet<K, M> {
+    let mut result = self;
+    for self.each |k| {
+      if !other.contains(*k) {
+        result = result.remove(*k);
+      }
+    }
+    return result;
+  }
+
+  fn difference(other: &SortedSet<K, M>) -> SortedSet<K, M> {
+    let mut result = self;
+    for other.each |k| {
+      result = result.remove(*k);
+    }
+    return result;
+  }
+
+  fn symmetric_difference(other: &SortedSet<K, M>) -> SortedSet<K, M> {
+    self.difference(other).union(&other.difference(&self))
+  }
+
+  fn to_vec() -> ~[K] {
+    let mut results : ~[K] = ~[];
+    for self.each |k| {
+      results.push(*k);
+    }
+    return results;
+  }
+}
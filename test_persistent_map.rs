@@ -0,0 +1,135 @@
+/**
+ * Conformance tests that every OrderedPersistentMap must pass. Invoke
+ * the macro with a module name and an expression for an empty
+ * map from int to int.
+ */
+macro_rules! persistent_map_conformance (
+  ($suite:ident, $empty:expr) => (
+    mod $suite {
+      use persistent_map::*;
+      use red_black_tree;
+      use tree;
+      use btree;
+
+      #[test]
+      fn test_empty() {
+        let m = $empty;
+        assert(m.is_empty());
+        assert(m.len() == 0);
+        assert(m.get(1) == None);
+        assert(!m.contains_key(1));
+        assert(m.min() == None);
+        assert(m.max() == None);
+      }
+
+      #[test]
+      fn test_put_get_delete() {
+        let v1 = $empty.put(2, 20).put(1, 10).put(3, 30);
+        assert(v1.len() == 3);
+        assert(!v1.is_empty());
+        assert(v1.get(1) == Some(10));
+        assert(v1.get(2) == Some(20));
+        assert(v1.get(3) == Some(30));
+        assert(v1.get(4) == None);
+
+        let v2 = v1.delete(2);
+        assert(v2.len() == 2);
+        assert(!v2.contains_key(2));
+        assert(v2.contains_key(1));
+
+        let v3 = v2.delete(1).delete(3);
+        assert(v3.is_empty());
+        assert(v3.len() == 0);
+      }
+
+      #[test]
+      fn test_old_versions_survive() {
+        let v1 = $empty.put(1, 10);
+        let v2 = v1.put(1, 11).put(2, 20);
+        let v3 = v2.delete(1);
+
+        assert(v1.get(1) == Some(10));
+        assert(v1.get(2) == None);
+        assert(v2.get(1) == Some(11));
+        assert(v3.get(1) == None);
+        assert(v3.get(2) == Some(20));
+      }
+
+      #[test]
+      fn test_iteration_is_ordered() {
+        let mut m = $empty;
+        for vec::each(~[5, 3, 8, 1, 4, 7, 9, 2, 6]) |k| {
+          m = m.put(*k, *k * 10);
+        }
+        m = m.delete(4);
+
+        let mut pairs : ~[(int, int)] = ~[];
+        for m.iter |k, v| { pairs.push((*k, *v)); }
+        assert(pairs == ~[(1, 10), (2, 20), (3, 30), (5, 50), (6, 60),
+                          (7, 70), (8, 80), (9, 90)]);
+
+        let mut keys : ~[int] = ~[];
+        for m.keys |k| { keys.push(*k); }
+        assert(keys == ~[1, 2, 3, 5, 6, 7, 8, 9]);
+
+        let mut values : ~[int] = ~[];
+        for m.values |v| { values.push(*v); }
+        assert(values == ~[10, 20, 30, 50, 60, 70, 80, 90]);
+
+        let mut first_two : ~[int] = ~[];
+        for m.keys |k| {
+          first_two.push(*k);
+          if first_two.len() == 2 { break; }
+        }
+        assert(first_two == ~[1, 2]);
+      }
+
+      #[test]
+      fn test_insert_returning_old_and_update() {
+        let (old1, v1) = $empty.insert_returning_old(1, 10);
+        let (old2, v2) = v1.insert_returning_old(1, 11);
+        assert(old1 == None);
+        assert(old2 == Some(10));
+        assert(v2.get(1) == Some(11));
+
+        let v3 = v2.update(1, |v| v.map(|n| *n + 1)).update(2, |_| Some(2));
+        assert(v3.get(1) == Some(12));
+        assert(v3.get(2) == Some(2));
+        assert(v3.update(1, |_| None).get(1) == None);
+
+        let v4 = v3.alter(2, |n| *n * 5).insert_with(2, 1, |a, b| *a + *b);
+        assert(v4.get(2) == Some(11));
+
+        let (x, v5) = v4.get_or_insert_with(7, || 70);
+        assert(x == 70);
+        assert(v5.get(7) == Some(70));
+      }
+
+      #[test]
+      fn test_ordered_queries() {
+        let m = $empty.put(10, 1).put(20, 2).put(30, 3).put(40, 4).delete(30);
+
+        assert(m.min() == Some((10, 1)));
+        assert(m.max() == Some((40, 4)));
+        assert(m.floor(25) == Some((20, 2)));
+        assert(m.floor(30) == Some((20, 2)));
+        assert(m.floor(5) == None);
+        assert(m.ceiling(25) == Some((40, 4)));
+        assert(m.ceiling(10) == Some((10, 1)));
+        assert(m.ceiling(41) == None);
+
+        let mut keys : ~[int] = ~[];
+        for m.range(15, 40) |k, _| { keys.push(*k); }
+        assert(keys == ~[20]);
+
+        keys = ~[];
+        for m.range(0, 100) |k, _| { keys.push(*k); }
+        assert(keys == ~[10, 20, 40]);
+      }
+    }
+  )
+)
+
+persistent_map_conformance!(tree_conformance, tree::Empty::<int, int>())
+persistent_map_conformance!(rb_map_conformance, red_black_tree::RBMap_empty::<int, int>())
+persistent_map_conformance!(btree_conformance, btree::BTreeMap::<int, int>())
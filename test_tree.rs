@@ -1,4 +1,5 @@
 use tree::*;
+use persistent_map::*;
 
 #[test]
 fn test_create_tree_with_single_element() {
@@ -86,9 +87,121 @@ fn test_add_items_double_level() {
   assert(v7.contains("tiatstacy"));
   assert(v7.contains("querty"));
   assert(v7.contains("qxerty"));
+  assert(v7.get("querty") == Some(2));
+  assert(v7.get("txatstacy") == Some(4));
 }
 
-
+#[test]
 fn test_iterate_in_order() {
+  let tree = Tree(3, 0).insert(1, 0).insert(5, 0).insert(2, 0).insert(4, 0);
+
+  let mut keys : ~[int] = ~[];
+  for tree.each |k, _| {
+    keys.push(*k);
+  }
+
+  assert(keys == ~[1, 2, 3, 4, 5]);
+}
+
+#[test]
+fn test_remove() {
+  let v1 = Tree("stevej", 150).insert("thatstacy", 167).insert("qwerty", 200);
+  let v2 = v1.remove("stevej");
+
+  assert(!v2.contains("stevej"));
+  assert(v2.get("thatstacy") == Some(167));
+  assert(v2.get("qwerty") == Some(200));
+  assert(v1.contains("stevej"));
+
+  let v3 = v2.remove("thatstacy").remove("qwerty");
+  assert(v3.is_empty());
+}
+
+#[test]
+fn test_update() {
+  let v1 = Tree("stevej", 150).insert("thatstacy", 167);
+
+  let v2 = v1.update("stevej", |v| v.map(|n| *n + 1));
+  assert(v2.get("stevej") == Some(151));
+  assert(v1.get("stevej") == Some(150));
+
+  let v3 = v2.update("qwerty", |_| Some(200));
+  assert(v3.get("qwerty") == Some(200));
+
+  let v4 = v3.update("thatstacy", |_| None);
+  assert(!v4.contains("thatstacy"));
+  assert(v4.get("qwerty") == Some(200));
+}
+
+#[test]
+fn test_alter_insert_with_and_entry() {
+  let v1 = Tree(2, 10).insert(1, 1).insert(3, 3);
+
+  let v2 = v1.alter(2, |n| *n * 2).alter(9, |n| *n * 2);
+  assert(v2.get(2) == Some(20));
+  assert(!v2.contains(9));
+
+  let v3 = v2.insert_with(3, 4, |old, new| *old + *new);
+  assert(v3.get(3) == Some(7));
+
+  let (a, v4) = v3.get_or_insert_with(5, || 55);
+  let (b, _) = v4.get_or_insert_with(5, || 99);
+  assert(a == 55);
+  assert(b == 55);
+
+  assert(v4.entry(2).remove().get(1) == Some(1));
+  assert(!v4.entry(2).remove().contains(2));
+}
 
-}
\ No newline at end of file
+#[test]
+fn test_len_and_positions() {
+  let mut tree = Empty();
+  for vec::each(~[50, 20, 80, 10, 30, 70, 90, 60]) |k| {
+    tree = tree.insert(*k, *k / 10);
+  }
+  assert(tree.len() == 8);
+  assert(tree.insert(30, 0).len() == 8);
+  assert(tree.remove(20).len() == 7);
+
+  assert(tree.nth(0) == Some((10, 1)));
+  assert(tree.nth(3) == Some((50, 5)));
+  assert(tree.nth(7) == Some((90, 9)));
+  assert(tree.nth(8) == None);
+
+  assert(tree.index_of(10) == Some(0));
+  assert(tree.index_of(60) == Some(4));
+  assert(tree.index_of(65) == None);
+}
+
+#[test]
+fn test_split_at() {
+  let tree = Tree(4, 0).insert(2, 0).insert(6, 0).insert(1, 0).insert(3, 0).insert(5, 0);
+
+  let (a, b) = tree.split_at(2);
+  assert(a.len() == 2);
+  assert(b.len() == 4);
+  assert(a.max() == Some((2, 0)));
+  assert(b.min() == Some((3, 0)));
+
+  let (c, d) = tree.split_at(6);
+  assert(c.len() == 6);
+  assert(d.is_empty());
+}
+
+#[test]
+fn test_from_sorted() {
+  let mut entries = ~[];
+  for uint::range(0, 100) |i| {
+    entries.push((i, i * 10));
+  }
+  let tree = Tree_from_sorted(entries);
+  assert(tree.len() == 100);
+  assert(tree.get(42) == Some(420));
+  assert(tree.nth(99) == Some((99, 990)));
+
+  let mut keys = ~[];
+  for tree.keys |k| { keys.push(*k); }
+  assert(keys == vec::map(entries, |e| e.first()));
+
+  assert(Tree_from_sorted::<int, int>(~[]).is_empty());
+}
@@ -0,0 +1,576 @@
+/**
+ * Transients: mutable builders for batches of edits to a persistent
+ * structure, after Clojure's transients.
+ *
+ * A TransientRBMap starts out pointing at the frozen nodes of the map it
+ * was made from. The first edit to reach a frozen node thaws it into a
+ * mutable copy; later edits along that path update the thawed nodes in
+ * place instead of copying the path again. `persistent()` freezes the
+ * thawed nodes back into RBMap nodes, reusing every untouched subtree.
+ *
+ * A thawed node is only ever reachable from the transient that thawed it,
+ * and freezing copies it, so that reachability stands in for Clojure's
+ * edit token: no persistent version can see a node change underneath it.
+ * A transient can keep being used after `persistent()`; it just thaws
+ * nodes afresh.
+ */
+use core::cmp::{Eq, Ord};
+use red_black_tree::{RBMap, Tree, Leaf, RBColor, Red, Black, node};
+use pairing_heap::{PairingHeap, HeapNode, Empty_, PairingHeapCell, Comparator, Heap};
+use persistent_map::*;
+
+enum TNode<K: Copy Eq Ord, V: Copy> {
+  Frozen(@RBMap<K, V>),
+  Thawed(@ThawedNode<K, V>)
+}
+
+struct ThawedNode<K: Copy Eq Ord, V: Copy> {
+  mut color: RBColor,
+  mut left: TNode<K, V>,
+  key: K,
+  mut value: Option<V>,
+  mut right: TNode<K, V>
+}
+
+pure fn thaw<K: Copy Eq Ord, V: Copy>(t: @RBMap<K, V>) -> @ThawedNode<K, V> {
+  match t {
+    @Tree(color, left, key, value, right, _) => @ThawedNode {
+      color: color,
+      left: Frozen(left),
+      key: key,
+      value: value,
+      right: Frozen(right)
+    },
+    @Leaf => fail ~"thawing an empty tree"
+  }
+}
+
+pure fn freeze<K: Copy Eq Ord, V: Copy>(t: TNode<K, V>) -> @RBMap<K, V> {
+  match t {
+    Frozen(map) => map,
+    Thawed(n) => node(n.color, freeze(n.left), n.key, n.value, freeze(n.right))
+  }
+}
+
+// the node at `t` if it's a red node this transient already owns. Edits
+// only ever leave two reds in a row on the path they thawed.
+pure fn red_thawed<K: Copy Eq Ord, V: Copy>(t: TNode<K, V>) -> Option<@ThawedNode<K, V>> {
+  match t {
+    Thawed(n) => match n.color { Red => Some(n), Black => None },
+    Frozen(_) => None
+  }
+}
+
+/**
+ * Okasaki's four rotations, done by relinking the three thawed nodes
+ * involved rather than allocating new ones. Returns the subtree's new top.
+ */
+fn balance<K: Copy Eq Ord, V: Copy>(z: @ThawedNode<K, V>) -> @ThawedNode<K, V> {
+  match z.color { Red => return z, Black => () }
+
+  match red_thawed(z.left) {
+    Some(y) => {
+      match red_thawed(y.left) {
+        Some(x) => {
+          z.left = y.right;
+          y.right = Thawed(z);
+          x.color = Black;
+          return y;
+        }
+        None => ()
+      }
+      match red_thawed(y.right) {
+        Some(x) => {
+          y.right = x.left;
+          z.left = x.right;
+          x.left = Thawed(y);
+          x.right = Thawed(z);
+          y.color = Black;
+          return x;
+        }
+        None => ()
+      }
+    }
+    None => ()
+  }
+
+  match red_thawed(z.right) {
+    Some(y) => {
+      match red_thawed(y.left) {
+        Some(x) => {
+          z.right = x.left;
+          y.left = x.right;
+          x.left = Thawed(z);
+          x.right = Thawed(y);
+          y.color = Black;
+          return x;
+        }
+        None => ()
+      }
+      match red_thawed(y.right) {
+        Some(x) => {
+          z.right = y.left;
+          y.left = Thawed(z);
+          x.color = Black;
+          return y;
+        }
+        None => ()
+      }
+    }
+    None => ()
+  }
+
+  z
+}
+
+pub struct TransientRBMap<K: Copy Eq Ord, V: Copy> {
+  mut root: TNode<K, V>,
+  mut len: uint
+}
+
+pure fn TransientRBMap<K: Copy Eq Ord, V: Copy>(map: @RBMap<K, V>) -> TransientRBMap<K, V> {
+  TransientRBMap { root: Frozen(map), len: map.len() }
+}
+
+impl<K: Copy Eq Ord, V: Copy> TransientRBMap<K, V> {
+  pure fn get(k: K) -> Option<V> {
+    let mut cur = self.root;
+    loop {
+      match cur {
+        Frozen(map) => return map.get(k),
+        Thawed(n) => {
+          if k < n.key {
+            cur = n.left;
+          } else if n.key < k {
+            cur = n.right;
+          } else {
+            return n.value;
+          }
+        }
+      }
+    }
+  }
+
+  pure fn contains_key(k: K) -> bool {
+    self.get(k).is_some()
+  }
+
+  pure fn len() -> uint {
+    self.len
+  }
+
+  pure fn is_empty() -> bool {
+    self.len == 0
+  }
+
+  fn put(k: K, v: V) {
+    let root = self.insert_at(self.root, k, v);
+    root.color = Black;
+    self.root = Thawed(root);
+  }
+
+  // deleting a key that isn't there leaves the map untouched.
+  fn delete(k: K) {
+    if self.contains_key(k) {
+      self.root = Thawed(self.clear_at(self.root, k));
+      self.len -= 1;
+    }
+  }
+
+  // freezes the edits made so far into an RBMap.
+  fn persistent() -> @RBMap<K, V> {
+    let map = freeze(self.root);
+    self.root = Frozen(map);
+    map
+  }
+
+  priv fn insert_at(t: TNode<K, V>, k: K, v: V) -> @ThawedNode<K, V> {
+    let n = match t {
+      Frozen(@Leaf) => {
+        self.len += 1;
+        return @ThawedNode {
+          color: Red,
+          left: Frozen(@Leaf),
+          key: k,
+          value: Some(v),
+          right: Frozen(@Leaf)
+        };
+      }
+      Frozen(map) => thaw(map),
+      Thawed(n) => n
+    };
+
+    if k < n.key {
+      n.left = Thawed(self.insert_at(n.left, k, v));
+      balance(n)
+    } else if n.key < k {
+      n.right = Thawed(self.insert_at(n.right, k, v));
+      balance(n)
+    } else {
+      // deleted keys stay behind with no value.
+      if n.value.is_none() {
+        self.len += 1;
+      }
+      n.value = Some(v);
+      n
+    }
+  }
+
+  // drops the value stored under `k`, which must be present.
+  priv fn clear_at(t: TNode<K, V>, k: K) -> @ThawedNode<K, V> {
+    let n = match t {
+      Frozen(map) => thaw(map),
+      Thawed(n) => n
+    };
+
+    if k < n.key {
+      n.left = Thawed(self.clear_at(n.left, k));
+    } else if n.key < k {
+      n.right = Thawed(self.clear_at(n.right, k));
+    } else {
+      n.value = None;
+    }
+    n
+  }
+}
+
+/**
+ * A heap cell seen from a TransientHeap: either a persistent cell (its
+ * element and child list, without the sibling it had) or a thawed one.
+ */
+enum TCell<E: Copy> {
+  FrozenCell(E, @HeapNode<E>),
+  ThawedCell(@ThawedHeapCell<E>)
+}
+
+struct ThawedHeapCell<E: Copy> {
+  elem: E,
+  // cells linked under this one by the transient, newest last.
+  mut linked: ~[TCell<E>],
+  // the child list it had when it was thawed.
+  frozen: @HeapNode<E>
+}
+
+/**
+ * A transient PairingHeap. Linking two cells makes the loser a child of
+ * the winner, and once the winner is thawed that is a push onto its own
+ * vector of children: an insert that loses to the root allocates nothing
+ * but vector growth, where a persistent insert allocates two cells. Pops
+ * pair the root's children up in place the same way. `persistent()`
+ * freezes the thawed cells into heap cells, reusing every frozen child
+ * list as it is.
+ */
+pub struct TransientHeap<E: Copy, C: Copy Comparator<E>> {
+  cmp: C,
+  mut root: Option<TCell<E>>,
+  // shared by every single-element cell an insert makes.
+  no_children: @HeapNode<E>
+}
+
+pure fn TransientHeap<E: Copy, C: Copy Comparator<E>>(heap: PairingHeap<E, C>) -> TransientHeap<E, C> {
+  let root = match heap.root {
+    Empty_ => None,
+    PairingHeapCell(elem, children, _) => Some(FrozenCell(elem, children))
+  };
+  TransientHeap { cmp: heap.cmp, root: root, no_children: @Empty_ }
+}
+
+pure fn cell_elem<E: Copy>(c: &TCell<E>) -> E {
+  match *c {
+    FrozenCell(elem, _) => elem,
+    ThawedCell(t) => t.elem
+  }
+}
+
+pure fn thaw_cell<E: Copy>(c: TCell<E>) -> @ThawedHeapCell<E> {
+  match c {
+    FrozenCell(elem, children) => @ThawedHeapCell { elem: elem, linked: ~[], frozen: children },
+    ThawedCell(t) => t
+  }
+}
+
+// `c` as a persistent cell with `sibling` after it.
+fn freeze_cell<E: Copy>(c: &TCell<E>, sibling: @HeapNode<E>) -> HeapNode<E> {
+  match *c {
+    FrozenCell(elem, children) => PairingHeapCell(elem, children, sibling),
+    ThawedCell(t) => {
+      // newest children go first, as merge_nodes puts them.
+      let mut children = t.frozen;
+      for uint::range(0, t.linked.len()) |i| {
+        let child = copy t.linked[i];
+        children = @freeze_cell(&child, children);
+      }
+      PairingHeapCell(t.elem, children, sibling)
+    }
+  }
+}
+
+impl<E: Copy, C: Copy Comparator<E>> TransientHeap<E, C> {
+  fn insert(e: E) {
+    let single = FrozenCell(e, self.no_children);
+    let mut root = None;
+    root <-> self.root;
+    self.root = Some(match root {
+      None => single,
+      Some(r) => self.link(r, single)
+    });
+  }
+
+  fn peek() -> Option<E> {
+    match self.root {
+      None => None,
+      Some(ref r) => Some(cell_elem(r))
+    }
+  }
+
+  fn pop() -> Option<E> {
+    let mut root = None;
+    root <-> self.root;
+    match root {
+      None => None,
+      Some(r) => {
+        let head = cell_elem(&r);
+        self.root = self.pair_children(r);
+        Some(head)
+      }
+    }
+  }
+
+  fn is_empty() -> bool {
+    self.root.is_none()
+  }
+
+  // freezes the heap as it is now. Later edits thaw its cells afresh.
+  fn persistent() -> PairingHeap<E, C> {
+    let root = match self.root {
+      None => Empty_,
+      Some(ref r) => freeze_cell(r, @Empty_)
+    };
+    self.root = match root {
+      PairingHeapCell(elem, children, _) => Some(FrozenCell(elem, children)),
+      Empty_ => None
+    };
+    PairingHeap { cmp: self.cmp, root: root }
+  }
+
+  // makes the loser a child of the winner, thawing the winner if need be.
+  priv fn link(a: TCell<E>, b: TCell<E>) -> TCell<E> {
+    let (winner, loser) = if self.cmp.le(&cell_elem(&a), &cell_elem(&b)) { (a, b) } else { (b, a) };
+    let w = thaw_cell(winner);
+    w.linked.push(loser);
+    ThawedCell(w)
+  }
+
+  // the two-pass pairing over the children of `c`, as merge_pairs does it.
+  priv fn pair_children(c: TCell<E>) -> Option<TCell<E>> {
+    let mut children = ~[];
+    let mut rest = match c {
+      FrozenCell(_, frozen) => frozen,
+      ThawedCell(t) => {
+        children <-> t.linked;
+        t.frozen
+      }
+    };
+    loop {
+      match *rest {
+        Empty_ => break,
+        PairingHeapCell(elem, grandchildren, sibling) => {
+          children.push(FrozenCell(elem, grandchildren));
+          rest = sibling;
+        }
+      }
+    }
+
+    let mut pairs = ~[];
+    while !children.is_empty() {
+      let a = children.pop();
+      if children.is_empty() {
+        pairs.push(a);
+      } else {
+        let b = children.pop();
+        pairs.push(self.link(a, b));
+      }
+    }
+
+    let mut result = None;
+    while !pairs.is_empty() {
+      let c = pairs.pop();
+      result = Some(match result {
+        None => c,
+        Some(r) => self.link(c, r)
+      });
+    }
+    result
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use red_black_tree::{RBMap, Tree, Leaf, Red, Black, RBMap_empty};
+  use pairing_heap::{PairingHeap, MaxHeap, Empty, Heap};
+  use persistent_map::*;
+
+  fn entries(m: @RBMap<uint, uint>) -> ~[(uint, uint)] {
+    let mut v = ~[];
+    for m.iter |k, val| { v.push((*k, *val)); }
+    v
+  }
+
+  // fails on a red node with a red child; returns the black height.
+  fn check_balanced(t: @RBMap<uint, uint>, parent_red: bool) -> uint {
+    match t {
+      @Leaf => 1,
+      @Tree(color, left, _, _, right, _) => {
+        let red = match color { Red => true, Black => false };
+        assert(!(red && parent_red));
+        let lh = check_balanced(left, red);
+        assert(lh == check_balanced(right, red));
+        if red { lh } else { lh + 1 }
+      }
+    }
+  }
+
+  #[test]
+  fn test_batch_matches_puts() {
+    let mut plain = RBMap_empty();
+    let t = RBMap_empty().transient();
+    for uint::range(0, 5000) |i| {
+      let k = (i * 7919) % 5000;
+      plain = plain.put(k, i);
+      t.put(k, i);
+    }
+    assert(t.len() == 5000);
+    assert(t.get(42) == plain.get(42));
+
+    let built = t.persistent();
+    assert(entries(built) == entries(plain));
+    assert(built.len() == 5000);
+    check_balanced(built, false);
+  }
+
+  #[test]
+  fn test_source_version_is_untouched() {
+    let mut base = RBMap_empty();
+    for uint::range(0, 100) |i| {
+      base = base.put(i, i);
+    }
+    let before = entries(base);
+
+    let t = TransientRBMap(base);
+    for uint::range(0, 100) |i| {
+      t.put(i, i * 10);
+    }
+    t.put(500, 5);
+    t.delete(7);
+    t.delete(1000);
+    let edited = t.persistent();
+
+    assert(entries(base) == before);
+    assert(edited.get(3) == Some(30));
+    assert(edited.get(7) == None);
+    assert(edited.len() == 100);
+
+    // edits after freezing don't reach the frozen version.
+    t.put(7, 70);
+    assert(edited.get(7) == None);
+    assert(t.persistent().get(7) == Some(70));
+    assert(t.len() == 101);
+  }
+
+  #[test]
+  fn test_untouched_subtrees_are_shared() {
+    let mut base = RBMap_empty();
+    for uint::range(0, 1000) |i| {
+      base = base.put(i, i);
+    }
+    let t = TransientRBMap(base);
+    t.put(0, 1);
+    t.put(1, 2);
+    match (base, t.persistent()) {
+      (@Tree(_, _, _, _, r1, _), @Tree(_, _, _, _, r2, _)) => {
+        // the root's right subtree holds hundreds of keys, none of them touched.
+        assert(r1.len() > 400);
+        assert(box::ptr_eq(r1, r2));
+      }
+      _ => fail
+    }
+  }
+
+  #[test]
+  fn test_frozen_versions_stay_balanced() {
+    let t = RBMap_empty().transient();
+    let mut m = RBMap_empty();
+    for uint::range(0, 3000) |i| {
+      let k = (i * 7919) % 3000;
+      t.put(k, i);
+      if i % 100 == 0 {
+        // the persistent puts rebalance a tree the transient built.
+        m = t.persistent();
+        for uint::range(0, 50) |j| {
+          m = m.put(3000 + i + j, j);
+          check_balanced(m, false);
+        }
+      }
+    }
+    assert(m.len() == 2951);
+    check_balanced(t.persistent(), false);
+  }
+
+  #[test]
+  fn test_transient_heap() {
+    let t = Empty().transient();
+    for uint::range(0, 1000) |i| {
+      t.insert((i * 7919) % 1000);
+    }
+    assert(t.peek() == Some(0));
+    assert(t.pop() == Some(0));
+    t.insert(0);
+
+    let mut heap = t.persistent();
+    for uint::range(0, 1000) |i| {
+      let (x, rest) = heap.pop();
+      assert(x == Some(i));
+      heap = rest;
+    }
+    assert(heap.is_empty());
+
+    let max = TransientHeap(MaxHeap().insert(5));
+    max.insert(9);
+    max.insert(1);
+    assert(max.persistent().peek() == Some(9));
+  }
+
+  #[test]
+  fn test_transient_heap_leaves_versions_alone() {
+    let mut base = Empty();
+    for uint::range(0, 100) |i| {
+      base = base.insert(100 - i);
+    }
+    let t = base.transient();
+    assert(t.pop() == Some(1));
+    t.insert(0);
+    let frozen = t.persistent();
+    assert(t.pop() == Some(0));
+    t.insert(500);
+
+    // neither the heap it came from nor the one it froze saw the later edits.
+    assert(base.peek() == Some(1));
+    assert(frozen.peek() == Some(0));
+    let mut heap = frozen;
+    let mut count = 0;
+    while !heap.is_empty() {
+      heap = heap.pop().second();
+      count += 1;
+    }
+    assert(count == 100);
+
+    let mut last = 0;
+    while !t.is_empty() {
+      let x = t.pop().get();
+      assert(x >= last);
+      last = x;
+    }
+    assert(last == 500);
+  }
+}
@@ -1,5 +1,7 @@
 use core::cmp::{Eq, Ord};
 use core::option;
+use std::list::{List, Cons, Nil};
+use persistent_map::{PersistentMap, OrderedPersistentMap};
 
 /**
  * A purely functional binary search tree.
@@ -11,17 +13,19 @@ use core::option;
  *
  * K is the key the object is stored under.
  * V is the type of objects stored.
+ *
+ * Keys smaller than a node's key live in its left branch, larger keys in
+ * its right branch.
  */
-
-// it would be nice to keep all these type bounds in one place.
-type Branch<K: Copy Eq Ord, V: Copy> = Option<Tree<K, V>>;
-
 pub enum Tree<K: Copy Eq Ord, V: Copy> {
+  Empty_,
   Tree_(@{
     key       : K,
     value     : V,
-    left  : Branch<K, V>,
-    right : Branch<K, V>
+    left  : Tree<K, V>,
+    right : Tree<K, V>,
+    // number of entries in this subtree, this one included.
+    size  : uint
   })
 }
 
@@ -32,104 +36,448 @@ pure fn Tree<K: Copy Eq Ord, V: Copy>(initial_key: K, initial_value: V) -> Tree<
     // why do we use colons here?
     key       : initial_key,
     value     : initial_value,
-    left  : None,
-    right : None
+    left  : Empty_,
+    right : Empty_,
+    size  : 1
   });
 
   return root;
 }
 
+pure fn Empty<K: Copy Eq Ord, V: Copy>() -> Tree<K, V> {
+  Empty_
+}
+
+// builds an interior node, working out its size from its branches.
+pure fn branch<K: Copy Eq Ord, V: Copy>(k: K, v: V, l: Tree<K, V>, r: Tree<K, V>) -> Tree<K, V> {
+  Tree_(@{
+    key   : k,
+    value : v,
+    left  : l,
+    right : r,
+    size  : l.len() + 1 + r.len()
+  })
+}
+
+/**
+ * Builds a balanced tree from entries sorted by strictly increasing key,
+ * in O(n). The middle entry becomes the root and each half is built the
+ * same way.
+ */
+pure fn Tree_from_sorted<K: Copy Eq Ord, V: Copy>(entries: &[(K, V)]) -> Tree<K, V> {
+  build_sorted(entries, 0, entries.len())
+}
+
+pure fn build_sorted<K: Copy Eq Ord, V: Copy>(entries: &[(K, V)], lo: uint, hi: uint) -> Tree<K, V> {
+  if lo == hi {
+    return Empty_;
+  }
+  let mid = (lo + hi) / 2;
+  let (k, v) = entries[mid];
+  branch(k, v, build_sorted(entries, lo, mid), build_sorted(entries, mid + 1, hi))
+}
+
 // why do we need to declare the type parameter constraints here?
 impl<K: Copy Eq Ord, V: Copy> Tree<K, V> {
-  fn get(search_key: K) -> Option<V> {
-    if self.key.eq(&search_key) {
-      return Some(self.value);
-    } else if self.key.gt(&search_key) {
-      // this is identical to right/lt so room for improvement.
-      match self.left {
-        // what are the performance implications here?
-        Some(tree) => { return tree.get(search_key); }
-        // does rust have flatMap?
-        None => { return None; }
-      }
-    } else if self.key.lt(&search_key) {
-      match self.right {
-        Some(tree) => { return tree.get(search_key); }
-        None => { return None; }
-      }
-    } else {
-      return None;
-    }
-  }
-
-  fn insert(new_key: K, new_value: V) -> Tree<K, V> {
-    let branch = Tree(new_key, new_value);
-    if self.key.lt(&new_key) {
-      match self.left {
-        Some(branch) => {
-          return Tree_(@{
-            key : self.key,
-            value : self.value,
-            left : Some(branch.insert(new_key, new_value)),
-            right : self.right
-          })
-        }
-        None => {
-          return Tree_(@{
-            key : self.key,
-            value : self.value,
-            left : Some(Tree(new_key, new_value)),
-            right : self.right
-          })
-        }
-      }
-    } else if self.key.gt(&new_key) {
-      match self.right {
-        Some(branch) => {
-          return Tree_(@{
-            key : self.key,
-            value : self.value,
-            left : self.left,
-            right : Some(branch.insert(new_key, new_value))
-          })
-        }
-        None => {
-          return Tree_(@{
-            key : self.key,
-            value : self.value,
-            left : self.left,
-            right : Some(Tree(new_key, new_value))
-          })
-        }
-      }
-    } else if self.key.eq(&new_key) {
-      return Tree_(@{
-        key   : new_key,
-        value : new_value,
-        left  : self.left,
-        right : self.right
-      });
-    }
-    return branch;
-  }
-
-  fn contains(maybe_key: K) -> bool {
-    if self.key.eq(&maybe_key) {
-      return true;
-    } else if self.key.gt(&maybe_key) {
-      match self.right {
-        Some(branch) => { return branch.contains(maybe_key); }
-        None => { return false; }
-      }
-    } else if self.key.lt(&maybe_key) {
-      match self.left {
-        Some(branch) => { return branch.contains(maybe_key); }
-        None => { return false; }
-      }
-    } else {
-      io::println("sorry, your key was not found in the tree");
-      return false;
+  pure fn insert(new_key: K, new_value: V) -> Tree<K, V> {
+    match self {
+      Empty_ => { return Tree(new_key, new_value); }
+      Tree_(node) => {
+        if node.key.gt(&new_key) {
+          return branch(node.key, node.value, node.left.insert(new_key, new_value), node.right);
+        } else if node.key.lt(&new_key) {
+          return branch(node.key, node.value, node.left, node.right.insert(new_key, new_value));
+        } else {
+          return branch(new_key, new_value, node.left, node.right);
+        }
+      }
+    }
+  }
+
+  pure fn contains(maybe_key: K) -> bool {
+    match self {
+      Empty_ => { return false; }
+      Tree_(node) => {
+        if node.key.eq(&maybe_key) {
+          return true;
+        } else if node.key.gt(&maybe_key) {
+          return node.left.contains(maybe_key);
+        } else {
+          return node.right.contains(maybe_key);
+        }
+      }
+    }
+  }
+
+  /**
+   * Returns a tree without `old_key`. A node with two branches is replaced
+   * by the smallest entry of its right branch.
+   */
+  pure fn remove(old_key: K) -> Tree<K, V> {
+    match self {
+      Empty_ => { return self; }
+      Tree_(node) => {
+        if node.key.gt(&old_key) {
+          return branch(node.key, node.value, node.left.remove(old_key), node.right);
+        } else if node.key.lt(&old_key) {
+          return branch(node.key, node.value, node.left, node.right.remove(old_key));
+        }
+
+        match (node.left, node.right) {
+          (Empty_, right) => { return right; }
+          (left, Empty_) => { return left; }
+          (left, right) => {
+            let (min_key, min_value) = right.min().get();
+            return branch(min_key, min_value, left, right.remove(min_key));
+          }
+        }
+      }
+    }
+  }
+
+  /**
+   * Calls `f` on every entry in ascending key order until it returns false.
+   * Returns false if `f` stopped the walk early.
+   */
+  pure fn traverse(f: fn(&K, &V) -> bool) -> bool {
+    match self {
+      Empty_ => { return true; }
+      Tree_(node) => {
+        return node.left.traverse(f) &&
+               f(&node.key, &node.value) &&
+               node.right.traverse(f);
+      }
+    }
+  }
+
+  pure fn each(f: fn(&K, &V) -> bool) {
+    self.traverse(f);
+  }
+
+  // like traverse, skipping branches that can't hold keys in [lo, hi).
+  pure fn traverse_range(lo: &K, hi: &K, f: fn(&K, &V) -> bool) -> bool {
+    match self {
+      Empty_ => { return true; }
+      Tree_(node) => {
+        if node.key.gt(lo) && !node.left.traverse_range(lo, hi, f) {
+          return false;
+        }
+        if node.key.ge(lo) && node.key.lt(hi) && !f(&node.key, &node.value) {
+          return false;
+        }
+        if node.key.lt(hi) {
+          return node.right.traverse_range(lo, hi, f);
+        }
+        return true;
+      }
+    }
+  }
+
+  // the entry at position `i` in key order, counting from 0.
+  pure fn nth(i: uint) -> Option<(K, V)> {
+    match self {
+      Empty_ => { return None; }
+      Tree_(node) => {
+        let left_len = node.left.len();
+        if i < left_len {
+          return node.left.nth(i);
+        } else if i == left_len {
+          return Some((node.key, node.value));
+        } else {
+          return node.right.nth(i - left_len - 1);
+        }
+      }
+    }
+  }
+
+  // the position of `k` in key order, if it is present.
+  pure fn index_of(k: K) -> Option<uint> {
+    let mut before = 0;
+    let mut cur = self;
+
+    loop {
+      match cur {
+        Empty_ => { return None; }
+        Tree_(node) => {
+          if node.key.gt(&k) {
+            cur = node.left;
+          } else if node.key.lt(&k) {
+            before += node.left.len() + 1;
+            cur = node.right;
+          } else {
+            return Some(before + node.left.len());
+          }
+        }
+      }
+    }
+  }
+
+  // splits into the first `i` entries and the rest, copying one path.
+  pure fn split_at(i: uint) -> (Tree<K, V>, Tree<K, V>) {
+    match self {
+      Empty_ => { return (Empty_, Empty_); }
+      Tree_(node) => {
+        let left_len = node.left.len();
+        if i <= left_len {
+          let (a, b) = node.left.split_at(i);
+          return (a, branch(node.key, node.value, b, node.right));
+        } else {
+          let (a, b) = node.right.split_at(i - left_len - 1);
+          return (branch(node.key, node.value, node.left, a), b);
+        }
+      }
+    }
+  }
+
+  /**
+   * Walks down to `k` once and remembers the way, so the returned entry
+   * can read, write or remove `k` without another descent.
+   */
+  pure fn entry(k: K) -> TreeEntry<K, V> {
+    let mut path = @Nil;
+    let mut cur = self;
+
+    loop {
+      match cur {
+        Empty_ => break,
+        Tree_(node) => {
+          if node.key.gt(&k) {
+            path = @Cons((true, cur), path);
+            cur = node.left;
+          } else if node.key.lt(&k) {
+            path = @Cons((false, cur), path);
+            cur = node.right;
+          } else {
+            break;
+          }
+        }
+      }
+    }
+
+    return TreeEntry { root: self, key: k, node: cur, path: path };
+  }
+}
+
+/**
+ * The result of walking down a Tree towards `key`. `node` is the subtree
+ * rooted at `key`, or Empty_ if it is absent, and `path` holds the trees
+ * passed on the way down, nearest first, with whether we went left.
+ */
+pub struct TreeEntry<K: Copy Eq Ord, V: Copy> {
+  root: Tree<K, V>,
+  key: K,
+  node: Tree<K, V>,
+  path: @List<(bool, Tree<K, V>)>
+}
+
+impl<K: Copy Eq Ord, V: Copy> TreeEntry<K, V> {
+  pure fn get() -> Option<V> {
+    match self.node {
+      Tree_(node) => { return Some(node.value); }
+      Empty_ => { return None; }
+    }
+  }
+
+  pure fn set(v: V) -> Tree<K, V> {
+    let replacement = match self.node {
+      Tree_(node) => branch(node.key, v, node.left, node.right),
+      Empty_ => Tree(self.key, v)
+    };
+    return self.rebuild(replacement);
+  }
+
+  pure fn remove() -> Tree<K, V> {
+    match self.node {
+      Tree_(_) => { return self.rebuild(self.node.remove(self.key)); }
+      Empty_ => { return self.root; }
+    }
+  }
+
+  pure fn or_insert_with(f: fn() -> V) -> (V, Tree<K, V>) {
+    match self.get() {
+      Some(v) => { return (v, self.root); }
+      None => {
+        let v = f();
+        return (v, self.set(v));
+      }
     }
   }
+
+  // copies the path back up to the root around a new subtree.
+  priv pure fn rebuild(subtree: Tree<K, V>) -> Tree<K, V> {
+    let mut result = subtree;
+    let mut cur = self.path;
+
+    loop {
+      match cur {
+        @Nil => break,
+        @Cons((went_left, Tree_(node)), rest) => {
+          result = if went_left {
+            branch(node.key, node.value, result, node.right)
+          } else {
+            branch(node.key, node.value, node.left, result)
+          };
+          cur = rest;
+        }
+        @Cons((_, Empty_), rest) => { cur = rest; }
+      }
+    }
+
+    return result;
+  }
 }
 
+impl<K: Copy Eq Ord, V: Copy> Tree<K, V> : PersistentMap<K, V> {
+  pure fn get(search_key: K) -> Option<V> {
+    match self {
+      Empty_ => { return None; }
+      Tree_(node) => {
+        if node.key.eq(&search_key) {
+          return Some(node.value);
+        } else if node.key.gt(&search_key) {
+          return node.left.get(search_key);
+        } else {
+          return node.right.get(search_key);
+        }
+      }
+    }
+  }
+
+  pure fn put(k: K, v: V) -> Tree<K, V> {
+    self.insert(k, v)
+  }
+
+  pure fn delete(k: K) -> Tree<K, V> {
+    self.remove(k)
+  }
+
+  pure fn len() -> uint {
+    match self {
+      Empty_ => 0,
+      Tree_(node) => node.size
+    }
+  }
+
+  pure fn is_empty() -> bool {
+    match self {
+      Empty_ => true,
+      Tree_(_) => false
+    }
+  }
+
+  pure fn contains_key(k: K) -> bool {
+    self.contains(k)
+  }
+
+  pure fn iter(f: fn(&K, &V) -> bool) {
+    self.traverse(f);
+  }
+
+  pure fn keys(f: fn(&K) -> bool) {
+    self.traverse(|k, _| f(k));
+  }
+
+  pure fn values(f: fn(&V) -> bool) {
+    self.traverse(|_, v| f(v));
+  }
+
+  pure fn insert_returning_old(k: K, v: V) -> (Option<V>, Tree<K, V>) {
+    let entry = self.entry(k);
+    return (entry.get(), entry.set(v));
+  }
+
+  pure fn update(k: K, f: fn(Option<&V>) -> Option<V>) -> Tree<K, V> {
+    let entry = self.entry(k);
+    let current = entry.get();
+    let result = match current {
+      Some(ref v) => f(Some(v)),
+      None => f(None)
+    };
+
+    match result {
+      Some(v) => { return entry.set(v); }
+      None => { return entry.remove(); }
+    }
+  }
+
+  pure fn alter(k: K, f: fn(&V) -> V) -> Tree<K, V> {
+    self.update(k, |current| current.map(|v| f(*v)))
+  }
+
+  pure fn insert_with(k: K, v: V, f: fn(&V, &V) -> V) -> Tree<K, V> {
+    self.update(k, |current| match current {
+      Some(old) => Some(f(old, &v)),
+      None => Some(v)
+    })
+  }
+
+  pure fn get_or_insert_with(k: K, f: fn() -> V) -> (V, Tree<K, V>) {
+    self.entry(k).or_insert_with(f)
+  }
+}
+
+impl<K: Copy Eq Ord, V: Copy> Tree<K, V> : OrderedPersistentMap<K, V> {
+  // the entry with the smallest key.
+  pure fn min() -> Option<(K, V)> {
+    match self {
+      Empty_ => { return None; }
+      Tree_(node) => {
+        match node.left {
+          Empty_ => { return Some((node.key, node.value)); }
+          left => { return left.min(); }
+        }
+      }
+    }
+  }
+
+  // the entry with the largest key.
+  pure fn max() -> Option<(K, V)> {
+    match self {
+      Empty_ => { return None; }
+      Tree_(node) => {
+        match node.right {
+          Empty_ => { return Some((node.key, node.value)); }
+          right => { return right.max(); }
+        }
+      }
+    }
+  }
+
+  pure fn floor(k: K) -> Option<(K, V)> {
+    match self {
+      Empty_ => { return None; }
+      Tree_(node) => {
+        if node.key.eq(&k) {
+          return Some((node.key, node.value));
+        } else if node.key.gt(&k) {
+          return node.left.floor(k);
+        }
+        match node.right.floor(k) {
+          None => { return Some((node.key, node.value)); }
+          found => { return found; }
+        }
+      }
+    }
+  }
+
+  pure fn ceiling(k: K) -> Option<(K, V)> {
+    match self {
+      Empty_ => { return None; }
+      Tree_(node) => {
+        if node.key.eq(&k) {
+          return Some((node.key, node.value));
+        } else if node.key.lt(&k) {
+          return node.right.ceiling(k);
+        }
+        match node.left.ceiling(k) {
+          None => { return Some((node.key, node.value)); }
+          found => { return found; }
+        }
+      }
+    }
+  }
+
+  pure fn range(lo: K, hi: K, f: fn(&K, &V) -> bool) {
+    self.traverse_range(&lo, &hi, f);
+  }
+}
@@ -0,0 +1,57 @@
+use core::cmp::{Eq, Ord};
+use tree::{Tree, Empty};
+use sorted_set::{SortedSet, SortedSet_from_vec};
+
+/**
+ * A purely functional sorted set on top of the binary search tree in
+ * tree.rs. The set operations are SortedSet's.
+ */
+pub type TreeSet<K> = SortedSet<K, Tree<K, ()>>;
+
+pure fn TreeSet<K: Copy Eq Ord>() -> TreeSet<K> {
+  SortedSet { map: Empty() }
+}
+
+fn TreeSet_from_vec<K: Copy Eq Ord>(items: &[K]) -> TreeSet<K> {
+  SortedSet_from_vec(TreeSet(), items)
+}
+
+
+#[test]
+fn test_insert_contains_remove() {
+  let v1 = TreeSet().insert("stevej").insert("thatstacy").insert("qwerty");
+  assert(v1.contains("stevej"));
+  assert(v1.contains("qwerty"));
+  assert(!v1.contains("jeremy"));
+
+  let v2 = v1.remove("stevej");
+  assert(!v2.contains("stevej"));
+  assert(v2.contains("thatstacy"));
+  assert(v2.contains("qwerty"));
+  assert(v1.contains("stevej"));
+  assert(v2.len() == 2);
+}
+
+#[test]
+fn test_ordered_iteration() {
+  let set = TreeSet_from_vec(~[4, 1, 5, 3, 2, 4]);
+  assert(set.to_vec() == ~[1, 2, 3, 4, 5]);
+  assert(set.remove(4).to_vec() == ~[1, 2, 3, 5]);
+  assert(TreeSet::<int>().is_empty());
+}
+
+#[test]
+fn test_set_algebra() {
+  let a = TreeSet_from_vec(~[1, 2, 3, 4]);
+  let b = TreeSet_from_vec(~[3, 4, 5]);
+
+  assert(a.union(&b).to_vec() == ~[1, 2, 3, 4, 5]);
+  assert(a.intersection(&b).to_vec() == ~[3, 4]);
+  assert(a.difference(&b).to_vec() == ~[1, 2]);
+  assert(a.symmetric_difference(&b).to_vec() == ~[1, 2, 5]);
+
+  assert(TreeSet_from_vec(~[2, 3]).is_subset(&a));
+  assert(!b.is_subset(&a));
+  assert(a.is_disjoint(&TreeSet_from_vec(~[7, 8])));
+  assert(!a.is_disjoint(&b));
+}
@@ -0,0 +1,30 @@
+/**
+ * Copy-on-write vector edits shared by the modules whose nodes keep their
+ * entries in small vectors, like btree.rs and radix_tree.rs. Each returns
+ * a new vector and leaves its argument alone.
+ */
+
+// a copy of v[lo..hi].
+pure fn sub<T: Copy>(v: &[T], lo: uint, hi: uint) -> ~[T] {
+  let mut result = ~[];
+  for uint::range(lo, hi) |i| {
+    result.push(v[i]);
+  }
+  result
+}
+
+pure fn insert_at<T: Copy>(v: &[T], i: uint, x: T) -> ~[T] {
+  let mut result = sub(v, 0, i);
+  result.push(x);
+  result + sub(v, i, v.len())
+}
+
+pure fn remove_at<T: Copy>(v: &[T], i: uint) -> ~[T] {
+  sub(v, 0, i) + sub(v, i + 1, v.len())
+}
+
+pure fn replace_at<T: Copy>(v: &[T], i: uint, x: T) -> ~[T] {
+  let mut result = sub(v, 0, v.len());
+  result[i] = x;
+  result
+}
@@ -0,0 +1,316 @@
+use core::cmp::{Eq, Ord};
+use std::list::{List, Cons, Nil};
+use red_black_tree::{RBMap, Tree, Leaf, RBMap_empty, subtree_len};
+use persistent_map::*;
+
+// one difference between two versions of a map.
+pub enum MapChange<K, V> {
+  Added(K, V),
+  Removed(K, V),
+  //      key old new
+  Changed(K, V, V)
+}
+
+impl<K: Eq, V: Eq> MapChange<K, V> : Eq {
+  pure fn eq(&self, other: &MapChange<K, V>) -> bool {
+    match (self, other) {
+      (&Added(ref k1, ref v1), &Added(ref k2, ref v2)) => *k1 == *k2 && *v1 == *v2,
+      (&Removed(ref k1, ref v1), &Removed(ref k2, ref v2)) => *k1 == *k2 && *v1 == *v2,
+      (&Changed(ref k1, ref a1, ref b1), &Changed(ref k2, ref a2, ref b2)) =>
+        *k1 == *k2 && *a1 == *a2 && *b1 == *b2,
+      _ => false
+    }
+  }
+
+  pure fn ne(&self, other: &MapChange<K, V>) -> bool { !self.eq(other) }
+}
+
+// what is left to visit of one side of a diff: a whole subtree, or a single entry.
+enum Pending<K: Copy Eq Ord, V: Copy> {
+  PendingTree(@RBMap<K, V>),
+  PendingEntry(K, Option<V>)
+}
+
+pure fn top<K: Copy Eq Ord, V: Copy>(stack: &[Pending<K, V>]) -> Option<Pending<K, V>> {
+  if stack.is_empty() { None } else { Some(stack[stack.len() - 1]) }
+}
+
+// replaces the subtree on top of `stack` with its left branch, its entry and its right branch.
+pure fn expand<K: Copy Eq Ord, V: Copy>(stack: ~[Pending<K, V>]) -> ~[Pending<K, V>] {
+  let mut stack = stack;
+  match stack.pop() {
+    PendingTree(@Tree(_, left, key, maybe_value, right, _)) => {
+      stack.push(PendingTree(right));
+      stack.push(PendingEntry(key, maybe_value));
+      stack.push(PendingTree(left));
+    }
+    _ => ()
+  }
+  stack
+}
+
+/**
+ * Calls `f` on every difference between two versions of a map, in
+ * ascending key order, until it returns false.
+ *
+ * Both trees are walked in order at once, each as a stack of subtrees
+ * still to visit. Whenever the two stacks have the very same subtree on
+ * top it is skipped whole, so versions that share most of their nodes
+ * are compared in about O(d log n) for d differences rather than O(n).
+ */
+pure fn diff<K: Copy Eq Ord, V: Copy Eq>(old: @RBMap<K, V>, new: @RBMap<K, V>, f: fn(&MapChange<K, V>) -> bool) {
+  let mut a = ~[PendingTree(old)];
+  let mut b = ~[PendingTree(new)];
+
+  loop {
+    match (top(a), top(b)) {
+      (None, None) => return,
+
+      (Some(PendingTree(@Leaf)), _) => { a.pop(); }
+      (_, Some(PendingTree(@Leaf))) => { b.pop(); }
+
+      (Some(PendingTree(x)), Some(PendingTree(y))) => {
+        if box::ptr_eq(x, y) {
+          a.pop();
+          b.pop();
+        } else if subtree_len(x) >= subtree_len(y) {
+          a = expand(a);
+        } else {
+          b = expand(b);
+        }
+      }
+      (Some(PendingTree(_)), _) => { a = expand(a); }
+      (_, Some(PendingTree(_))) => { b = expand(b); }
+
+      (Some(PendingEntry(k, v)), None) => {
+        a.pop();
+        match v {
+          Some(v) => if !f(&Removed(k, v)) { return; },
+          None => ()
+        }
+      }
+      (None, Some(PendingEntry(k, v))) => {
+        b.pop();
+        match v {
+          Some(v) => if !f(&Added(k, v)) { return; },
+          None => ()
+        }
+      }
+
+      (Some(PendingEntry(ka, va)), Some(PendingEntry(kb, vb))) => {
+        let change = if ka < kb {
+          a.pop();
+          match va { Some(v) => Some(Removed(ka, v)), None => None }
+        } else if kb < ka {
+          b.pop();
+          match vb { Some(v) => Some(Added(kb, v)), None => None }
+        } else {
+          a.pop();
+          b.pop();
+          // deleted keys stay behind with no value.
+          match (va, vb) {
+            (Some(x), Some(y)) => if x == y { None } else { Some(Changed(ka, x, y)) },
+            (Some(x), None) => Some(Removed(ka, x)),
+            (None, Some(y)) => Some(Added(ka, y)),
+            (None, None) => None
+          }
+        };
+        match change {
+          Some(ref c) => if !f(c) { return; },
+          None => ()
+        }
+      }
+    }
+  }
+}
+
+/**
+ * An RBMap with an undo/redo history. Every change pushes the version it
+ * replaced onto the undo list; since versions share all but the nodes on
+ * the changed path, each step of history costs O(log n) space.
+ *
+ * A VersionedMap is itself persistent: undo, redo and every change
+ * return a new one.
+ */
+pub struct VersionedMap<K: Copy Eq Ord, V: Copy Eq> {
+  current: @RBMap<K, V>,
+  undo_list: @List<@RBMap<K, V>>,
+  redo_list: @List<@RBMap<K, V>>
+}
+
+pure fn VersionedMap<K: Copy Eq Ord, V: Copy Eq>() -> VersionedMap<K, V> {
+  VersionedMap_from(RBMap_empty())
+}
+
+pure fn VersionedMap_from<K: Copy Eq Ord, V: Copy Eq>(map: @RBMap<K, V>) -> VersionedMap<K, V> {
+  VersionedMap { current: map, undo_list: @Nil, redo_list: @Nil }
+}
+
+impl<K: Copy Eq Ord, V: Copy Eq> VersionedMap<K, V> {
+  pure fn current() -> @RBMap<K, V> {
+    self.current
+  }
+
+  pure fn get(k: K) -> Option<V> {
+    self.current.get(k)
+  }
+
+  pure fn len() -> uint {
+    self.current.len()
+  }
+
+  // makes `map` the current version; anything that could be redone is dropped.
+  pure fn commit(map: @RBMap<K, V>) -> VersionedMap<K, V> {
+    if box::ptr_eq(map, self.current) {
+      return self;
+    }
+    VersionedMap { current: map, undo_list: @Cons(self.current, self.undo_list), redo_list: @Nil }
+  }
+
+  pure fn put(k: K, v: V) -> VersionedMap<K, V> {
+    self.commit(self.current.put(k, v))
+  }
+
+  pure fn delete(k: K) -> VersionedMap<K, V> {
+    self.commit(self.current.delete(k))
+  }
+
+  pure fn can_undo() -> bool {
+    match self.undo_list { @Nil => false, _ => true }
+  }
+
+  pure fn can_redo() -> bool {
+    match self.redo_list { @Nil => false, _ => true }
+  }
+
+  // steps back one version; does nothing if there is no history.
+  pure fn undo() -> VersionedMap<K, V> {
+    match self.undo_list {
+      @Nil => self,
+      @Cons(previous, rest) => VersionedMap {
+        current: previous,
+        undo_list: rest,
+        redo_list: @Cons(self.current, self.redo_list)
+      }
+    }
+  }
+
+  pure fn redo() -> VersionedMap<K, V> {
+    match self.redo_list {
+      @Nil => self,
+      @Cons(next, rest) => VersionedMap {
+        current: next,
+        undo_list: @Cons(self.current, self.undo_list),
+        redo_list: rest
+      }
+    }
+  }
+
+  // the changes undo would revert, in key order.
+  pure fn last_changes(f: fn(&MapChange<K, V>) -> bool) {
+    match self.undo_list {
+      @Nil => (),
+      @Cons(previous, _) => diff(previous, self.current, f)
+    }
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use red_black_tree::{RBMap, RBMap_empty};
+  use persistent_map::*;
+
+  fn changes(old: @RBMap<int, int>, new: @RBMap<int, int>) -> ~[MapChange<int, int>] {
+    let mut v = ~[];
+    for diff(old, new) |c| { v.push(*c); }
+    v
+  }
+
+  // the same diff worked out from the full entry lists.
+  fn naive_changes(old: @RBMap<int, int>, new: @RBMap<int, int>) -> ~[MapChange<int, int>] {
+    let mut v = ~[];
+    let mut keys = ~[];
+    for old.keys |k| { keys.push(*k); }
+    for new.keys |k| { if !old.contains_key(*k) { keys.push(*k); } }
+    let keys = std::sort::merge_sort(|a, b| *a <= *b, keys);
+    for vec::each(keys) |k| {
+      match (old.get(*k), new.get(*k)) {
+        (Some(x), Some(y)) => if x != y { v.push(Changed(*k, x, y)); },
+        (Some(x), None) => v.push(Removed(*k, x)),
+        (None, Some(y)) => v.push(Added(*k, y)),
+        (None, None) => ()
+      }
+    }
+    v
+  }
+
+  #[test]
+  fn test_diff_events() {
+    let v1 = RBMap_empty().put(1, 10).put(2, 20).put(3, 30);
+    let v2 = v1.put(2, 21).delete(3).put(4, 40);
+
+    assert(changes(v1, v2) == ~[Changed(2, 20, 21), Removed(3, 30), Added(4, 40)]);
+    assert(changes(v2, v1) == ~[Changed(2, 21, 20), Added(3, 30), Removed(4, 40)]);
+    assert(changes(v1, v1).is_empty());
+    // putting back the same value is no change.
+    assert(changes(v1, v1.put(1, 10)).is_empty());
+    assert(changes(RBMap_empty(), v1).len() == 3);
+  }
+
+  #[test]
+  fn test_diff_matches_naive() {
+    let mut base = RBMap_empty();
+    let mut seed = 7;
+    for uint::range(0, 2000) |i| {
+      base = base.put(i as int, i as int);
+    }
+
+    let mut version = base;
+    for uint::range(0, 50) |round| {
+      for uint::range(0, round % 7 + 1) |_| {
+        seed = (seed * 1103515245 + 12345) % 2147483648;
+        let k = (seed % 2500) as int;
+        version = if seed % 3 == 0 { version.delete(k) } else { version.put(k, seed as int) };
+      }
+      assert(changes(base, version) == naive_changes(base, version));
+    }
+
+    // an unrelated tree with the same contents has no differences either.
+    let mut rebuilt = RBMap_empty();
+    for version.iter |k, v| { rebuilt = rebuilt.put(*k, *v); }
+    assert(changes(version, rebuilt).is_empty());
+  }
+
+  #[test]
+  fn test_undo_redo() {
+    let h = VersionedMap().put(1, 10).put(2, 20).put(1, 11);
+    assert(h.get(1) == Some(11));
+
+    let mut last = ~[];
+    for h.last_changes |c| { last.push(*c); }
+    assert(last == ~[Changed(1, 10, 11)]);
+
+    let back = h.undo().undo();
+    assert(back.get(1) == Some(10));
+    assert(back.get(2) == None);
+    assert(back.can_redo());
+
+    let forward = back.redo();
+    assert(forward.get(2) == Some(20));
+    assert(forward.redo().get(1) == Some(11));
+    assert(!forward.redo().can_redo());
+
+    // a new change after undoing drops the redo history.
+    let branched = back.delete(1);
+    assert(!branched.can_redo());
+    assert(branched.undo().get(1) == Some(10));
+
+    let start : VersionedMap<int, int> = VersionedMap();
+    assert(!start.can_undo());
+    assert(start.undo().len() == 0);
+    assert(box::ptr_eq(h.delete(99).current(), h.current()));
+    // deleting a missing key isn't a change, so there is nothing to undo.
+    assert(!start.delete(1).can_undo());
+  }
+}
@@ -0,0 +1,356 @@
+/**
+ * Debugging views of the trees and heaps: Graphviz DOT graphs, and an
+ * indented text rendering small enough to put in a test failure message.
+ *
+ * Several RBMap or Tree versions can be drawn into one graph. Each box is
+ * drawn once however many versions reach it, so the nodes the versions
+ * share are the ones with more than one arrow coming in.
+ */
+use core::cmp::{Eq, Ord};
+use tree;
+use tree::{Tree_, Empty_};
+use red_black_tree::{RBMap, Tree, Leaf, Red, Black, RBMap_empty};
+use pairing_heap::{PairingHeap, HeapNode, PairingHeapCell, Comparator};
+use persistent_map::*;
+
+pub trait Visualize {
+  // a Graphviz digraph of every node.
+  fn to_dot() -> ~str;
+
+  // the shape as indented text, one node per line.
+  fn to_ascii() -> ~str;
+}
+
+// quotes a label for use inside a DOT string.
+pure fn escape(label: &str) -> ~str {
+  str::replace(str::replace(label, "\\", "\\\\"), "\"", "\\\"")
+}
+
+pure fn indent(depth: uint) -> ~str {
+  let mut s = ~"";
+  for uint::range(0, depth) |_| {
+    s += "    ";
+  }
+  s
+}
+
+struct DotWriter {
+  // box address to the number of the node drawn for it.
+  mut ids: @RBMap<uint, uint>,
+  mut count: uint,
+  mut body: ~str
+}
+
+pure fn DotWriter() -> DotWriter {
+  DotWriter { ids: RBMap_empty(), count: 0, body: ~"" }
+}
+
+impl DotWriter {
+  fn line(s: &str) {
+    self.body += ~"  " + s + ~"\n";
+  }
+
+  // a node that stands for nothing else, such as an empty subtree.
+  fn fresh() -> ~str {
+    self.count += 1;
+    fmt!("n%u", self.count)
+  }
+
+  // the node for the box at `addr`, and whether it has been drawn already.
+  fn node_for(addr: uint) -> (~str, bool) {
+    match self.ids.get(addr) {
+      Some(id) => (fmt!("n%u", id), true),
+      None => {
+        self.count += 1;
+        self.ids = self.ids.put(addr, self.count);
+        (fmt!("n%u", self.count), false)
+      }
+    }
+  }
+
+  fn empty() -> ~str {
+    let name = self.fresh();
+    self.line(fmt!("%s [shape=point]", name));
+    name
+  }
+
+  fn edges(from: &str, left: &str, right: &str) {
+    self.line(fmt!("%s -> %s", from, left));
+    self.line(fmt!("%s -> %s", from, right));
+  }
+
+  fn rbmap<K: Copy Eq Ord, V: Copy>(t: @RBMap<K, V>) -> ~str {
+    match t {
+      @Leaf => self.empty(),
+      @Tree(color, left, key, maybe_value, right, _) => {
+        let (name, drawn) = self.node_for(ptr::to_uint(ptr::addr_of(&*t)));
+        if drawn {
+          return name;
+        }
+        let fill = match color { Red => "red", Black => "black" };
+        let (label, style) = match maybe_value {
+          Some(value) => (fmt!("%?: %?", key, value), "filled"),
+          // deleted keys stay behind with no value.
+          None => (fmt!("%? (deleted)", key), "filled,dashed")
+        };
+        self.line(fmt!("%s [label=\"%s\", style=\"%s\", fillcolor=%s, fontcolor=white]",
+                       name, escape(label), style, fill));
+        let l = self.rbmap(left);
+        let r = self.rbmap(right);
+        self.edges(name, l, r);
+        name
+      }
+    }
+  }
+
+  fn tree<K: Copy Eq Ord, V: Copy>(t: tree::Tree<K, V>) -> ~str {
+    match t {
+      Empty_ => self.empty(),
+      Tree_(node) => {
+        let (name, drawn) = self.node_for(ptr::to_uint(ptr::addr_of(&*node)));
+        if drawn {
+          return name;
+        }
+        self.line(fmt!("%s [label=\"%s\"]", name, escape(fmt!("%?: %?", node.key, node.value))));
+        let l = self.tree(node.left);
+        let r = self.tree(node.right);
+        self.edges(name, l, r);
+        name
+      }
+    }
+  }
+
+  // a heap cell and its children, left to right, with dashed arrows along
+  // the sibling list.
+  fn heap<E: Copy>(elem: E, first_child: @HeapNode<E>) -> ~str {
+    let name = self.fresh();
+    self.line(fmt!("%s [label=\"%s\"]", name, escape(fmt!("%?", elem))));
+
+    let mut previous = None;
+    let mut cur = first_child;
+    loop {
+      match *cur {
+        PairingHeapCell(child_elem, grandchild, sibling) => {
+          let child = self.heap(child_elem, grandchild);
+          self.line(fmt!("%s -> %s", name, child));
+          match previous {
+            Some(ref p) => self.line(fmt!("%s -> %s [style=dashed, constraint=false]", *p, child)),
+            None => ()
+          }
+          previous = Some(child);
+          cur = sibling;
+        }
+        _ => break
+      }
+    }
+    name
+  }
+
+  // labels version i and points it at its root.
+  fn version(i: uint, root: &str) {
+    self.line(fmt!("v%u [shape=plaintext, label=\"version %u\"]", i, i));
+    self.line(fmt!("v%u -> %s [style=dotted]", i, root));
+  }
+
+  fn finish(graph: &str) -> ~str {
+    fmt!("digraph %s {\n%s}\n", graph, self.body)
+  }
+}
+
+fn rbmap_versions_to_dot<K: Copy Eq Ord, V: Copy>(versions: &[@RBMap<K, V>]) -> ~str {
+  let w = DotWriter();
+  for uint::range(0, versions.len()) |i| {
+    let root = w.rbmap(versions[i]);
+    w.version(i, root);
+  }
+  w.finish("RBMapVersions")
+}
+
+fn tree_versions_to_dot<K: Copy Eq Ord, V: Copy>(versions: &[tree::Tree<K, V>]) -> ~str {
+  let w = DotWriter();
+  for uint::range(0, versions.len()) |i| {
+    let root = w.tree(versions[i]);
+    w.version(i, root);
+  }
+  w.finish("TreeVersions")
+}
+
+// right branches print above their parent and left ones below, so the
+// output is the tree turned a quarter turn anticlockwise.
+fn rbmap_ascii<K: Copy Eq Ord, V: Copy>(t: @RBMap<K, V>, depth: uint, out: &mut ~str) {
+  match t {
+    @Leaf => (),
+    @Tree(color, left, key, maybe_value, right, _) => {
+      rbmap_ascii(right, depth + 1, out);
+      let c = match color { Red => "R", Black => "B" };
+      *out += match maybe_value {
+        Some(value) => fmt!("%s%?: %? [%s]\n", indent(depth), key, value, c),
+        None => fmt!("%s%? (deleted) [%s]\n", indent(depth), key, c)
+      };
+      rbmap_ascii(left, depth + 1, out);
+    }
+  }
+}
+
+fn tree_ascii<K: Copy Eq Ord, V: Copy>(t: tree::Tree<K, V>, depth: uint, out: &mut ~str) {
+  match t {
+    Empty_ => (),
+    Tree_(node) => {
+      tree_ascii(node.right, depth + 1, out);
+      *out += fmt!("%s%?: %?\n", indent(depth), node.key, node.value);
+      tree_ascii(node.left, depth + 1, out);
+    }
+  }
+}
+
+// a cell, then each of its children one level further in.
+fn heap_ascii<E: Copy>(elem: E, first_child: @HeapNode<E>, depth: uint, out: &mut ~str) {
+  *out += fmt!("%s%?\n", indent(depth), elem);
+  let mut cur = first_child;
+  loop {
+    match *cur {
+      PairingHeapCell(child_elem, grandchild, sibling) => {
+        heap_ascii(child_elem, grandchild, depth + 1, out);
+        cur = sibling;
+      }
+      _ => break
+    }
+  }
+}
+
+impl<K: Copy Eq Ord, V: Copy> @RBMap<K, V> : Visualize {
+  fn to_dot() -> ~str {
+    let w = DotWriter();
+    w.rbmap(self);
+    w.finish("RBMap")
+  }
+
+  fn to_ascii() -> ~str {
+    let mut out = ~"";
+    rbmap_ascii(self, 0, &mut out);
+    if out.is_empty() { ~"(empty)\n" } else { out }
+  }
+}
+
+impl<K: Copy Eq Ord, V: Copy> tree::Tree<K, V> : Visualize {
+  fn to_dot() -> ~str {
+    let w = DotWriter();
+    w.tree(self);
+    w.finish("Tree")
+  }
+
+  fn to_ascii() -> ~str {
+    let mut out = ~"";
+    tree_ascii(self, 0, &mut out);
+    if out.is_empty() { ~"(empty)\n" } else { out }
+  }
+}
+
+impl<E: Copy, C: Copy Comparator<E>> PairingHeap<E, C> : Visualize {
+  fn to_dot() -> ~str {
+    let w = DotWriter();
+    match self.root {
+      PairingHeapCell(elem, child, _) => { w.heap(elem, child); }
+      _ => ()
+    }
+    w.finish("PairingHeap")
+  }
+
+  fn to_ascii() -> ~str {
+    let mut out = ~"";
+    match self.root {
+      PairingHeapCell(elem, child, _) => heap_ascii(elem, child, 0, &mut out),
+      _ => out = ~"(empty)\n"
+    }
+    out
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use tree;
+  use red_black_tree::{RBMap, RBMap_empty};
+  use pairing_heap::{PairingHeap, Heap};
+  use persistent_map::*;
+
+  fn occurrences(haystack: &str, needle: &str) -> uint {
+    let mut n = 0;
+    let mut start = 0;
+    loop {
+      match str::find_str_from(haystack, needle, start) {
+        Some(i) => {
+          n += 1;
+          start = i + needle.len();
+        }
+        None => return n
+      }
+    }
+  }
+
+  #[test]
+  fn test_rbmap_dot() {
+    let m = RBMap_empty().put(2, 20).put(1, 10).put(3, 30).delete(1);
+    let dot = m.to_dot();
+    assert(str::starts_with(dot, "digraph RBMap {"));
+    assert(occurrences(dot, "label=") == 3);
+    assert(str::contains(dot, "label=\"2: 20\""));
+    assert(str::contains(dot, "label=\"1 (deleted)\""));
+    // a black root over two red children; a chain of red nodes means the
+    // root wasn't blackened.
+    assert(occurrences(dot, "fillcolor=black") == 1);
+    assert(occurrences(dot, "fillcolor=red") == 2);
+  }
+
+  #[test]
+  fn test_versions_share_nodes() {
+    let mut base = RBMap_empty();
+    for uint::range(0, 64) |i| {
+      base = base.put(i, i);
+    }
+    let next = base.put(0, 100);
+
+    let both = rbmap_versions_to_dot(~[base, next]);
+    let labels = occurrences(both, "\", style=");
+    // next only adds its copy of the path down to key 0, which is short
+    // as long as the tree stays balanced.
+    assert(labels > 64 && labels < 64 + 16);
+    assert(str::contains(both, "v1 -> "));
+  }
+
+  #[test]
+  fn test_tree_ascii() {
+    let t = tree::Empty().insert(2, 20).insert(1, 10).insert(3, 30);
+    assert(t.to_ascii() == ~"    3: 30\n2: 20\n    1: 10\n");
+
+    let t2 = t.insert(4, 40);
+    let dot = tree_versions_to_dot(~[t, t2]);
+    // 2 is copied, 1 is shared, 3 is copied and 4 is new.
+    assert(occurrences(dot, "[label=\"") == 6);
+
+    let empty : tree::Tree<int, int> = tree::Empty();
+    assert(empty.to_ascii() == ~"(empty)\n");
+  }
+
+  #[test]
+  fn test_heap_views() {
+    let heap = PairingHeap(5).insert(3).insert(8).insert(9);
+    // 3 beat 5 and then took 8 and 9 as its newest children.
+    assert(heap.to_ascii() == ~"3\n    9\n    8\n    5\n");
+
+    let dot = heap.to_dot();
+    assert(occurrences(dot, "label=") == 4);
+    assert(occurrences(dot, "style=dashed") == 2);
+  }
+
+  #[test]
+  fn test_ascii_for_failure_messages() {
+    let m = RBMap_empty().put(1, 10).put(2, 20);
+    let shown = m.to_ascii();
+    assert(str::contains(shown, "1: 10 ["));
+    assert(str::contains(shown, "2: 20 ["));
+    if m.len() != 2 {
+      fail fmt!("wrong size for:\n%s", shown);
+    }
+  }
+}